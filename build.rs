@@ -1,8 +1,8 @@
 use std::env;
-use std::fs::create_dir;
+use std::fs::{create_dir, read_to_string, write};
 use std::io::ErrorKind;
-use std::path::PathBuf;
-use std::process::{exit, Command};
+use std::path::{Path, PathBuf};
+use std::process::{exit, Command, Stdio};
 
 fn main() {
     let host = env::var("HOST").unwrap();
@@ -16,16 +16,15 @@ fn main() {
             );
             exit(1);
         }
-        let cross = format!(
-            "--cross-prefix={}-",
-            cross_prefix(&target).unwrap_or_else(|| {
-                eprintln!(
-                    "Currently, cross compilation of libtcc doesn't support target:{}",
-                    target
-                );
-                exit(1);
-            })
-        );
+        let prefix = cross_prefix(&target).unwrap_or_else(|| {
+            eprintln!(
+                "Currently, cross compilation of libtcc doesn't support target:{}",
+                target
+            );
+            exit(1);
+        });
+        check_cross_compiler(prefix);
+        let cross = format!("--cross-prefix={}-", prefix);
 
         let cpu = format!("--cpu={}", resolve_cpu(&target));
         let config_args = [
@@ -45,15 +44,33 @@ fn main() {
                    \tbecause libtcc need some small but necessary runtime libaray such as libtcc1.a\n\
                    \tand some header files, which should be found in [prefix]/lib/tcc");
         exit(1);
-    } else {
-        if target.contains("linux") {
-            println!("cargo:rustc-link-search=native=/usr/local/lib");
-        }
+    } else if target.contains("windows") {
         if let Ok(path) = env::var("LIB_TCC") {
             println!("cargo:rustc-link-search=native={}", path);
         }
+    } else {
+        // src/libtcc_ext.c reads TCCState through the bundled tcc.h, so libtcc must be
+        // built from the same source and config.h, the installed one only provides the
+        // runtime, looked up in its tccdir.
+        let mut config_args = vec![String::from("--extra-cflags=-fPIC -O2")];
+        if let Some(dir) = installed_tccdir() {
+            config_args.push(format!("--tccdir={}", dir));
+        }
+        // configure detects the multiarch triplet with conftest.c, which is not bundled.
+        match cross_prefix(&target) {
+            Some(triplet) if Path::new("/usr/lib").join(triplet).join("crti.o").exists() => {
+                config_args.push(format!("--triplet={}", triplet))
+            }
+            _ => (),
+        }
+        let config_args: Vec<&str> = config_args.iter().map(String::as_str).collect();
+        build_tcc(Some(&config_args), Some(&["libtcc.a"]));
     }
 
+    write_targets(&target);
+    write_capabilities(&host, &target);
+    build_ext();
+
     if target.contains("msvc") {
         println!("cargo:rustc-link-lib=static=libtcc");
     } else {
@@ -79,7 +96,8 @@ fn build_tcc(config_arg: Option<&[&str]>, make_arg: Option<&[&str]>) {
     }
 
     let mut make = Command::new("make");
-    make.current_dir(&out_dir).arg(format!(
+    // objects of a previous run may be built with other config args.
+    make.current_dir(&out_dir).arg("-B").arg(format!(
         "-j{}",
         env::var("NUM_JOBS").unwrap_or_else(|_| String::from("1"))
     ));
@@ -97,6 +115,91 @@ fn build_tcc(config_arg: Option<&[&str]>, make_arg: Option<&[&str]>) {
     println!("cargo:rerun-if-changed={}", tcc_src.display());
 }
 
+/// Build extensions that need the internals of libtcc, see src/libtcc_ext.c.
+fn build_ext() {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    // tcc.h includes config.h, which only exists if tcc is configured by us, i.e. not
+    // on windows, where the installed libtcc must be 0.9.27 with default config.
+    let config = out_dir.join("config.h");
+    if !config.exists() {
+        write(&config, "#define TCC_VERSION \"0.9.27\"\n").unwrap();
+    }
+    let mut build = cc::Build::new();
+    // CONFIG_* paths read by the extensions must be those libtcc is built with.
+    for (name, val) in native_defines(&out_dir.join("config.mak")) {
+        build.define(&name, val.as_deref());
+    }
+    build
+        .file("src/libtcc_ext.c")
+        .include(&out_dir)
+        .include("src/tcc-0.9.27")
+        .warnings(false)
+        .compile("tcc_ext");
+    println!("cargo:rerun-if-changed=src/libtcc_ext.c");
+}
+
+/// Defines passed to libtcc by its Makefile, from `NATIVE_DEFINES` in `config_mak`.
+///
+/// Values are C string literals quoted for the shell, e.g. `"\"x86_64-linux-gnu\""`.
+fn native_defines(config_mak: &Path) -> Vec<(String, Option<String>)> {
+    let config = read_to_string(config_mak).unwrap_or_default();
+    config
+        .lines()
+        .filter_map(|l| l.strip_prefix("NATIVE_DEFINES+="))
+        .flat_map(str::split_whitespace)
+        .filter_map(|d| d.strip_prefix("-D"))
+        .map(|d| match d.split_once('=') {
+            Some((name, val)) => {
+                let val = val.strip_prefix('"').and_then(|v| v.strip_suffix('"'));
+                (name.to_string(), val.map(|v| v.replace("\\\"", "\"")))
+            }
+            None => (d.to_string(), None),
+        })
+        .collect()
+}
+
+/// Write architectures targeted by libtcc as a rust slice, see `supported_targets`.
+fn write_targets(target: &str) {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    write(
+        out_dir.join("targets.rs"),
+        format!("&[{:?}]\n", tcc_arch(target)),
+    )
+    .unwrap();
+}
+
+/// Write features of libtcc built for `target` as a rust expression, see `tcc_capabilities`.
+///
+/// Follows the conditions of CONFIG_TCC_BACKTRACE and CONFIG_TCC_BCHECK in tcc.h, libtcc
+/// always runs on the architecture it targets.
+fn write_capabilities(host: &str, target: &str) {
+    let arch = tcc_arch(target);
+    let backtrace = matches!(arch, "i386" | "x86_64" | "arm" | "arm64");
+    let bounds_check =
+        matches!(arch, "i386" | "x86_64") && !target.contains("musl") && !target.contains("uclibc");
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    write(
+        out_dir.join("capabilities.rs"),
+        format!(
+            "Capabilities {{ bounds_check: {}, backtrace: {}, cross: {} }}\n",
+            bounds_check,
+            backtrace,
+            host != target
+        ),
+    )
+    .unwrap();
+}
+
+/// Name of TCC_TARGET_* for `target`, e.g. `arm64` for aarch64.
+fn tcc_arch(target: &str) -> &str {
+    match resolve_cpu(target) {
+        "i386" | "i586" | "i686" => "i386",
+        "aarch64" => "arm64",
+        cpu if cpu.starts_with("arm") || cpu.starts_with("thumb") => "arm",
+        cpu => cpu,
+    }
+}
+
 fn tcc_installed() -> bool {
     if cfg!(target_os = "windows") {
         eprintln!(
@@ -135,6 +238,54 @@ fn tcc_installed() -> bool {
     false
 }
 
+/// Runtime dir of the installed tcc, None if it can not be asked.
+///
+/// Warn if the installed tcc is not 0.9.27, whose libtcc1.a and headers may not match.
+fn installed_tccdir() -> Option<String> {
+    let version = Command::new("tcc").arg("-v").output().ok()?;
+    let version = String::from_utf8_lossy(&version.stdout);
+    if !version.starts_with("tcc version 0.9.27 ") {
+        println!(
+            "cargo:warning=installed tcc is not 0.9.27, its runtime may not match libtcc: {}",
+            version.trim()
+        );
+    }
+    let dirs = Command::new("tcc")
+        .arg("-print-search-dirs")
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&dirs.stdout)
+        .lines()
+        .find_map(|l| l.strip_prefix("install: "))
+        .map(|dir| dir.trim().to_string())
+}
+
+/// Exit with a hint if `{prefix}-gcc`, which configure of tcc uses, can not be run.
+fn check_cross_compiler(prefix: &str) {
+    let gcc = format!("{}-gcc", prefix);
+    let found = Command::new(&gcc)
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false);
+    if !found {
+        let package = if prefix == "musl" {
+            String::from("musl-tools")
+        } else {
+            format!("gcc-{}", prefix)
+        };
+        eprintln!("ERROR: Can not find cross compiler {} in PATH:", gcc);
+        eprintln!(
+            "\tIt is needed to build libtcc for the target, install it and make sure it is in PATH,\n\
+             \te.g. `apt install {}` on Debian or Ubuntu.",
+            package
+        );
+        exit(1);
+    }
+}
+
 fn cross_prefix(target: &str) -> Option<&'static str> {
     match target {
         "aarch64-unknown-linux-gnu" => Some("aarch64-linux-gnu"),
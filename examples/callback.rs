@@ -0,0 +1,38 @@
+use libtcc::*;
+use std::ffi::CStr;
+use std::os::raw::c_int;
+
+static PLUGIN: &str = r#"
+typedef void (*log_t)(const char *msg, int level);
+static log_t logger;
+
+void register_logger(log_t f){
+    logger = f;
+}
+
+int work(int n){
+    int i, sum = 0;
+    for (i = 0; i < n; i++)
+        sum += i;
+    logger("work done", sum);
+    return sum;
+}
+"#;
+
+extern "C" fn log(msg: *const std::os::raw::c_char, level: c_int) {
+    let msg = unsafe { CStr::from_ptr(msg) };
+    println!("[{}] {}", level, msg.to_str().unwrap());
+}
+
+fn main() {
+    let mut jit = Jit::new().unwrap();
+    jit.compile(PLUGIN).unwrap();
+
+    // compiled code receives the Rust function as a plain function pointer.
+    let register_logger: extern "C" fn(extern "C" fn(*const std::os::raw::c_char, c_int)) =
+        unsafe { jit.get("register_logger").unwrap() };
+    let work: extern "C" fn(c_int) -> c_int = unsafe { jit.get("work").unwrap() };
+
+    register_logger(log);
+    assert_eq!(work(10), 45);
+}
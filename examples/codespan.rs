@@ -0,0 +1,31 @@
+//! Render a compile error with a source snippet, run with
+//! `cargo run --example codespan --features codespan`.
+use codespan_reporting::files::SimpleFiles;
+use codespan_reporting::term::{self, Config};
+use libtcc::*;
+use std::ffi::CString;
+
+static PROGRAM: &str = r#"int add(int a, int b) {
+    return a + c;
+}
+"#;
+
+fn main() {
+    let mut files = SimpleFiles::new();
+    let file_id = files.add("add.c", PROGRAM);
+
+    let mut g = Guard::new().unwrap();
+    let mut ctx = Context::new(&mut g).unwrap();
+    let diagnostics = ctx.diagnostics_channel();
+    ctx.set_input_name(&CString::new("add.c").unwrap());
+    if ctx.compile_string(&CString::new(PROGRAM).unwrap()).is_ok() {
+        return;
+    }
+    drop(ctx);
+
+    let stderr = &mut std::io::stderr();
+    for diag in diagnostics.try_iter() {
+        let diag = diag.to_codespan(file_id, PROGRAM);
+        term::emit_to_io_write(stderr, &Config::default(), &files, &diag).unwrap();
+    }
+}
@@ -19,6 +19,7 @@ fn main() {
 
     let compile_ret = ctx
         .set_output_type(OutputType::Memory)
+        .unwrap()
         .set_call_back(|msg| err_warn = Some(String::from(msg.to_str().unwrap())))
         .compile_string(&c_program);
     if compile_ret.is_err() {
@@ -0,0 +1,70 @@
+//! Calling compiled functions taking and returning structs by value.
+//!
+//! A struct crossing the boundary must be `#[repr(C)]`, with fields of the same
+//! C types in the same order, so that both sides agree on its size, alignment and
+//! how it is passed, in registers or in memory. Use `std::os::raw` types for C
+//! integers, never `bool` for `int`, and check the layout with `size_of` against
+//! `sizeof` of the C side when in doubt.
+//!
+//! On x86_64, tcc 0.9.27 passes a struct of at most 16 bytes mixing integer and
+//! floating point fields, e.g. `struct { int n; double w; }`, differently from the
+//! System V ABI, so the callee reads garbage. Returning such a struct works, but
+//! pass it by pointer instead.
+use libtcc::*;
+use std::os::raw::c_int;
+
+static SRC: &str = r#"
+struct point { int x, y; };
+struct rect { double x0, y0, x1, y1; };
+
+struct point add(struct point a, struct point b){
+    struct point r = { a.x + b.x, a.y + b.y };
+    return r;
+}
+
+double area(struct rect b){
+    return (b.x1 - b.x0) * (b.y1 - b.y0);
+}
+
+int point_size(void){ return sizeof(struct point); }
+"#;
+
+// same layout as `struct point`, passed in a register.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Point {
+    x: c_int,
+    y: c_int,
+}
+
+// same layout as `struct rect`, passed in memory as it is larger than 16 bytes.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Rect {
+    x0: f64,
+    y0: f64,
+    x1: f64,
+    y1: f64,
+}
+
+fn main() {
+    let mut jit = Jit::new().unwrap();
+    jit.compile(SRC).unwrap();
+
+    let point_size: extern "C" fn() -> c_int = unsafe { jit.get("point_size").unwrap() };
+    assert_eq!(point_size() as usize, std::mem::size_of::<Point>());
+
+    let add: extern "C" fn(Point, Point) -> Point = unsafe { jit.get("add").unwrap() };
+    let area: extern "C" fn(Rect) -> f64 = unsafe { jit.get("area").unwrap() };
+
+    let p = add(Point { x: 1, y: 2 }, Point { x: 3, y: 4 });
+    assert_eq!(p, Point { x: 4, y: 6 });
+    let b = Rect {
+        x0: 0.0,
+        y0: 0.0,
+        x1: 2.0,
+        y1: 3.0,
+    };
+    assert_eq!(area(b), 6.0);
+    println!("{:?} {}", p, area(b));
+}
@@ -0,0 +1,22 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use libtcc::{Context, Guard, OutputType};
+use std::ffi::CString;
+
+// Input is split by nul into options, macro name, macro value and source,
+// none of them should panic or abort the process.
+fuzz_target!(|data: &[u8]| {
+    let mut parts = data.splitn(4, |&c| c == 0).map(|p| CString::new(p).unwrap());
+    let mut next = || parts.next().unwrap_or_default();
+    let (opt, sym, val, src) = (next(), next(), next(), next());
+
+    let mut g = Guard::new().unwrap();
+    let mut ctx = Context::new(&mut g).unwrap();
+    ctx.set_call_back(|_| ());
+    let _ = ctx.set_options(&opt);
+    let _ = ctx.define_symbol(&sym, &val);
+    let _ = ctx.set_output_type(OutputType::Memory);
+    if ctx.compile_string(&src).is_ok() {
+        let _ = ctx.relocate();
+    }
+});
@@ -118,3 +118,233 @@ extern "C" {
         name: *const ::std::os::raw::c_char,
     ) -> *mut ::std::os::raw::c_void;
 }
+
+/* extensions implemented in libtcc_ext.c */
+
+extern "C" {
+    pub fn tcc_ext_preprocess(
+        s: *mut TCCState,
+        str: *const ::std::os::raw::c_char,
+        dflag: ::std::os::raw::c_int,
+        opaque: *mut ::std::os::raw::c_void,
+        write_func: ::std::option::Option<
+            unsafe extern "C" fn(
+                opaque: *mut ::std::os::raw::c_void,
+                buf: *const ::std::os::raw::c_char,
+                len: usize,
+            ),
+        >,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tcc_ext_compile_asm(
+        s: *mut TCCState,
+        str: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tcc_ext_add_file(
+        s: *mut TCCState,
+        filename: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tcc_ext_set_verbose(s: *mut TCCState, level: ::std::os::raw::c_int);
+}
+extern "C" {
+    pub fn tcc_ext_stdout_begin() -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tcc_ext_stdout_end(
+        opaque: *mut ::std::os::raw::c_void,
+        write_func: ::std::option::Option<
+            unsafe extern "C" fn(
+                opaque: *mut ::std::os::raw::c_void,
+                buf: *const ::std::os::raw::c_char,
+                len: usize,
+            ),
+        >,
+    );
+}
+extern "C" {
+    pub fn tcc_ext_execute(
+        prog_main: ::std::option::Option<
+            unsafe extern "C" fn(
+                argc: ::std::os::raw::c_int,
+                argv: *mut *mut ::std::os::raw::c_char,
+            ) -> ::std::os::raw::c_int,
+        >,
+        argc: ::std::os::raw::c_int,
+        argv: *mut *mut ::std::os::raw::c_char,
+        stack_size: usize,
+        timeout_ms: ::std::os::raw::c_int,
+        input: *const ::std::os::raw::c_char,
+        input_len: usize,
+        code: *mut ::std::os::raw::c_int,
+        sig: *mut ::std::os::raw::c_int,
+        out: *mut ::std::os::raw::c_void,
+        err: *mut ::std::os::raw::c_void,
+        write_func: ::std::option::Option<
+            unsafe extern "C" fn(
+                opaque: *mut ::std::os::raw::c_void,
+                buf: *const ::std::os::raw::c_char,
+                len: usize,
+            ),
+        >,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tcc_ext_define_symbol(
+        s: *mut TCCState,
+        sym: *const ::std::os::raw::c_char,
+        value: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tcc_ext_set_options(
+        s: *mut TCCState,
+        str: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tcc_ext_relocate(
+        s1: *mut TCCState,
+        ptr: *mut ::std::os::raw::c_void,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tcc_ext_relocate_checked(
+        s1: *mut TCCState,
+        ptr: *mut ::std::os::raw::c_void,
+        size: usize,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tcc_ext_load_dll(
+        s: *mut TCCState,
+        filename: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tcc_ext_get_dll_symbol(
+        s: *mut TCCState,
+        name: *const ::std::os::raw::c_char,
+    ) -> *mut ::std::os::raw::c_void;
+}
+extern "C" {
+    pub fn tcc_ext_list_deps(
+        s: *mut TCCState,
+        ctx: *mut ::std::os::raw::c_void,
+        dep_cb: ::std::option::Option<
+            unsafe extern "C" fn(
+                ctx: *mut ::std::os::raw::c_void,
+                name: *const ::std::os::raw::c_char,
+            ),
+        >,
+    );
+}
+extern "C" {
+    pub fn tcc_ext_list_symbols(
+        s: *mut TCCState,
+        ctx: *mut ::std::os::raw::c_void,
+        symbol_cb: ::std::option::Option<
+            unsafe extern "C" fn(
+                ctx: *mut ::std::os::raw::c_void,
+                name: *const ::std::os::raw::c_char,
+                val: *const ::std::os::raw::c_void,
+            ),
+        >,
+    );
+}
+extern "C" {
+    pub fn tcc_ext_list_undefined(
+        s: *mut TCCState,
+        ctx: *mut ::std::os::raw::c_void,
+        symbol_cb: ::std::option::Option<
+            unsafe extern "C" fn(
+                ctx: *mut ::std::os::raw::c_void,
+                name: *const ::std::os::raw::c_char,
+                val: *const ::std::os::raw::c_void,
+            ),
+        >,
+    );
+}
+extern "C" {
+    pub fn tcc_ext_memory_used(s: *mut TCCState) -> usize;
+}
+extern "C" {
+    pub fn tcc_ext_reloc_stats(
+        s: *mut TCCState,
+        code: *mut usize,
+        data: *mut usize,
+        relocs: *mut usize,
+    );
+}
+extern "C" {
+    pub fn tcc_ext_hide_symbols(s: *mut TCCState, local: ::std::os::raw::c_int);
+}
+extern "C" {
+    pub fn tcc_ext_symbol_section_flags(
+        s: *mut TCCState,
+        name: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_long;
+}
+extern "C" {
+    pub fn tcc_ext_set_sysroot(s: *mut TCCState, sysroot: *const ::std::os::raw::c_char);
+}
+extern "C" {
+    pub fn tcc_ext_set_libc(
+        s: *mut TCCState,
+        includes: *const ::std::os::raw::c_char,
+        lib: *const ::std::os::raw::c_char,
+    );
+}
+extern "C" {
+    pub fn tcc_ext_insert_include_path(
+        s: *mut TCCState,
+        pathname: *const ::std::os::raw::c_char,
+        index: ::std::os::raw::c_int,
+    );
+}
+extern "C" {
+    pub fn tcc_ext_resolve_include(
+        s: *mut TCCState,
+        name: *const ::std::os::raw::c_char,
+        is_bracket: ::std::os::raw::c_int,
+        buf: *mut ::std::os::raw::c_char,
+        size: ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tcc_ext_set_file_name(
+        s: *mut TCCState,
+        dir: *const ::std::os::raw::c_char,
+        name: *const ::std::os::raw::c_char,
+    );
+}
+extern "C" {
+    pub fn tcc_ext_get_lib_path(s: *mut TCCState) -> *const ::std::os::raw::c_char;
+}
+extern "C" {
+    pub fn tcc_ext_shim_set_output(
+        write_func: ::std::option::Option<
+            unsafe extern "C" fn(
+                opaque: *mut ::std::os::raw::c_void,
+                buf: *const ::std::os::raw::c_char,
+                len: usize,
+            ),
+        >,
+    );
+}
+extern "C" {
+    pub fn tcc_ext_shim_printf(fmt: *const ::std::os::raw::c_char, ...) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tcc_ext_shim_puts(str: *const ::std::os::raw::c_char) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tcc_ext_shim_putchar(c: ::std::os::raw::c_int) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tcc_ext_shim_errno_location() -> *mut ::std::os::raw::c_int;
+}
@@ -0,0 +1,357 @@
+use crate::options::Setting;
+use crate::{Context, ContextConfig, ErrorKind, Guard, OutputType, RelocatedCtx, TccError};
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+
+/// Settings of a [`Context`] applied before compilation, e.g. loaded from a config file.
+///
+/// With feature `serde`, the config can be serialized and deserialized, all fields
+/// are optional then. Libraries are not part of it, since they are added after sources.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct CompileConfig {
+    /// see [`Context::set_lib_path`].
+    pub lib_path: Option<PathBuf>,
+    /// command line options, see [`Context::set_options`].
+    pub options: Vec<String>,
+    /// see [`Context::add_include_path`].
+    pub include_paths: Vec<PathBuf>,
+    /// see [`Context::add_sys_include_path`].
+    pub sys_include_paths: Vec<PathBuf>,
+    /// see [`Context::add_library_path`].
+    pub library_paths: Vec<PathBuf>,
+    /// preprocessor symbols and their values, see [`Context::define_symbol`].
+    pub defines: BTreeMap<String, String>,
+    /// see [`Context::set_output_type`].
+    pub output_type: Option<OutputType>,
+}
+
+/// Builder of [`Context`] from a [`CompileConfig`].
+/// ```
+/// use libtcc::{ContextBuilder, Guard, OutputType};
+/// use std::ffi::CString;
+/// let mut g = Guard::new().unwrap();
+/// let mut ctx = ContextBuilder::new()
+///     .define("ANSWER", "42")
+///     .output_type(OutputType::Memory)
+///     .build(&mut g)
+///     .unwrap();
+/// let p = CString::new("int f(void){ return ANSWER; }").unwrap();
+/// assert!(ctx.compile_string(&p).is_ok());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ContextBuilder {
+    base: ContextConfig,
+    config: CompileConfig,
+}
+
+impl ContextBuilder {
+    /// Create a builder with empty config.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a builder from `config`.
+    pub fn from_config(config: CompileConfig) -> Self {
+        Self {
+            base: ContextConfig::default(),
+            config,
+        }
+    }
+
+    /// Create a builder applying `base` first, e.g. the [config](Context::config) of a
+    /// context with common paths and defines, to derive variants of it.
+    ///
+    /// Output type of `base` is still set last, unless the builder sets another one.
+    pub fn from_context_config(base: &ContextConfig) -> Self {
+        Self {
+            base: base.clone(),
+            config: CompileConfig::default(),
+        }
+    }
+
+    /// config to be applied.
+    pub fn config(&self) -> &CompileConfig {
+        &self.config
+    }
+
+    /// see [`Context::set_lib_path`].
+    pub fn lib_path<T: Into<PathBuf>>(&mut self, path: T) -> &mut Self {
+        self.config.lib_path = Some(path.into());
+        self
+    }
+
+    /// see [`Context::set_options`].
+    pub fn option<T: Into<String>>(&mut self, option: T) -> &mut Self {
+        self.config.options.push(option.into());
+        self
+    }
+
+    /// see [`Context::add_include_path`].
+    pub fn include_path<T: Into<PathBuf>>(&mut self, path: T) -> &mut Self {
+        self.config.include_paths.push(path.into());
+        self
+    }
+
+    /// see [`Context::add_sys_include_path`].
+    pub fn sys_include_path<T: Into<PathBuf>>(&mut self, path: T) -> &mut Self {
+        self.config.sys_include_paths.push(path.into());
+        self
+    }
+
+    /// see [`Context::add_library_path`].
+    pub fn library_path<T: Into<PathBuf>>(&mut self, path: T) -> &mut Self {
+        self.config.library_paths.push(path.into());
+        self
+    }
+
+    /// see [`Context::define_symbol`].
+    pub fn define<K: Into<String>, V: Into<String>>(&mut self, sym: K, val: V) -> &mut Self {
+        self.config.defines.insert(sym.into(), val.into());
+        self
+    }
+
+    /// see [`Context::set_output_type`].
+    pub fn output_type(&mut self, output: OutputType) -> &mut Self {
+        self.config.output_type = Some(output);
+        self
+    }
+
+    /// Create a context with the config applied.
+    ///
+    /// Output type is set last, so that include paths of the config are searched
+    /// before the default ones.
+    pub fn build<'a>(&self, g: &'a mut Guard) -> Result<Context<'a, 'static>, TccError> {
+        let ctx = Context::new(g).map_err(|_| TccError::new(ErrorKind::Io, "out of memory"))?;
+        self.apply(ctx)
+    }
+
+    /// same as [`build`](ContextBuilder::build), but without borrowing a guard.
+    ///
+    /// # Safety
+    /// Caller must hold the guard for the whole lifetime of the context, and of the
+    /// module relocated from it.
+    pub(crate) unsafe fn build_unguarded(&self) -> Result<Context<'static, 'static>, TccError> {
+        let ctx =
+            Context::new_unguarded().map_err(|_| TccError::new(ErrorKind::Io, "out of memory"))?;
+        self.apply(ctx)
+    }
+
+    fn apply<'a>(&self, mut ctx: Context<'a, 'static>) -> Result<Context<'a, 'static>, TccError> {
+        let config = &self.config;
+        let mut output = config.output_type;
+        for setting in &self.base.settings {
+            match setting {
+                Setting::OutputType(base) => output = output.or(Some(*base)),
+                setting => setting.apply(&mut ctx)?,
+            }
+        }
+        if let Some(ref path) = config.lib_path {
+            ctx.set_lib_path(path)?;
+        }
+        for opt in &config.options {
+            ctx.set_options(&to_cstring(opt)?)?;
+        }
+        for path in &config.include_paths {
+            ctx.add_include_path(path)?;
+        }
+        for path in &config.sys_include_paths {
+            ctx.add_sys_include_path(path)?;
+        }
+        for path in &config.library_paths {
+            ctx.add_library_path(path)?;
+        }
+        for (sym, val) in &config.defines {
+            ctx.define_symbol(&to_cstring(sym)?, &to_cstring(val)?)?;
+        }
+        if let Some(output) = output {
+            ctx.set_output_type(output)?;
+        }
+        Ok(ctx)
+    }
+}
+
+fn to_cstring(s: &str) -> Result<CString, TccError> {
+    CString::new(s).map_err(|_| {
+        TccError::new(
+            ErrorKind::InvalidInput,
+            format!("{:?} contains nul byte", s),
+        )
+    })
+}
+
+/// Builder of several outputs of one source, see [`Context::multi_output`].
+///
+/// The source is compiled once: an object file is not linked, so the same state is
+/// relocated in memory after writing it, like [`Context::compile_dual`]. Requesting
+/// both outputs costs one extra write of the object file, not a second compilation.
+pub struct MultiOutput<'a, 'b> {
+    ctx: Context<'a, 'b>,
+    src: Vec<u8>,
+    object: Option<PathBuf>,
+    memory: bool,
+}
+
+impl<'a, 'b> MultiOutput<'a, 'b> {
+    pub(crate) fn new(ctx: Context<'a, 'b>, src: &[u8]) -> Self {
+        Self {
+            ctx,
+            src: src.to_vec(),
+            object: None,
+            memory: false,
+        }
+    }
+
+    /// write an object file to `path`, see [`Context::output_file`].
+    pub fn object<T: AsRef<Path>>(mut self, path: T) -> Self {
+        self.object = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// relocate in memory, see [`Context::relocate`].
+    pub fn memory(mut self) -> Self {
+        self.memory = true;
+        self
+    }
+
+    /// compile the source and produce the requested outputs, `None` for those not requested.
+    ///
+    /// Output type is set to [`OutputType::Obj`] if an object file is requested,
+    /// [`OutputType::Memory`] otherwise, so it must not be changed by an earlier
+    /// compilation. Return Err of [`ErrorKind::InvalidInput`] if the source contains nul byte.
+    pub fn finish(self) -> Result<(Option<()>, Option<RelocatedCtx<'a>>), TccError> {
+        let src = CString::new(self.src)
+            .map_err(|_| TccError::new(ErrorKind::InvalidInput, "source contains nul byte"))?;
+        let mut ctx = self.ctx;
+        let output = match self.object {
+            Some(_) => OutputType::Obj,
+            None => OutputType::Memory,
+        };
+        ctx.set_output_type(output)?;
+        ctx.compile_string(&src)?;
+        match (self.object, self.memory) {
+            (Some(obj), true) => Ok((Some(()), Some(ctx.compile_dual(obj)?))),
+            (Some(obj), false) => ctx.output_file(obj).map(|_| (Some(()), None)),
+            (None, true) => Ok((None, Some(ctx.relocate()?))),
+            (None, false) => Ok((None, None)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, remove_dir_all, remove_file, write};
+
+    #[test]
+    fn build() {
+        let dir = temp_dir().join("libtcc_builder");
+        create_dir_all(&dir).unwrap();
+        write(dir.join("builder_test.h"), "#define FROM_HEADER 1\n").unwrap();
+        let p = CString::new(
+            "#include \"builder_test.h\"\nint f(void){ return FROM_HEADER + ANSWER; }",
+        )
+        .unwrap();
+
+        let mut builder = ContextBuilder::new();
+        builder
+            .include_path(&dir)
+            .define("ANSWER", "41")
+            .output_type(OutputType::Memory);
+        let mut g = Guard::wait();
+        let mut ctx = builder.build(&mut g).unwrap();
+        assert_eq!(ctx.output_type(), Some(OutputType::Memory));
+        assert!(ctx.compile_string(&p).is_ok());
+        drop(ctx);
+
+        let err = ContextBuilder::new()
+            .define("BAD\0", "")
+            .build(&mut g)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn multi_output() {
+        let src = b"int counter = 40; int next(void){ return ++counter; }";
+        let next = CString::new("next").unwrap();
+        let obj = temp_dir().join("libtcc_multi_output.o");
+        let mut g = Guard::wait();
+
+        let ctx = Context::new(&mut g).unwrap();
+        let (written, relocated) = ctx
+            .multi_output(src)
+            .object(&obj)
+            .memory()
+            .finish()
+            .unwrap();
+        assert_eq!(written, Some(()));
+        let mut relocated = relocated.unwrap();
+        let f: extern "C" fn() -> i32 = unsafe { relocated.get_function(&next).unwrap() };
+        assert_eq!(f(), 41);
+        drop(relocated);
+
+        let ctx = Context::link_objects(&mut g, &[&obj], OutputType::Memory).unwrap();
+        let mut relocated = ctx.relocate().unwrap();
+        let f: extern "C" fn() -> i32 = unsafe { relocated.get_function(&next).unwrap() };
+        assert_eq!(f(), 41);
+        drop(relocated);
+        remove_file(&obj).unwrap();
+
+        let ctx = Context::new(&mut g).unwrap();
+        let (written, relocated) = ctx.multi_output(src).memory().finish().unwrap();
+        assert!(written.is_none() && relocated.is_some());
+        drop(relocated);
+
+        let ctx = Context::new(&mut g).unwrap();
+        let err = ctx.multi_output(b"int\0").memory().finish().err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn from_context_config() {
+        let p = CString::new("int f(void){ return BASE + EXTRA; }").unwrap();
+        let mut g = Guard::wait();
+        let mut base = ContextBuilder::new()
+            .define("BASE", "40")
+            .include_path("/opt/include")
+            .build(&mut g)
+            .unwrap();
+        base.undefine_symbol(&CString::new("UNUSED").unwrap());
+        let config = base.config();
+        drop(base);
+
+        for extra in ["1", "2"] {
+            let mut ctx = ContextBuilder::from_context_config(&config)
+                .define("EXTRA", extra)
+                .build(&mut g)
+                .unwrap();
+            assert!(ctx
+                .effective_options()
+                .starts_with(&config.effective_options()));
+            assert!(ctx.compile_string(&p).is_ok());
+            assert_eq!(ctx.output_type(), Some(OutputType::Memory));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde() {
+        let mut builder = ContextBuilder::new();
+        builder
+            .include_path("/opt/include")
+            .define("ANSWER", "42")
+            .output_type(OutputType::Obj);
+        let json = serde_json::to_string(builder.config()).unwrap();
+        let config: CompileConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(&config, builder.config());
+        let config: CompileConfig = serde_json::from_str(r#"{"output_type":"Exe"}"#).unwrap();
+        assert_eq!(config.output_type, Some(OutputType::Exe));
+        assert!(config.defines.is_empty());
+    }
+}
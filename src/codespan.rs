@@ -0,0 +1,64 @@
+use crate::{Diagnostic, Severity};
+use codespan_reporting::diagnostic::{self, Label};
+
+impl Diagnostic {
+    /// convert to a diagnostic of [codespan-reporting](https://docs.rs/codespan-reporting),
+    /// labeling its line of `source`, the text of the file `file_id` refers to.
+    ///
+    /// tcc reports no column, so the label spans the whole line but leading and
+    /// trailing whitespace. It is left out if there is no line, or `source` is shorter.
+    /// Only with the `codespan` feature.
+    pub fn to_codespan<FileId>(
+        &self,
+        file_id: FileId,
+        source: &str,
+    ) -> diagnostic::Diagnostic<FileId> {
+        let severity = match self.severity {
+            Severity::Error => diagnostic::Severity::Error,
+            Severity::Warning => diagnostic::Severity::Warning,
+            Severity::Note => diagnostic::Severity::Note,
+        };
+        let mut diag = diagnostic::Diagnostic::new(severity).with_message(&self.message);
+        let line = self.line.and_then(|line| line_span(source, line as usize));
+        if let Some(span) = line {
+            diag = diag.with_labels(vec![Label::primary(file_id, span)]);
+        }
+        diag
+    }
+}
+
+/// Byte range of line `line`, starting from 1, of `source` without surrounding
+/// whitespace.
+fn line_span(source: &str, line: usize) -> Option<std::ops::Range<usize>> {
+    let mut start = 0;
+    let text = source
+        .split_inclusive('\n')
+        .inspect(|l| start += l.len())
+        .nth(line.checked_sub(1)?)?;
+    let start = start - text.len();
+    let text = text.trim_end_matches(['\n', '\r']);
+    let lead = text.len() - text.trim_start().len();
+    let end = text.trim_end().len().max(lead);
+    Some(start + lead..start + end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_codespan() {
+        let src = "int main(void) {\n    return x;\n}\n";
+        let diag = Diagnostic::parse("<string>:2: error: 'x' undeclared");
+        let out = diag.to_codespan(0, src);
+        assert_eq!(out.severity, diagnostic::Severity::Error);
+        assert_eq!(out.message, "'x' undeclared");
+        assert_eq!(out.labels.len(), 1);
+        assert_eq!(&src[out.labels[0].range.clone()], "return x;");
+
+        let diag = Diagnostic::parse("<string>:9: warning: beyond the end");
+        assert!(diag.to_codespan(0, src).labels.is_empty());
+        assert_eq!(line_span("a\n\nb", 2), Some(2..2));
+        assert_eq!(line_span("a", 0), None);
+    }
+}
@@ -0,0 +1,231 @@
+use std::error::Error;
+use std::fmt;
+
+/// Kind of [`TccError`], telling which stage of the compilation fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// source can not be preprocessed or compiled.
+    Compile,
+    /// symbols can not be resolved, or an object/library can not be linked.
+    Link,
+    /// compiled code can not be relocated into memory.
+    Relocate,
+    /// file can not be found, read or written, or memory runs out.
+    Io,
+    /// argument rejected before reaching tcc, or misuse of the API.
+    InvalidInput,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ErrorKind::Compile => "compile error",
+            ErrorKind::Link => "link error",
+            ErrorKind::Relocate => "relocation error",
+            ErrorKind::Io => "io error",
+            ErrorKind::InvalidInput => "invalid input",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Error of the compilation, carrying the diagnostics reported by tcc.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TccError {
+    kind: ErrorKind,
+    msg: String,
+}
+
+impl TccError {
+    pub(crate) fn new<T: Into<String>>(kind: ErrorKind, msg: T) -> Self {
+        Self {
+            kind,
+            msg: msg.into(),
+        }
+    }
+
+    /// Build error of `kind` from diagnostics of tcc, refining the kind by them.
+    pub(crate) fn from_diagnostics(kind: ErrorKind, msgs: &[String]) -> Self {
+        if msgs.is_empty() {
+            return Self::new(kind, kind.to_string());
+        }
+        let msg = msgs.join("\n");
+        Self::new(classify(kind, &msg), msg)
+    }
+
+    /// kind of the error.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// message of the error, usually the diagnostics emitted by tcc.
+    pub fn message(&self) -> &str {
+        &self.msg
+    }
+}
+
+/// Refine `kind` by message of tcc, this is a heuristic.
+///
+/// - unresolved symbols are link errors, wherever they are reported.
+/// - errors with source location are compile errors, e.g. `add_file` on a C file,
+///   so are warnings, which only fail under -Werror.
+/// - missing or unreadable files are io errors.
+fn classify(kind: ErrorKind, msg: &str) -> ErrorKind {
+    if msg.contains("undefined symbol") {
+        ErrorKind::Link
+    } else if kind == ErrorKind::Compile {
+        kind
+    } else if msg
+        .lines()
+        .any(|l| !l.starts_with("tcc:") && (l.contains(": error: ") || l.contains(": warning: ")))
+    {
+        ErrorKind::Compile
+    } else if msg.contains("not found") || msg.contains("could not") {
+        ErrorKind::Io
+    } else {
+        kind
+    }
+}
+
+impl fmt::Display for TccError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl Error for TccError {}
+
+/// Severity of a [`Diagnostic`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Severity {
+    /// fails the compilation.
+    Error,
+    /// only fails under -Werror.
+    Warning,
+    /// anything else, e.g. verbose output.
+    Note,
+}
+
+/// Message reported by tcc, parsed from its text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// severity of the message.
+    pub severity: Severity,
+    /// file the message is about, None if it is about tcc itself.
+    pub file: Option<String>,
+    /// line in `file`, None if tcc reports no line.
+    pub line: Option<u32>,
+    /// text of the message, without location and severity.
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// parse `msg` in the format of tcc, e.g. `a.c:1: error: ...`.
+    ///
+    /// Leading `In file included from` lines are skipped. Messages not in the format
+    /// are [`Severity::Note`] without location.
+    pub fn parse(msg: &str) -> Diagnostic {
+        let last = msg
+            .lines()
+            .find(|l| !l.starts_with("In file included from "))
+            .unwrap_or("");
+        for (tag, severity) in [
+            (": error: ", Severity::Error),
+            (": warning: ", Severity::Warning),
+        ] {
+            if let Some(i) = last.find(tag) {
+                let loc = &last[..i];
+                let (file, line) = match loc.rsplit_once(':') {
+                    Some((file, line)) if line.parse::<u32>().is_ok() => {
+                        (Some(file), line.parse().ok())
+                    }
+                    _ if loc == "tcc" => (None, None),
+                    _ => (Some(loc), None),
+                };
+                return Diagnostic {
+                    severity,
+                    file: file.map(String::from),
+                    line,
+                    message: last[i + tag.len()..].to_string(),
+                };
+            }
+        }
+        Diagnostic {
+            severity: Severity::Note,
+            file: None,
+            line: None,
+            message: msg.to_string(),
+        }
+    }
+}
+
+/// Error of [`Guard::new`](crate::Guard::new).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum GuardError {
+    /// another guard exists.
+    AlreadyHeld,
+}
+
+impl fmt::Display for GuardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GuardError::AlreadyHeld => f.write_str("Try to create TCC instance multiple time"),
+        }
+    }
+}
+
+impl Error for GuardError {}
+
+impl From<GuardError> for TccError {
+    fn from(e: GuardError) -> Self {
+        TccError::new(ErrorKind::InvalidInput, e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_diagnostics() {
+        let msgs = |m: &str| vec![String::from(m)];
+        let err = TccError::from_diagnostics(
+            ErrorKind::Relocate,
+            &msgs("tcc: error: undefined symbol 'add'"),
+        );
+        assert_eq!(err.kind(), ErrorKind::Link);
+        let err =
+            TccError::from_diagnostics(ErrorKind::Link, &msgs("tcc: error: file 'a.o' not found"));
+        assert_eq!(err.kind(), ErrorKind::Io);
+        let err = TccError::from_diagnostics(
+            ErrorKind::Link,
+            &msgs("a.c:1: error: include file 'b.h' not found"),
+        );
+        assert_eq!(err.kind(), ErrorKind::Compile);
+        let err = TccError::from_diagnostics(
+            ErrorKind::Link,
+            &msgs("a.c:1: warning: assignment makes integer from pointer without a cast"),
+        );
+        assert_eq!(err.kind(), ErrorKind::Compile);
+        let err = TccError::from_diagnostics(ErrorKind::Relocate, &[]);
+        assert_eq!(err.kind(), ErrorKind::Relocate);
+        assert_eq!(err.message(), "relocation error");
+    }
+
+    #[test]
+    fn parse_diagnostic() {
+        let d = Diagnostic::parse("In file included from a.c:1:\nb.h:2: warning: x redefined");
+        assert_eq!(d.severity, Severity::Warning);
+        assert_eq!(d.file.as_deref(), Some("b.h"));
+        assert_eq!(d.line, Some(2));
+        assert_eq!(d.message, "x redefined");
+        let d = Diagnostic::parse("tcc: error: undefined symbol 'f'");
+        assert_eq!((d.severity, d.file, d.line), (Severity::Error, None, None));
+        assert_eq!(d.message, "undefined symbol 'f'");
+        let d = Diagnostic::parse("a.o: error: invalid object file");
+        assert_eq!((d.file.as_deref(), d.line), (Some("a.o"), None));
+        assert_eq!(Diagnostic::parse("-> a.c").severity, Severity::Note);
+    }
+}
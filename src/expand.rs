@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+
+/// Macros expanded on one source line, see [`preprocess_verbose`](crate::Context::preprocess_verbose).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expansion {
+    /// line number in the source, starting from 1.
+    pub line: usize,
+    /// macros expanded on the line, each once in order of first use, including
+    /// those reached through bodies of other macros.
+    pub macros: Vec<String>,
+    /// the line before expansion.
+    pub source: String,
+    /// the line after expansion. An invocation spanning lines is expanded on its
+    /// first line, leaving the others empty.
+    pub expanded: String,
+}
+
+struct Macro {
+    function_like: bool,
+    body: String,
+}
+
+/// Find lines of `src` expanding macros of `defines` (output of -dM), and pair them
+/// with lines of `output` (output of -E).
+pub(crate) fn trace(src: &str, defines: &str, output: &str) -> Vec<Expansion> {
+    let macros = parse_macros(defines);
+    let expanded = expanded_lines(output, "<string>");
+    let mut in_comment = false;
+    let mut directive = false;
+    let mut traces = Vec::new();
+    for (i, line) in src.lines().enumerate() {
+        let ids = identifiers(line, &mut in_comment);
+        // directives, and their continuation lines, are not traced.
+        directive = directive || line.trim_start().starts_with('#');
+        if directive {
+            directive = line.ends_with('\\');
+            continue;
+        }
+        let mut used = Vec::new();
+        for (id, rest) in ids {
+            if invoked(&macros, id, rest) {
+                collect(&macros, id, &mut used);
+            }
+        }
+        if !used.is_empty() {
+            traces.push(Expansion {
+                line: i + 1,
+                macros: used,
+                source: line.to_string(),
+                expanded: expanded.get(&(i + 1)).cloned().unwrap_or_default(),
+            });
+        }
+    }
+    traces
+}
+
+fn parse_macros(defines: &str) -> HashMap<&str, Macro> {
+    let mut macros = HashMap::new();
+    for def in defines.lines().filter_map(|l| l.strip_prefix("#define ")) {
+        let (name, body) = def.split_once(' ').unwrap_or((def, ""));
+        let (name, function_like) = match name.find('(') {
+            Some(i) => (&name[..i], true),
+            None => (name, false),
+        };
+        let body = body.to_string();
+        macros.insert(
+            name,
+            Macro {
+                function_like,
+                body,
+            },
+        );
+    }
+    macros
+}
+
+/// Map line numbers of file `file` to lines of preprocessor output, following line markers.
+fn expanded_lines(output: &str, file: &str) -> HashMap<usize, String> {
+    let mut lines = HashMap::new();
+    let mut line = 1;
+    let mut in_file = true;
+    for l in output.lines() {
+        if let Some((n, f)) = parse_marker(l) {
+            line = n;
+            in_file = f == file;
+            continue;
+        }
+        if in_file {
+            lines.insert(line, l.to_string());
+        }
+        line += 1;
+    }
+    lines
+}
+
+/// Parse line marker `# 12 "file"`.
+fn parse_marker(l: &str) -> Option<(usize, &str)> {
+    let (n, rest) = l.strip_prefix("# ")?.split_once(' ')?;
+    let file = rest.strip_prefix('"')?.split('"').next()?;
+    Some((n.parse().ok()?, file))
+}
+
+/// Identifiers of C code `line` outside of comments and literals, each with the rest
+/// of the line after it. `in_comment` carries a block comment across lines.
+fn identifiers<'s>(line: &'s str, in_comment: &mut bool) -> Vec<(&'s str, &'s str)> {
+    let b = line.as_bytes();
+    let ident = |c: u8| c == b'_' || c.is_ascii_alphanumeric();
+    let mut ids = Vec::new();
+    let mut i = 0;
+    while i < b.len() {
+        if *in_comment {
+            if b[i..].starts_with(b"*/") {
+                *in_comment = false;
+                i += 1;
+            }
+            i += 1;
+            continue;
+        }
+        match b[i] {
+            b'/' if b.get(i + 1) == Some(&b'/') => break,
+            b'/' if b.get(i + 1) == Some(&b'*') => {
+                *in_comment = true;
+                i += 2;
+            }
+            q @ b'"' | q @ b'\'' => {
+                i += 1;
+                while i < b.len() && b[i] != q {
+                    i += if b[i] == b'\\' { 2 } else { 1 };
+                }
+                i += 1;
+            }
+            c if c.is_ascii_digit() => {
+                // pp-number, e.g. 1UL or 0x1f.
+                while i < b.len() && (ident(b[i]) || b[i] == b'.') {
+                    i += 1;
+                }
+            }
+            c if ident(c) => {
+                let start = i;
+                while i < b.len() && ident(b[i]) {
+                    i += 1;
+                }
+                ids.push((&line[start..i], &line[i..]));
+            }
+            _ => i += 1,
+        }
+    }
+    ids
+}
+
+/// Whether `id` followed by `rest` invokes a macro. Arguments of a function-like
+/// macro may start on the next line.
+fn invoked(macros: &HashMap<&str, Macro>, id: &str, rest: &str) -> bool {
+    match macros.get(id) {
+        Some(m) if m.function_like => {
+            let rest = rest.trim_start();
+            rest.is_empty() || rest.starts_with('(')
+        }
+        Some(_) => true,
+        None => false,
+    }
+}
+
+/// Add `name` and the macros in its body to `used`.
+fn collect(macros: &HashMap<&str, Macro>, name: &str, used: &mut Vec<String>) {
+    if used.iter().any(|u| u == name) {
+        return;
+    }
+    used.push(name.to_string());
+    let mut in_comment = false;
+    for (id, rest) in identifiers(&macros[name].body, &mut in_comment) {
+        if invoked(macros, id, rest) {
+            collect(macros, id, used);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Context, ErrorKind, Guard};
+    use std::ffi::CString;
+
+    #[test]
+    fn preprocess_verbose() {
+        let p = CString::new(
+            r#"#define A 1
+#define SQ(x) ((x)*(x))
+#define B SQ(A)
+int f(void){
+    return B + SQ(2); /* A */
+}
+const char *s = "A";
+int g = SQ(
+    3);
+int SQ;
+"#,
+        )
+        .unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        let traces = ctx.preprocess_verbose(&p).unwrap();
+        assert_eq!(traces.len(), 2);
+        assert_eq!(traces[0].line, 5);
+        assert_eq!(traces[0].macros, ["B", "SQ", "A"]);
+        assert_eq!(traces[0].source, "    return B + SQ(2); /* A */");
+        assert_eq!(traces[0].expanded.trim(), "return ((1)*(1)) + ((2)*(2));");
+        assert_eq!(traces[1].line, 8);
+        assert_eq!(traces[1].macros, ["SQ"]);
+        assert_eq!(traces[1].expanded, "int g = ((3)*(3));");
+
+        let bad = CString::new("#if\n#endif").unwrap();
+        ctx.set_call_back(|_| ());
+        let err = ctx.preprocess_verbose(&bad).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Compile);
+    }
+}
@@ -0,0 +1,481 @@
+use crate::{
+    CompileConfig, Context, ContextBuilder, ErrorKind, Guard, OutputType, RelocatedCtx, TccError,
+};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::hash::{Hash, Hasher};
+use std::mem::transmute_copy;
+use std::os::raw::{c_char, c_int, c_void};
+
+/// Function pointer types that can be fetched from a [`Jit`] or [`RelocatedCtx`].
+///
+/// # Variadic functions
+/// A C function declared with `...`, e.g. `int sum(int n, ...)`, must be fetched as
+/// variadic pointer, e.g. `extern "C" fn(c_int, ...) -> c_int`, with the fixed
+/// arguments as declared. Calling it through a non-variadic pointer is undefined:
+/// variadic calls differ on x86_64, which passes the number of vector registers used,
+/// and on Apple arm64, which passes variadic arguments on stack. Variadic arguments are
+/// limited to scalars and pointers after C's default promotions, i.e. `c_int` or wider
+/// integers and `c_double`, Rust rejects the others. Structs can not be passed as
+/// variadic arguments. Up to 6 fixed arguments are supported, see [`PrintfLike`]
+/// for the common case of a format string.
+///
+/// # Safety
+/// Implementors must be plain function pointers of the same size as `*mut c_void`.
+pub unsafe trait FnPtr: Copy {
+    /// reinterpret a symbol address as `Self`.
+    ///
+    /// # Safety
+    /// `ptr` must point to a function whose signature matches `Self`.
+    unsafe fn from_ptr(ptr: *mut c_void) -> Self;
+}
+
+/// Argument tuples of compiled functions, see [`Context::compile_fn`](crate::Context::compile_fn).
+///
+/// # Safety
+/// Implementors must call `f` as an `extern "C"` function taking the tuple elements.
+pub unsafe trait FnArgs {
+    /// call function at `f` with `self` as arguments.
+    ///
+    /// # Safety
+    /// `f` must point to a function taking `Self` as arguments and returning `R`.
+    unsafe fn call<R>(self, f: *mut c_void) -> R;
+}
+
+macro_rules! impl_fn_ptr {
+    ($($arg:ident),*) => {
+        unsafe impl<$($arg),*> FnArgs for ($($arg,)*) {
+            #[allow(non_snake_case)]
+            unsafe fn call<R>(self, f: *mut c_void) -> R {
+                let f: extern "C" fn($($arg),*) -> R = transmute_copy(&f);
+                let ($($arg,)*) = self;
+                f($($arg),*)
+            }
+        }
+
+        unsafe impl<R, $($arg),*> FnPtr for extern "C" fn($($arg),*) -> R {
+            unsafe fn from_ptr(ptr: *mut c_void) -> Self {
+                transmute_copy(&ptr)
+            }
+        }
+
+        unsafe impl<R, $($arg),*> FnPtr for unsafe extern "C" fn($($arg),*) -> R {
+            unsafe fn from_ptr(ptr: *mut c_void) -> Self {
+                transmute_copy(&ptr)
+            }
+        }
+    };
+}
+
+impl_fn_ptr!();
+impl_fn_ptr!(A);
+impl_fn_ptr!(A, B);
+impl_fn_ptr!(A, B, C);
+impl_fn_ptr!(A, B, C, D);
+impl_fn_ptr!(A, B, C, D, E);
+impl_fn_ptr!(A, B, C, D, E, F);
+
+macro_rules! impl_variadic_fn_ptr {
+    ($($arg:ident),+) => {
+        unsafe impl<R, $($arg),+> FnPtr for extern "C" fn($($arg),+, ...) -> R {
+            unsafe fn from_ptr(ptr: *mut c_void) -> Self {
+                transmute_copy(&ptr)
+            }
+        }
+
+        unsafe impl<R, $($arg),+> FnPtr for unsafe extern "C" fn($($arg),+, ...) -> R {
+            unsafe fn from_ptr(ptr: *mut c_void) -> Self {
+                transmute_copy(&ptr)
+            }
+        }
+    };
+}
+
+impl_variadic_fn_ptr!(A);
+impl_variadic_fn_ptr!(A, B);
+impl_variadic_fn_ptr!(A, B, C);
+impl_variadic_fn_ptr!(A, B, C, D);
+impl_variadic_fn_ptr!(A, B, C, D, E);
+impl_variadic_fn_ptr!(A, B, C, D, E, F);
+
+/// Compiled function taking a printf format and its arguments, e.g. `int log(const char *fmt, ...)`.
+///
+/// Arguments must match the format as for printf, which is not checked.
+/// ```
+/// use libtcc::{Jit, PrintfLike};
+/// use std::os::raw::{c_char, c_int};
+/// let mut jit = Jit::new().unwrap();
+/// jit.compile(r#"
+///     #include <stdio.h>
+///     #include <stdarg.h>
+///     char buf[64];
+///     int format(const char *fmt, ...) {
+///         va_list ap;
+///         int n;
+///         va_start(ap, fmt);
+///         n = vsnprintf(buf, sizeof buf, fmt, ap);
+///         va_end(ap);
+///         return n;
+///     }
+/// "#).unwrap();
+/// let format: PrintfLike = unsafe { jit.get("format").unwrap() };
+/// let n = unsafe { format(b"%d-%s\0".as_ptr() as *const c_char, 42 as c_int, b"x\0".as_ptr()) };
+/// assert_eq!(n, 4);
+/// ```
+pub type PrintfLike = unsafe extern "C" fn(*const c_char, ...) -> c_int;
+
+/// All-in-one jit compiler owning its own guard.
+///
+/// Sources are compiled into memory and relocated lazily on the first [`get`](Jit::get),
+/// after which no more source can be added.
+/// ```
+/// use libtcc::Jit;
+/// let mut jit = Jit::new().unwrap();
+/// jit.compile("int add(int a, int b){ return a + b; }").unwrap();
+/// let add: extern "C" fn(i32, i32) -> i32 = unsafe { jit.get("add").unwrap() };
+/// assert_eq!(add(1, 2), 3);
+/// ```
+pub struct Jit {
+    // fields drop in order, so tcc states are released before the guard.
+    ctx: Option<Context<'static, 'static>>,
+    relocated: Option<RelocatedCtx<'static>>,
+    _g: Guard,
+}
+
+impl Jit {
+    /// Create a new jit, fail if a [`Guard`] already exists.
+    pub fn new() -> Result<Self, TccError> {
+        Self::with_guard(Guard::new()?)
+    }
+
+    /// same as [`new`](Jit::new), but own `g`, e.g. from [`Guard::wait`].
+    pub fn with_guard(g: Guard) -> Result<Self, TccError> {
+        // the guard is owned by the jit and outlives the context.
+        let mut ctx = unsafe { Context::new_unguarded() }
+            .map_err(|_| TccError::new(ErrorKind::Io, "out of memory"))?;
+        ctx.set_output_type(OutputType::Memory)?
+            .set_call_back(|_| ());
+        Ok(Self {
+            ctx: Some(ctx),
+            relocated: None,
+            _g: g,
+        })
+    }
+
+    /// compile a string containing a C source.
+    pub fn compile(&mut self, src: &str) -> Result<(), TccError> {
+        let src = CString::new(src)
+            .map_err(|_| TccError::new(ErrorKind::InvalidInput, "source contains nul byte"))?;
+        let ctx = self.ctx.as_mut().ok_or_else(|| {
+            TccError::new(ErrorKind::InvalidInput, "can not compile after relocation")
+        })?;
+        ctx.compile_string(&src)
+    }
+
+    /// return function `name` as `F`, relocating on first call.
+    ///
+    /// Return None if relocation fails or `name` is not found.
+    ///
+    /// # Safety
+    /// `F` must match the signature of the compiled function, and the returned
+    /// pointer can not outlive the jit itself.
+    pub unsafe fn get<F: FnPtr>(&mut self, name: &str) -> Option<F> {
+        let name = CString::new(name).ok()?;
+        if let Some(ctx) = self.ctx.take() {
+            self.relocated = ctx.relocate().ok();
+        }
+        self.relocated.as_mut()?.get_function(&name)
+    }
+}
+
+/// Jit caching relocated modules by hash of source and config, so that compiling
+/// the same source again skips tcc entirely.
+///
+/// At most `capacity` modules are kept, the least recently used one is evicted
+/// first. Cached modules keep their code mapped until evicted or the cache is
+/// dropped, so functions fetched from them stay valid until then.
+/// ```
+/// use libtcc::CachingJit;
+/// use std::ffi::CString;
+/// let mut jit = CachingJit::new(8).unwrap();
+/// let name = CString::new("answer").unwrap();
+/// let m = jit.compile("int answer(void){ return 42; }").unwrap();
+/// let answer: extern "C" fn() -> i32 = unsafe { m.get_function(&name).unwrap() };
+/// assert_eq!(answer(), 42);
+/// ```
+pub struct CachingJit {
+    config: CompileConfig,
+    capacity: usize,
+    // hash and source of each module, most recently used last, dropped before the
+    // guard. config is the same for all, so the source alone identifies a module.
+    modules: Vec<(u64, String, RelocatedCtx<'static>)>,
+    _g: Guard,
+}
+
+impl CachingJit {
+    /// Create a cache of `capacity` modules with default config, fail if a
+    /// [`Guard`] already exists. At least one module is kept.
+    pub fn new(capacity: usize) -> Result<Self, TccError> {
+        Self::with_config(CompileConfig::default(), capacity)
+    }
+
+    /// same as [`new`](CachingJit::new), but compile with `config`.
+    pub fn with_config(config: CompileConfig, capacity: usize) -> Result<Self, TccError> {
+        Ok(Self::with_guard(Guard::new()?, config, capacity))
+    }
+
+    /// same as [`with_config`](CachingJit::with_config), but own `g`, e.g. from [`Guard::wait`].
+    pub fn with_guard(g: Guard, config: CompileConfig, capacity: usize) -> Self {
+        Self {
+            config,
+            capacity: capacity.max(1),
+            modules: Vec::new(),
+            _g: g,
+        }
+    }
+
+    /// compile and relocate `src`, or return the cached module compiled from it.
+    pub fn compile(&mut self, src: &str) -> Result<&mut RelocatedCtx<'static>, TccError> {
+        let mut hasher = DefaultHasher::new();
+        src.hash(&mut hasher);
+        let key = hasher.finish();
+
+        // the hash only filters, a collision must not return another module.
+        let hit = self
+            .modules
+            .iter()
+            .position(|(k, s, _)| *k == key && s == src);
+        if let Some(i) = hit {
+            let module = self.modules.remove(i);
+            self.modules.push(module);
+        } else {
+            let c_src = CString::new(src)
+                .map_err(|_| TccError::new(ErrorKind::InvalidInput, "source contains nul byte"))?;
+            // the guard is owned by the cache and outlives the modules.
+            let mut ctx =
+                unsafe { ContextBuilder::from_config(self.config.clone()).build_unguarded() }?;
+            ctx.set_call_back(|_| ()).compile_string(&c_src)?;
+            let module = ctx.relocate()?;
+            // no context is alive now, so evicted modules can be deleted.
+            if self.modules.len() == self.capacity {
+                self.modules.remove(0);
+            }
+            self.modules.push((key, src.to_owned(), module));
+        }
+        Ok(&mut self.modules.last_mut().unwrap().2)
+    }
+
+    /// number of cached modules.
+    pub fn len(&self) -> usize {
+        self.modules.len()
+    }
+
+    /// return true if no module is cached.
+    pub fn is_empty(&self) -> bool {
+        self.modules.is_empty()
+    }
+}
+
+/// Interpreter-like session, where each source evaluated can use functions and
+/// variables defined by the earlier ones, like cells of a notebook.
+///
+/// Sources are compiled separately, symbols of the earlier modules are passed by
+/// [`Context::add_symbol`], so they must be declared before use. Every module is kept
+/// alive by the session. Defining a symbol again in a later source is an error.
+/// ```
+/// use libtcc::Session;
+/// use std::ffi::CString;
+/// let mut session = Session::new().unwrap();
+/// let name = CString::new("quad").unwrap();
+/// session.eval("int sq(int x){ return x * x; }").unwrap();
+/// let m = session.eval("int sq(int); int quad(int x){ return sq(sq(x)); }").unwrap();
+/// let quad: extern "C" fn(i32) -> i32 = unsafe { m.get_function(&name).unwrap() };
+/// assert_eq!(quad(2), 16);
+/// ```
+pub struct Session {
+    config: CompileConfig,
+    // symbols defined by the modules so far.
+    symbols: HashMap<CString, *mut c_void>,
+    // dropped before the guard.
+    modules: Vec<RelocatedCtx<'static>>,
+    _g: Guard,
+}
+
+impl Session {
+    /// Create an empty session with default config, fail if a [`Guard`] already exists.
+    pub fn new() -> Result<Self, TccError> {
+        Self::with_config(CompileConfig::default())
+    }
+
+    /// same as [`new`](Session::new), but compile with `config`.
+    pub fn with_config(config: CompileConfig) -> Result<Self, TccError> {
+        Ok(Self::with_guard(Guard::new()?, config))
+    }
+
+    /// same as [`with_config`](Session::with_config), but own `g`, e.g. from [`Guard::wait`].
+    pub fn with_guard(g: Guard, config: CompileConfig) -> Self {
+        Self {
+            config,
+            symbols: HashMap::new(),
+            modules: Vec::new(),
+            _g: g,
+        }
+    }
+
+    /// compile and relocate `src` with access to symbols of the earlier sources,
+    /// return the new module.
+    ///
+    /// Nothing is kept if it fails, so a corrected source can be evaluated again.
+    pub fn eval(&mut self, src: &str) -> Result<&mut RelocatedCtx<'static>, TccError> {
+        let src = CString::new(src)
+            .map_err(|_| TccError::new(ErrorKind::InvalidInput, "source contains nul byte"))?;
+        // the guard is owned by the session and outlives the modules.
+        let mut ctx =
+            unsafe { ContextBuilder::from_config(self.config.clone()).build_unguarded() }?;
+        ctx.set_call_back(|_| ());
+        for (name, addr) in &self.symbols {
+            // the module defining it is kept alive by the session.
+            unsafe { ctx.add_symbol(name, *addr) };
+        }
+        ctx.compile_string(&src)?;
+        let module = ctx.relocate()?;
+        self.symbols.extend(module.symbol_map());
+        self.modules.push(module);
+        Ok(self.modules.last_mut().unwrap())
+    }
+
+    /// number of modules evaluated so far.
+    pub fn len(&self) -> usize {
+        self.modules.len()
+    }
+
+    /// return true if nothing is evaluated yet.
+    pub fn is_empty(&self) -> bool {
+        self.modules.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// wait for other tests holding the guard.
+    fn new_jit() -> Jit {
+        Jit::with_guard(Guard::wait()).unwrap()
+    }
+
+    #[test]
+    fn compile_error() {
+        let mut jit = new_jit();
+        let err = jit
+            .compile("int bad(int a, int b){ return a + }")
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Compile);
+        assert!(err.message().contains("error"));
+        assert!(jit
+            .compile("int add(int a, int b){ return a + b; }")
+            .is_ok());
+        let add: Option<extern "C" fn(i32, i32) -> i32> = unsafe { jit.get("add") };
+        assert_eq!(add.unwrap()(1, 2), 3);
+        let err = jit
+            .compile("int sub(int a, int b){ return a - b; }")
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn variadic() {
+        let mut jit = new_jit();
+        jit.compile(
+            r#"
+            #include <stdarg.h>
+            double sum(int n, ...) {
+                va_list ap;
+                double s = 0;
+                va_start(ap, n);
+                while (n--)
+                    s += va_arg(ap, int) * va_arg(ap, double);
+                va_end(ap);
+                return s;
+            }
+            int count(const char *fmt, ...) {
+                va_list ap;
+                int n = 0;
+                va_start(ap, fmt);
+                for (; *fmt; fmt++)
+                    if (*fmt == 'd')
+                        n += va_arg(ap, int);
+                    else if (*fmt == 's')
+                        n += *va_arg(ap, const char *) != 0;
+                va_end(ap);
+                return n;
+            }
+        "#,
+        )
+        .unwrap();
+        let sum: unsafe extern "C" fn(c_int, ...) -> f64 = unsafe { jit.get("sum").unwrap() };
+        assert_eq!(
+            unsafe { sum(2, 1 as c_int, 0.5f64, 2 as c_int, 1.5f64) },
+            3.5
+        );
+        let count: PrintfLike = unsafe { jit.get("count").unwrap() };
+        let fmt = CString::new("dsd").unwrap();
+        let s = CString::new("x").unwrap();
+        assert_eq!(
+            unsafe { count(fmt.as_ptr(), 1 as c_int, s.as_ptr(), 2 as c_int) },
+            4
+        );
+    }
+
+    #[test]
+    fn caching_jit() {
+        let mut jit = CachingJit::with_guard(Guard::wait(), CompileConfig::default(), 2);
+        let name = CString::new("f").unwrap();
+        let src = |n: i32| format!("int f(void){{ return {}; }}", n);
+        let first = jit.compile(&src(1)).unwrap().base_ptr();
+        assert_eq!(jit.compile(&src(1)).unwrap().base_ptr(), first);
+        let f: extern "C" fn() -> i32 =
+            unsafe { jit.compile(&src(1)).unwrap().get_function(&name).unwrap() };
+        assert_eq!(f(), 1);
+
+        jit.compile(&src(2)).unwrap();
+        // 1 is used more recently than 2, so 2 is evicted.
+        jit.compile(&src(1)).unwrap();
+        jit.compile(&src(3)).unwrap();
+        assert_eq!(jit.len(), 2);
+        assert_eq!(f(), 1);
+        assert_eq!(jit.compile(&src(1)).unwrap().base_ptr(), first);
+        let err = jit.compile("int g(void){ return }").err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::Compile);
+        assert_eq!(jit.len(), 2);
+
+        // a module whose hash collides with another source is not returned for it.
+        let mut hasher = DefaultHasher::new();
+        src(4).hash(&mut hasher);
+        jit.modules.last_mut().unwrap().0 = hasher.finish();
+        let f: extern "C" fn() -> i32 =
+            unsafe { jit.compile(&src(4)).unwrap().get_function(&name).unwrap() };
+        assert_eq!(f(), 4);
+    }
+
+    #[test]
+    fn session() {
+        let mut session = Session::with_guard(Guard::wait(), CompileConfig::default());
+        session.eval("int sq(int x){ return x * x; }").unwrap();
+        let err = session
+            .eval("int cube(int x){ return x * sq(x) }")
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::Compile);
+        assert_eq!(session.len(), 1);
+
+        let name = CString::new("quad").unwrap();
+        let m = session
+            .eval("int sq(int); int quad(int x){ return sq(sq(x)); }")
+            .unwrap();
+        let quad: extern "C" fn(i32) -> i32 = unsafe { m.get_function(&name).unwrap() };
+        assert_eq!(quad(2), 16);
+        assert_eq!(session.len(), 2);
+    }
+}
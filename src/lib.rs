@@ -1,4 +1,8 @@
-#![allow(clippy::type_complexity, clippy::should_implement_trait)]
+#![allow(
+    clippy::type_complexity,
+    clippy::should_implement_trait,
+    clippy::result_unit_err
+)]
 #![deny(missing_docs)]
 
 //! Rust binding for [tcc](https://repo.or.cz/w/tinycc.git)
@@ -16,49 +20,182 @@
 //! let mut ctx = Context::new(&mut g).unwrap();
 //! assert!(ctx.compile_string(&p).is_ok());
 //! ```
+//!
+//! # Untrusted input
+//! Invalid input to this crate, e.g. malformed options, macro definitions or
+//! paths containing nul, is reported as `Err` instead of panicking or letting
+//! tcc exit the process. However, tcc itself is not hardened against hostile
+//! sources: a crafted program may still crash or hang the compiler, and compiled
+//! code runs with the full privileges of the host process. Compile and run
+//! untrusted sources in a separate, sandboxed process.
 
 /// libtcc.h itself is cross-platform, so no need for runtime generating
 #[allow(dead_code)]
 mod binding;
+mod builder;
+#[cfg(feature = "codespan")]
+mod codespan;
+mod error;
+mod expand;
+mod jit;
+mod options;
+mod pragma;
+mod shared;
+mod shims;
 
 use binding::*;
-use std::ffi::{CStr, CString};
+pub use builder::{CompileConfig, ContextBuilder, MultiOutput};
+pub use error::{Diagnostic, ErrorKind, GuardError, Severity, TccError};
+pub use expand::Expansion;
+pub use jit::{CachingJit, FnArgs, FnPtr, Jit, PrintfLike, Session};
+pub use options::ContextConfig;
+use options::Setting;
+pub use pragma::Pragma;
+pub use shared::SharedSymbols;
+pub use shims::LibcShims;
+use std::alloc::{self, Layout};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CStr, CString, OsString};
+use std::fmt::Display;
+use std::io::Write;
 use std::marker::PhantomData;
-use std::os::raw::{c_char, c_int, c_void};
-use std::path::Path;
+use std::ops::{Deref, Range};
+use std::os::raw::{c_char, c_int, c_long, c_void};
+use std::panic;
+use std::path::{Component, Path, PathBuf};
+use std::process;
 use std::ptr::null_mut;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::slice;
+#[cfg(not(feature = "no-guard"))]
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{env, fs, io};
 
+#[cfg(not(feature = "no-guard"))]
 static AVAILABLE: AtomicBool = AtomicBool::new(true);
 
 /// An empty type prevents the use of TCC simultaneously.
+///
+/// The guard is released when dropped, also while unwinding from a panic, so a
+/// panicking user of tcc does not lock out the others.
 /// ```
 /// use libtcc::Guard;
 /// let g1 = Guard::new();
 /// assert!(g1.is_ok());
 /// let g2 = Guard::new();
+/// # #[cfg(not(feature = "no-guard"))]
 /// assert!(g2.is_err());
 /// ```
+///
+/// # `no-guard` feature
+/// With the `no-guard` feature, [`Guard::new`] always succeeds and [`Guard::wait`]
+/// never waits, for applications serializing the use of tcc by themselves.
+///
+/// **This removes the only protection this crate has against compiling from several
+/// threads at once, which corrupts tcc's global state.** Every guard, context and
+/// [`serial_tcc`] must then be confined to one thread at a time by the caller,
+/// including tests, which `cargo test` runs in parallel.
 pub struct Guard([u8; 0]);
 
 impl Guard {
     /// Creat a new guard, return Err if a instance already exists.
-    pub fn new() -> Result<Guard, &'static str> {
+    #[cfg(not(feature = "no-guard"))]
+    pub fn new() -> Result<Guard, GuardError> {
         if AVAILABLE.swap(false, Ordering::SeqCst) {
             Ok(Guard([]))
         } else {
-            Err("Try to create TCC instance multiple time")
+            Err(GuardError::AlreadyHeld)
+        }
+    }
+
+    /// Creat a new guard, which always succeeds with the `no-guard` feature.
+    #[cfg(feature = "no-guard")]
+    pub fn new() -> Result<Guard, GuardError> {
+        Ok(Guard([]))
+    }
+}
+
+impl Guard {
+    /// Block until no other guard exists, then create one.
+    pub fn wait() -> Guard {
+        loop {
+            if let Ok(g) = Guard::new() {
+                return g;
+            }
+            thread::sleep(Duration::from_millis(1));
         }
     }
+
+    /// Release the guard held by someone else, e.g. one leaked by `mem::forget`,
+    /// which otherwise locks out tcc until the process exits.
+    ///
+    /// # Safety
+    /// The leaked guard and every context created with it must be gone, i.e.
+    /// forgotten or dropped, and never used again. Resetting while a guard or
+    /// context is in use lets two tcc instances run simultaneously.
+    pub unsafe fn force_reset() {
+        #[cfg(not(feature = "no-guard"))]
+        AVAILABLE.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Run `f` with a guard, waiting for other users of tcc to finish first.
+///
+/// Tests using tcc can not run simultaneously, wrapping each of them with
+/// `serial_tcc` makes them safe to run under parallel `cargo test`:
+/// ```
+/// use libtcc::{serial_tcc, Context};
+/// use std::ffi::CString;
+/// let p = CString::new("int f(void){ return 1; }").unwrap();
+/// serial_tcc(|g| {
+///     let mut ctx = Context::new(g).unwrap();
+///     assert!(ctx.compile_string(&p).is_ok());
+/// });
+/// ```
+/// Note that [`Guard::new`] and [`Jit::new`] do not wait, and fail while
+/// `serial_tcc` is running.
+pub fn serial_tcc<R, F: FnOnce(&mut Guard) -> R>(f: F) -> R {
+    let mut g = Guard::wait();
+    f(&mut g)
 }
 
 impl Drop for Guard {
     fn drop(&mut self) {
+        #[cfg(not(feature = "no-guard"))]
         AVAILABLE.store(true, Ordering::SeqCst);
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// architectures the linked libtcc generates code for, as tcc names them, e.g. `x86_64`.
+///
+/// A libtcc build targets a single architecture, which is the target of this crate.
+pub fn supported_targets() -> &'static [&'static str] {
+    include!(concat!(env!("OUT_DIR"), "/targets.rs"))
+}
+
+/// Features of the linked libtcc, which depend on how it is built, see [`tcc_capabilities`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// bounds checking by -b, see [`Context::enable_bounds_check`].
+    pub bounds_check: bool,
+    /// backtrace of runtime errors in code run from memory, by -bt.
+    pub backtrace: bool,
+    /// libtcc is cross compiled for the target of this crate.
+    pub cross: bool,
+}
+
+/// features the linked libtcc is built with, detected when building this crate.
+pub fn tcc_capabilities() -> Capabilities {
+    include!(concat!(env!("OUT_DIR"), "/capabilities.rs"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u32)]
 /// Output type of the compilation.
 pub enum OutputType {
@@ -78,18 +215,216 @@ pub enum OutputType {
     Preprocess = TCC_OUTPUT_PREPROCESS,
 }
 
+/// C standard of the compilation, see [`Context::c_std`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum CStd {
+    /// C99, with `__STDC_VERSION__` of `199901L`.
+    C99,
+}
+
+/// Options of [`Context::execute`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExecuteOptions {
+    /// kill the program if it runs longer, no limit if None.
+    pub timeout: Option<Duration>,
+    /// stack size of `main`, below a guard page, that of the forking thread if None.
+    ///
+    /// Only supported on linux, starting the program fails elsewhere.
+    pub stack_size: Option<usize>,
+}
+
+/// Outcome of a program run by [`Context::execute`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecuteResult {
+    /// exit code, or 128 plus the number of the signal killing the program, like sh.
+    pub code: i32,
+    /// output written to stdout.
+    pub stdout: Vec<u8>,
+    /// output written to stderr.
+    pub stderr: Vec<u8>,
+    /// the program is killed for running longer than the timeout.
+    pub timed_out: bool,
+}
+
+/// How a program run by [`Context::run_sandboxed`] ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum RunOutcome {
+    /// `main` returned, or `exit` is called, with the code.
+    Exited(i32),
+    /// the program is killed by the signal, e.g. `SIGSEGV` of a null dereference.
+    Crashed(i32),
+}
+
+/// Set of runtime checks of compiled code, see [`Context::enable_checks`].
+///
+/// Checks are combined by `|`, e.g. `Checks::BOUNDS | other`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Checks(u32);
+
+impl Checks {
+    /// bounds of pointer accesses, see [`Context::enable_bounds_check`].
+    pub const BOUNDS: Checks = Checks(1);
+
+    /// no check.
+    pub const fn empty() -> Checks {
+        Checks(0)
+    }
+
+    /// checks supported by the linked libtcc, see [`tcc_capabilities`].
+    pub fn supported() -> Checks {
+        let mut checks = Checks::empty();
+        if tcc_capabilities().bounds_check {
+            checks |= Checks::BOUNDS;
+        }
+        checks
+    }
+
+    /// return true if all of `other` are in the set.
+    pub const fn contains(self, other: Checks) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// return true if there is no check in the set.
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl std::ops::BitOr for Checks {
+    type Output = Checks;
+
+    fn bitor(self, rhs: Checks) -> Checks {
+        Checks(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for Checks {
+    fn bitor_assign(&mut self, rhs: Checks) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// What to optimize compiled code for, see [`Context::optimize_for`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OptGoal {
+    /// smaller code, like -Os of gcc.
+    Size,
+    /// faster code, like -O2 of gcc.
+    Speed,
+}
+
+/// Visibility of symbols not marked otherwise, see [`Context::set_default_visibility`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Visibility {
+    /// visible to other modules, as by default in C.
+    Default,
+    /// bound within the module, like -fvisibility=hidden of gcc.
+    Hidden,
+}
+
+/// C library to compile against, see [`Context::use_libc`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Libc {
+    /// the GNU C library.
+    Glibc,
+    /// musl libc.
+    Musl,
+}
+
+/// Step of a build reported by [`Context::on_phase`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// preprocessing only, e.g. by [`Context::preprocess_to_writer`].
+    Preprocess,
+    /// compiling or assembling a source.
+    Compile,
+    /// linking the compiled code with libraries, for an executable, a library or
+    /// relocation.
+    Link,
+    /// copying linked code into memory of the module.
+    Relocate,
+}
+
+impl OutputType {
+    /// usual extension of the output file on this platform, without the dot, e.g. `so`.
+    ///
+    /// Empty for [`OutputType::Memory`], and for executables other than on windows.
+    pub fn default_extension(&self) -> &'static str {
+        match self {
+            OutputType::Memory => "",
+            OutputType::Exe if cfg!(windows) => "exe",
+            OutputType::Exe => "",
+            OutputType::Dll if cfg!(windows) => "dll",
+            OutputType::Dll if cfg!(target_os = "macos") => "dylib",
+            OutputType::Dll => "so",
+            OutputType::Obj => "o",
+            OutputType::Preprocess => "i",
+        }
+    }
+}
+
 /// Compilation context.
 pub struct Context<'a, 'b> {
     inner: *mut TCCState,
-    _g: &'a mut Guard,
-    err_func: Option<Box<Box<dyn 'b + FnMut(&CStr)>>>,
+    _g: PhantomData<&'a mut Guard>,
+    diag: Box<Diagnostics<'b>>,
+    input_name: Option<CString>,
+    object_name: Option<CString>,
+    output: Option<OutputType>,
+    // tcc_set_output_type is called once, see apply_output_type.
+    output_applied: Cell<bool>,
+    started: bool,
+    emitted: Option<OutputType>,
+    base_dir: Option<PathBuf>,
+    dep_output: Option<PathBuf>,
+    shared: Vec<SharedSymbols>,
+    deny_dir: Option<PathBuf>,
+    includes: Option<IncludeResolver<'b>>,
+    max_include_depth: Option<u32>,
+    visibility: Visibility,
+    bounds_check: bool,
+    verbosity: u8,
+    entry: Option<CString>,
+    resolver: Option<Box<dyn 'b + FnMut(&CStr) -> Option<*const c_void>>>,
+    transform: Option<Box<dyn 'b + FnMut(&[u8]) -> Vec<u8>>>,
+    on_phase: Option<Box<dyn 'b + FnMut(Phase)>>,
+    pragmas: Vec<(String, bool)>,
+    collected: Vec<Pragma>,
+    settings: Vec<Setting>,
+    reloc_size: Option<usize>,
     phantom: PhantomData<TCCState>,
 }
 
+/// INCLUDE_STACK_SIZE of tcc, deeper includes always fail.
+const MAX_INCLUDE_DEPTH: u32 = 32;
+
+/// -d flags of tcc's preprocessor, same as -dM.
+const DFLAG_DUMP_MACROS: c_int = 7;
+
+/// Diagnostics of the running operation, and user's call back.
+struct Diagnostics<'b> {
+    msgs: Vec<String>,
+    err_func: Option<Box<dyn 'b + FnMut(&CStr)>>,
+}
+
+/// Headers served by the user's include resolver, see [`Context::set_include_resolver`].
+struct IncludeResolver<'b> {
+    // include path holding the served headers.
+    dir: PathBuf,
+    resolve: Box<dyn 'b + FnMut(&CStr, bool) -> Option<Vec<u8>>>,
+    // names passed to resolve so far, served or not.
+    asked: HashSet<String>,
+}
+
 /// Real call back of tcc.
 extern "C" fn call_back(opaque: *mut c_void, msg: *const c_char) {
-    let func: *mut &mut dyn FnMut(&CStr) = opaque as *mut &mut dyn FnMut(&CStr);
-    unsafe { (*func)(CStr::from_ptr(msg)) }
+    let diag = opaque as *mut Diagnostics;
+    let msg = unsafe { CStr::from_ptr(msg) };
+    let diag = unsafe { &mut *diag };
+    diag.msgs.push(msg.to_string_lossy().into_owned());
+    diag.report(msg);
 }
 
 impl<'a, 'b> Context<'a, 'b> {
@@ -97,484 +432,5516 @@ impl<'a, 'b> Context<'a, 'b> {
     ///
     /// Context can not live together, mutable reference to guard makes compiler check this.
     /// Out of memory is only possible reason of failure.
-    pub fn new(g: &'a mut Guard) -> Result<Self, ()> {
-        let inner = unsafe { tcc_new() };
+    pub fn new(_g: &'a mut Guard) -> Result<Self, ()> {
+        unsafe { Self::new_unguarded() }
+    }
+
+    /// Create a new context without borrowing a guard.
+    ///
+    /// # Safety
+    /// Caller must hold the guard for the whole lifetime of the context.
+    pub(crate) unsafe fn new_unguarded() -> Result<Self, ()> {
+        let inner = tcc_new();
         if inner.is_null() {
             // OOM
             Err(())
         } else {
+            let mut diag = Box::new(Diagnostics {
+                msgs: Vec::new(),
+                err_func: None,
+            });
+            tcc_set_error_func(
+                inner,
+                diag.as_mut() as *mut _ as *mut c_void,
+                Some(call_back),
+            );
             Ok(Self {
                 inner,
-                _g: g,
-                err_func: None,
+                _g: PhantomData,
+                diag,
+                input_name: None,
+                object_name: None,
+                output: None,
+                output_applied: Cell::new(false),
+                started: false,
+                emitted: None,
+                base_dir: None,
+                dep_output: None,
+                shared: Vec::new(),
+                deny_dir: None,
+                includes: None,
+                max_include_depth: None,
+                visibility: Visibility::Default,
+                transform: None,
+                bounds_check: false,
+                verbosity: 0,
+                entry: None,
+                resolver: None,
+                on_phase: None,
+                pragmas: Vec::new(),
+                collected: Vec::new(),
+                settings: Vec::new(),
+                reloc_size: None,
                 phantom: PhantomData,
             })
         }
     }
 
+    /// Create a context linking object files `objs` into `output`.
+    ///
+    /// The returned context is ready for [`output_file`](Context::output_file) or
+    /// [`relocate`](Context::relocate). Err names the object that tcc fails to load.
+    pub fn link_objects<T: AsRef<Path>>(
+        g: &'a mut Guard,
+        objs: &[T],
+        output: OutputType,
+    ) -> Result<Self, TccError> {
+        let mut ctx = Self::new(g).map_err(|_| TccError::new(ErrorKind::Io, "out of memory"))?;
+        ctx.set_output_type(output)?;
+        for obj in objs {
+            let obj = obj.as_ref();
+            ctx.add_file(obj).map_err(|e| {
+                let msg = format!("fail to link {}: {}", obj.display(), e.message());
+                TccError::new(e.kind(), msg)
+            })?;
+        }
+        Ok(ctx)
+    }
+
     /// set CONFIG_TCCDIR at runtime
-    pub fn set_lib_path<T: AsRef<Path>>(&mut self, path: T) -> &mut Self {
-        let path = to_cstr(path);
+    ///
+    /// The path is kept by each context, so contexts may use different runtime
+    /// libraries, e.g. libtcc1.a. It should be set before
+    /// [`set_output_type`](Context::set_output_type), which adds tcc's own include
+    /// paths under it.
+    pub fn set_lib_path<T: AsRef<Path>>(&mut self, path: T) -> Result<&mut Self, TccError> {
+        let path = to_cstr(path)?;
         unsafe {
             tcc_set_lib_path(self.inner, path.as_ptr());
         }
+        self.settings.push(Setting::LibPath(from_cstr(&path)));
+        Ok(self)
+    }
+
+    /// return the path set by [`set_lib_path`](Context::set_lib_path), or CONFIG_TCCDIR.
+    pub fn lib_path(&self) -> PathBuf {
+        from_cstr(unsafe { CStr::from_ptr(tcc_ext_get_lib_path(self.inner)) })
+    }
+
+    /// treat warnings as errors if `on`, same as -Werror and -Wno-error.
+    ///
+    /// With it, a warning makes the compilation return Err of [`ErrorKind::Compile`].
+    pub fn werror(&mut self, on: bool) -> &mut Self {
+        let opt: &[u8] = if on { b"-Werror\0" } else { b"-Wno-error\0" };
+        // both are valid options.
+        unsafe { tcc_ext_set_options(self.inner, opt.as_ptr() as *const c_char) };
+        self.settings.push(Setting::Werror(on));
         self
     }
 
-    /// set options as from command line (multiple supported)
-    pub fn set_options(&mut self, option: &CStr) -> &mut Self {
-        unsafe {
-            tcc_set_options(self.inner, option.as_ptr());
+    /// make calls of undeclared functions an error, same as
+    /// -Wimplicit-function-declaration -Werror.
+    ///
+    /// tcc can not turn a single warning into an error, so any other warning fails
+    /// the compilation as well, see [`werror`](Context::werror).
+    pub fn strict_decl(&mut self) -> &mut Self {
+        self.set_valid_option(b"-Wimplicit-function-declaration\0");
+        self.werror(true)
+    }
+
+    /// optimize compiled code for `goal`.
+    ///
+    /// tcc is a single pass compiler without optimizer, code generated is the same
+    /// for either goal. Only the macros of gcc are defined, so that headers can pick
+    /// variants for the goal:
+    /// - [`OptGoal::Speed`] sets -O2, defining `__OPTIMIZE__`.
+    /// - [`OptGoal::Size`] defines `__OPTIMIZE__` and `__OPTIMIZE_SIZE__`, since tcc
+    ///   ignores -Os.
+    pub fn optimize_for(&mut self, goal: OptGoal) -> &mut Self {
+        match goal {
+            OptGoal::Speed => self.set_valid_option(b"-O2\0"),
+            OptGoal::Size => {
+                let one = CStr::from_bytes_with_nul(b"1\0").unwrap();
+                for sym in [&b"__OPTIMIZE__\0"[..], b"__OPTIMIZE_SIZE__\0"] {
+                    // valid definitions, can not fail.
+                    let _ = self.define_symbol(CStr::from_bytes_with_nul(sym).unwrap(), one);
+                }
+            }
+        }
+        self
+    }
+
+    /// set `opt`, ending with nul, which tcc always accepts.
+    fn set_valid_option(&mut self, opt: &[u8]) {
+        let opt = CStr::from_bytes_with_nul(opt).unwrap();
+        unsafe { tcc_ext_set_options(self.inner, opt.as_ptr()) };
+        self.settings.push(Setting::Options(opt.to_owned()));
+    }
+
+    /// check bounds of pointer accesses in compiled code, same as -b.
+    ///
+    /// An invalid access reports the error on stderr and crashes by writing to null,
+    /// instead of silently corrupting memory. Checked code is several times slower,
+    /// and needs the bcheck runtime, either in libtcc1.a or as bcheck.o in the
+    /// [lib path](Context::set_lib_path), otherwise relocation fails. The runtime is
+    /// initialized by [`relocate`](Context::relocate). Must be called before
+    /// [`set_output_type`](Context::set_output_type), and is unsupported by some builds
+    /// of tcc, see [`tcc_capabilities`].
+    pub fn enable_bounds_check(&mut self) -> Result<&mut Self, TccError> {
+        if !tcc_capabilities().bounds_check {
+            return Err(TccError::new(
+                ErrorKind::InvalidInput,
+                "bounds check is unsupported in this build of tcc",
+            ));
+        }
+        if self.output.is_some() {
+            return Err(TccError::new(
+                ErrorKind::InvalidInput,
+                "bounds check must be enabled before output type",
+            ));
+        }
+        // a valid option.
+        unsafe { tcc_ext_set_options(self.inner, b"-b\0".as_ptr() as *const c_char) };
+        self.bounds_check = true;
+        self.settings.push(Setting::BoundsCheck);
+        Ok(self)
+    }
+
+    /// enable runtime checks of compiled code, with the runtime they need.
+    ///
+    /// tcc only offers bounds checking, which also covers arrays on the stack, see
+    /// [`enable_bounds_check`](Context::enable_bounds_check) for its requirements. Return
+    /// Err of [`ErrorKind::InvalidInput`] if any of `checks` is not in
+    /// [`Checks::supported`], without enabling the others.
+    pub fn enable_checks(&mut self, checks: Checks) -> Result<&mut Self, TccError> {
+        let unsupported = Checks(checks.0 & !Checks::supported().0);
+        if !unsupported.is_empty() {
+            return Err(TccError::new(
+                ErrorKind::InvalidInput,
+                format!("{:?} unsupported in this build of tcc", unsupported),
+            ));
+        }
+        if checks.contains(Checks::BOUNDS) {
+            self.enable_bounds_check()?;
+        }
+        Ok(self)
+    }
+
+    /// do not link libc, libtcc1.a and crt objects, same as -nostdlib.
+    ///
+    /// Code must not call anything it does not define itself, or get by
+    /// [`add_symbol`](Context::add_symbol), see also
+    /// [`set_entry_symbol`](Context::set_entry_symbol). Must be called before
+    /// [`set_output_type`](Context::set_output_type).
+    pub fn no_std_lib(&mut self) -> Result<&mut Self, TccError> {
+        if self.output.is_some() {
+            return Err(TccError::new(
+                ErrorKind::InvalidInput,
+                "nostdlib must be set before output type",
+            ));
         }
+        self.set_valid_option(b"-nostdlib\0");
+        Ok(self)
+    }
+
+    /// use `sym` as entry point of the relocated module, e.g. a custom `_start`.
+    ///
+    /// [`relocate`](Context::relocate) fails with [`ErrorKind::Link`] if `sym` is not
+    /// defined, its address is returned by [`RelocatedCtx::entry_point`]. Executables
+    /// of tcc always start at `_start`, so [`output_file`](Context::output_file) of
+    /// [`OutputType::Exe`] fails with another entry.
+    pub fn set_entry_symbol(&mut self, sym: &CStr) -> &mut Self {
+        self.entry = Some(sym.to_owned());
+        self.settings.push(Setting::Entry(sym.to_owned()));
         self
     }
 
+    /// set options as from command line (multiple supported)
+    ///
+    /// Return Err if tcc fails to parse the options, e.g. missing argument.
+    pub fn set_options(&mut self, option: &CStr) -> Result<&mut Self, TccError> {
+        self.check(ErrorKind::InvalidInput, |s| unsafe {
+            tcc_ext_set_options(s, option.as_ptr())
+        })?;
+        self.settings.push(Setting::Options(option.to_owned()));
+        Ok(self)
+    }
+
+    /// receive diagnostics by a channel instead of a call back, e.g. on another thread.
+    ///
+    /// This replaces the [call back](Context::set_call_back). Diagnostics reported
+    /// after the receiver is dropped are discarded.
+    pub fn diagnostics_channel(&mut self) -> Receiver<Diagnostic> {
+        let (tx, rx) = mpsc::channel();
+        self.set_call_back(move |msg| {
+            // the receiver may be gone.
+            let _ = tx.send(Diagnostic::parse(&msg.to_string_lossy()));
+        });
+        rx
+    }
+
     /// set error/warning display callback
     pub fn set_call_back<T>(&mut self, f: T) -> &mut Self
     where
         T: FnMut(&CStr) + 'b,
     {
-        let mut user_err_func: Box<Box<dyn FnMut(&CStr)>> = Box::new(Box::new(f));
-        // user_err_func.as_mut().
-        unsafe {
-            tcc_set_error_func(
-                self.inner,
-                user_err_func.as_mut() as *mut _ as *mut c_void,
-                Some(call_back),
-            )
-        }
-        self.err_func = Some(user_err_func);
+        self.diag.err_func = Some(Box::new(f));
         self
     }
 
-    /// add include path
-    pub fn add_include_path<T: AsRef<Path>>(&mut self, path: T) -> &mut Self {
-        let path = to_cstr(path);
-        let ret = unsafe { tcc_add_include_path(self.inner, path.as_ptr()) };
-        // this api only returns 0.
-        assert_eq!(ret, 0);
+    /// call `f` when a step of the build starts, e.g. to drive a progress bar.
+    ///
+    /// Phases are reported at the boundaries of this crate's calls, not from within
+    /// tcc: [`Phase::Compile`] by each call compiling a source, including
+    /// [`add_file`](Context::add_file) of one, [`Phase::Link`] by
+    /// [`relocate`](Context::relocate) and outputs other than objects, followed by
+    /// [`Phase::Relocate`] for the former. A phase is reported before it runs, even
+    /// if it fails.
+    pub fn on_phase<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnMut(Phase) + 'b,
+    {
+        self.on_phase = Some(Box::new(f));
         self
     }
 
-    /// add in system include path
-    pub fn add_sys_include_path<T: AsRef<Path>>(&mut self, path: T) -> &mut Self {
-        let path = to_cstr(path);
-        let ret = unsafe { tcc_add_sysinclude_path(self.inner, path.as_ptr()) };
-        // this api only returns 0.
-        assert_eq!(ret, 0);
-        self
+    fn report_phase(&mut self, phase: Phase) {
+        if let Some(ref mut f) = self.on_phase {
+            f(phase);
+        }
     }
 
-    /// define preprocessor symbol 'sym'. Can put optional value
-    pub fn define_symbol(&mut self, sym: &CStr, val: &CStr) -> *mut Self {
-        unsafe {
-            tcc_define_symbol(self.inner, sym.as_ptr(), val.as_ptr());
+    /// serve headers by `f` instead of the file system, e.g. from an in-memory project.
+    ///
+    /// `f` is called with the name as written in the include directive, and true for
+    /// `<name>`, false for `"name"`. Return None to search the include paths as usual.
+    ///
+    /// tcc has no such hook, so includes of a source, and of the headers served, are
+    /// scanned before [`compile_string`](Context::compile_string) and
+    /// [`preprocess_verbose`](Context::preprocess_verbose), and served headers are
+    /// written to a temporary include path searched first. Each name is asked once,
+    /// names computed by macros and absolute names are not seen by `f`. Setting
+    /// another resolver discards the headers served by the earlier one.
+    pub fn set_include_resolver<F>(&mut self, f: F) -> Result<&mut Self, TccError>
+    where
+        F: FnMut(&CStr, bool) -> Option<Vec<u8>> + 'b,
+    {
+        let dir = match self.includes.take() {
+            Some(includes) => {
+                fs::remove_dir_all(&includes.dir).map_err(io_error)?;
+                fs::create_dir_all(&includes.dir).map_err(io_error)?;
+                includes.dir
+            }
+            None => {
+                let dir = temp_include_dir("vfs")?;
+                let c_dir = to_cstr(&dir)?;
+                // after the denied headers.
+                let index = self.deny_dir.is_some() as c_int;
+                unsafe { tcc_ext_insert_include_path(self.inner, c_dir.as_ptr(), index) };
+                dir
+            }
+        };
+        self.includes = Some(IncludeResolver {
+            dir,
+            resolve: Box::new(f),
+            asked: HashSet::new(),
+        });
+        Ok(self)
+    }
+
+    /// write headers included by `src` and served by the include resolver.
+    fn resolve_includes(&mut self, src: &[u8]) -> Result<(), TccError> {
+        let includes = match self.includes.as_mut() {
+            Some(includes) => includes,
+            None => return Ok(()),
+        };
+        let mut pending = scan_includes(src);
+        while let Some((name, system)) = pending.pop() {
+            let path = Path::new(&name);
+            if path.is_absolute()
+                || path.components().any(|c| c == Component::ParentDir)
+                || !includes.asked.insert(name.clone())
+            {
+                continue;
+            }
+            let c_name = match CString::new(name.as_str()) {
+                Ok(c_name) => c_name,
+                Err(_) => continue,
+            };
+            if let Some(header) = (includes.resolve)(&c_name, system) {
+                let file = includes.dir.join(path);
+                if let Some(parent) = file.parent() {
+                    fs::create_dir_all(parent).map_err(io_error)?;
+                }
+                fs::write(&file, &header).map_err(io_error)?;
+                pending.extend(scan_includes(&header));
+            }
         }
+        Ok(())
+    }
+
+    /// resolve symbols the compiled code references but does not define by `f`, e.g. to
+    /// bind host functions lazily instead of adding them all by [`add_symbol`](Context::add_symbol).
+    ///
+    /// tcc has no such hook, so [`relocate`](Context::relocate) calls `f` for each of them
+    /// before tcc resolves them from libraries, i.e. also for libc functions. Return None
+    /// to leave a symbol to tcc, which fails relocation if it can not find it either.
+    pub fn set_symbol_resolver<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnMut(&CStr) -> Option<*const c_void> + 'b,
+    {
+        self.resolver = Some(Box::new(f));
         self
     }
 
-    /// undefine preprocess symbol 'sym'
-    pub fn undefine_symbol(&mut self, sym: &CStr) -> &mut Self {
-        unsafe { tcc_undefine_symbol(self.inner, sym.as_ptr()) }
+    /// rewrite C sources by `f` before tcc sees them, e.g. for a textual macro pass.
+    ///
+    /// Applies to [`compile_string`](Context::compile_string), and to C files added by
+    /// [`add_file`](Context::add_file), which are then read and compiled as string named
+    /// by the path, so their `"name"` includes are searched in the working directory
+    /// rather than the file's. Line numbers of diagnostics refer to the transformed
+    /// source, unless `f` inserts `#line`. Transformed source must not contain nul byte.
+    pub fn set_source_transform<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnMut(&[u8]) -> Vec<u8> + 'b,
+    {
+        self.transform = Some(Box::new(f));
         self
     }
 
-    /// output an executable, library or object file. DO NOT call tcc_relocate() before
-    pub fn set_output_type(&mut self, output: OutputType) -> &mut Self {
-        let ret = unsafe { tcc_set_output_type(self.inner, output as c_int) };
-        assert_eq!(ret, 0);
+    /// collect `#pragma name ...` lines of sources, see
+    /// [`collected_pragmas`](Context::collected_pragmas), and remove them before tcc
+    /// sees them if `strip`, e.g. to carry metadata tcc would warn about.
+    ///
+    /// Sources are scanned after the [source transform](Context::set_source_transform),
+    /// and likewise only those of [`compile_string`](Context::compile_string) and C files
+    /// of [`add_file`](Context::add_file), not headers. The scan is textual: a pragma
+    /// must be on one line, `_Pragma` is not recognized, and pragmas in comments or
+    /// disabled `#if` branches are collected as well. Stripped lines are left empty,
+    /// so line numbers are kept.
+    pub fn recognize_pragma(&mut self, name: &str, strip: bool) -> &mut Self {
+        match self.pragmas.iter_mut().find(|(n, _)| n == name) {
+            Some(entry) => entry.1 = strip,
+            None => self.pragmas.push((name.to_string(), strip)),
+        }
         self
     }
 
-    /// add a file (C file, dll, object, library, ld script).
-    pub fn add_file<T: AsRef<Path>>(&mut self, file: T) -> Result<(), ()> {
-        let file = to_cstr(file);
-        let ret = unsafe { tcc_add_file(self.inner, file.as_ptr()) };
-        map_c_ret(ret)
+    /// pragmas recognized so far, in order of compilation, see
+    /// [`recognize_pragma`](Context::recognize_pragma).
+    pub fn collected_pragmas(&self) -> &[Pragma] {
+        &self.collected
     }
 
-    ///  compile a string containing a C source.
-    pub fn compile_string(&mut self, p: &CStr) -> Result<(), ()> {
-        let ret = unsafe { tcc_compile_string(self.inner, p.as_ptr()) };
-        map_c_ret(ret)
+    /// apply the source transform and strip pragmas if any, or return None.
+    fn transform_source(&mut self, src: &[u8]) -> Result<Option<CString>, TccError> {
+        let mut out = self.transform.as_mut().map(|f| f(src));
+        if !self.pragmas.is_empty() {
+            let scanned = out.as_deref().unwrap_or(src);
+            if let Some(stripped) = pragma::scan(scanned, &self.pragmas, &mut self.collected) {
+                out = Some(stripped);
+            }
+        }
+        out.map(CString::new).transpose().map_err(|_| {
+            TccError::new(
+                ErrorKind::InvalidInput,
+                "transformed source contains nul byte",
+            )
+        })
     }
 
-    /// Equivalent to -Lpath option.
-    pub fn add_library_path<T: AsRef<Path>>(&mut self, path: T) -> &mut Self {
-        let path = to_cstr(path);
-        let ret = unsafe { tcc_add_library_path(self.inner, path.as_ptr()) };
-        assert_eq!(ret, 0);
+    /// set visibility of global symbols the context defines without visibility attribute,
+    /// e.g. to keep helpers of plugins loaded together from binding to each other.
+    ///
+    /// tcc has no -fvisibility, so symbols of default visibility are made hidden when
+    /// linking, i.e. by [`relocate`](Context::relocate) and
+    /// [`output_file`](Context::output_file), including those of added objects. tcc
+    /// can not tell `visibility("default")` from no attribute, mark exported symbols
+    /// `__attribute__((visibility("protected")))` instead.
+    ///
+    /// tcc 0.9.27 exports every global symbol of a dll, so for [`OutputType::Dll`]
+    /// hidden symbols are made local, as a linker does. In memory, they are only left
+    /// out of [`RelocatedCtx::symbols`], and [`get_symbol`](RelocatedCtx::get_symbol)
+    /// still finds them.
+    pub fn set_default_visibility(&mut self, vis: Visibility) -> &mut Self {
+        self.visibility = vis;
+        self.settings.push(Setting::DefaultVisibility(vis));
         self
     }
 
-    /// The library name is the same as the argument of the '-l' option.
-    pub fn add_library(&mut self, lib_name: &CStr) -> Result<(), ()> {
-        let ret = unsafe { tcc_add_library(self.inner, lib_name.as_ptr()) };
-        map_c_ret(ret)
+    /// set verbosity of tcc, same as -v, -vv and -vvv, 0 to turn it off.
+    ///
+    /// Messages are passed to the [call back](Context::set_call_back) like warnings,
+    /// but not included in errors:
+    /// - 1: files written, e.g. `<- a.out`.
+    /// - 2: also files read, sources, headers and libraries, e.g. `-> /usr/include/stdio.h`,
+    ///   indented by include depth.
+    /// - 3: also paths tried without success, e.g. `nf /usr/local/include/stdio.h`.
+    ///
+    /// Levels above 3 are the same as 3.
+    ///
+    /// tcc prints them to stdout, which is captured by redirecting file descriptor 1
+    /// during each call into tcc. The descriptor is process-wide, so anything other
+    /// threads write to stdout meanwhile is captured, and reported, as well.
+    pub fn set_verbosity(&mut self, level: u8) -> &mut Self {
+        let level = level.min(3);
+        unsafe { tcc_ext_set_verbose(self.inner, level as c_int) };
+        self.verbosity = level;
+        self.settings.push(Setting::Verbosity(level));
+        self
     }
 
-    /// Add a symbol to the compiled program.
+    /// append errors/warnings to file `path` as they arrive, replacing the callback.
     ///
-    /// # Safety
-    /// Symbol need satisfy ABI requirement.
-    pub unsafe fn add_symbol(&mut self, sym: &CStr, val: *const c_void) {
-        let ret = tcc_add_symbol(self.inner, sym.as_ptr(), val);
-        assert_eq!(ret, 0);
+    /// Each message is written as a line, the file is flushed and closed when the
+    /// context is dropped or relocated.
+    pub fn set_diagnostic_file<T: AsRef<Path>>(&mut self, path: T) -> Result<&mut Self, TccError> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(io_error)?;
+        let mut out = io::LineWriter::new(file);
+        Ok(self.set_call_back(move |msg| {
+            let _ = out.write_all(msg.to_bytes());
+            let _ = out.write_all(b"\n");
+        }))
     }
 
-    /// output an executable, library or object file.
-    pub fn output_file<T: AsRef<Path>>(self, file_name: T) -> Result<(), ()> {
-        let file_name = to_cstr(file_name);
-        let ret = unsafe { tcc_output_file(self.inner, file_name.as_ptr()) };
+    /// resolve relative paths passed to this context against `dir` instead of the
+    /// working directory, e.g. in a threaded server that can not change it.
+    ///
+    /// Applies to files added by [`add_file`](Context::add_file) and
+    /// [`add_source_dir`](Context::add_source_dir), include and library paths, and
+    /// output files, which are joined to `dir` before tcc sees them. Paths within
+    /// [`set_options`](Context::set_options), and `"name"` includes of
+    /// [`compile_string`](Context::compile_string) sources, still resolve against the
+    /// working directory. Return Err of [`ErrorKind::InvalidInput`] if `dir` is relative.
+    pub fn set_base_dir<T: AsRef<Path>>(&mut self, dir: T) -> Result<&mut Self, TccError> {
+        let dir = dir.as_ref();
+        if dir.is_relative() {
+            return Err(TccError::new(
+                ErrorKind::InvalidInput,
+                format!("base dir '{}' is not absolute", dir.display()),
+            ));
+        }
+        self.base_dir = Some(dir.to_path_buf());
+        Ok(self)
+    }
 
-        map_c_ret(ret)
+    /// dir set by [`set_base_dir`](Context::set_base_dir), None if relative paths
+    /// resolve against the working directory.
+    pub fn base_dir(&self) -> Option<&Path> {
+        self.base_dir.as_deref()
     }
 
-    /// do all relocations (needed before get symbol)
-    pub fn relocate(mut self) -> Result<RelocatedCtx, ()> {
-        // pass null ptr to get required length
-        let len = unsafe { tcc_relocate(self.inner, null_mut()) };
-        if len == -1 {
-            return Err(());
-        };
-        let mut bin = Vec::with_capacity(len as usize);
-        let ret = unsafe { tcc_relocate(self.inner, bin.as_mut_ptr() as *mut c_void) };
-        if ret != 0 {
-            return Err(());
+    /// Join `path` to the base dir if it is relative and one is set.
+    fn in_base_dir<T: AsRef<Path>>(&self, path: T) -> PathBuf {
+        match self.base_dir {
+            Some(ref dir) => dir.join(path),
+            None => path.as_ref().to_path_buf(),
         }
-        unsafe {
-            bin.set_len(len as usize);
+    }
+
+    /// write a makefile rule of the files read to `path` with each output file, like
+    /// `-MD -MF` of gcc, to integrate with make or ninja.
+    ///
+    /// The rule is written by [`output_file`](Context::output_file) and its variants,
+    /// with the output as target. tcc lists sources and headers added, and libraries
+    /// and crt objects linked, but not string sources. Read it back by
+    /// [`read_deps`](Context::read_deps).
+    pub fn set_dep_output<T: AsRef<Path>>(&mut self, path: T) -> &mut Self {
+        self.dep_output = Some(self.in_base_dir(path));
+        self
+    }
+
+    /// files a makefile rule at `path` depends on, e.g. written by
+    /// [`set_dep_output`](Context::set_dep_output) or gcc `-MD`.
+    ///
+    /// Prerequisites of every rule are returned in order, without duplicates, with
+    /// `\ ` and `$$` unescaped. Return Err of [`ErrorKind::Io`] if `path` can not be read,
+    /// and of [`ErrorKind::InvalidInput`] if a rule has no `:`.
+    pub fn read_deps<T: AsRef<Path>>(path: T) -> Result<Vec<PathBuf>, TccError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| {
+            TccError::new(
+                ErrorKind::Io,
+                format!("can not read '{}': {}", path.display(), e),
+            )
+        })?;
+        let mut deps: Vec<PathBuf> = Vec::new();
+        for rule in text.replace("\\\r\n", " ").replace("\\\n", " ").lines() {
+            if rule.trim().is_empty() {
+                continue;
+            }
+            let colon = find_rule_colon(rule).ok_or_else(|| {
+                TccError::new(
+                    ErrorKind::InvalidInput,
+                    format!("{}: rule without ':': {}", path.display(), rule),
+                )
+            })?;
+            for dep in split_make_words(&rule[colon + 1..]) {
+                let dep = PathBuf::from(dep);
+                if !deps.contains(&dep) {
+                    deps.push(dep);
+                }
+            }
         }
-        let tcc_handle = self.inner;
-        self.inner = null_mut();
+        Ok(deps)
+    }
 
-        Ok(RelocatedCtx {
-            inner: tcc_handle,
-            _bin: bin,
-            phantom: PhantomData,
+    /// Write the dep file set by `set_dep_output` for `target`.
+    fn write_deps(&self, target: &CStr) -> Result<(), TccError> {
+        let path = match self.dep_output {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let mut deps: Vec<CString> = Vec::new();
+        unsafe {
+            tcc_ext_list_deps(
+                self.inner,
+                &mut deps as *mut _ as *mut c_void,
+                Some(push_dep),
+            )
+        };
+        let mut out = format!("{}: \\\n", escape_make(&target.to_string_lossy()));
+        let mut seen = HashSet::new();
+        for dep in &deps {
+            if seen.insert(dep) {
+                out.push_str(&format!(" {} \\\n", escape_make(&dep.to_string_lossy())));
+            }
+        }
+        out.push('\n');
+        fs::write(path, out).map_err(|e| {
+            TccError::new(
+                ErrorKind::Io,
+                format!("can not write '{}': {}", path.display(), e),
+            )
         })
     }
-}
 
-#[cfg(target_family = "unix")]
-fn to_cstr<T: AsRef<Path>>(p: T) -> CString {
-    use std::os::unix::ffi::OsStrExt;
-    CString::new(p.as_ref().as_os_str().as_bytes()).unwrap()
-}
+    /// add include path
+    pub fn add_include_path<T: AsRef<Path>>(&mut self, path: T) -> Result<&mut Self, TccError> {
+        let path = to_cstr(self.in_base_dir(path))?;
+        // this api only returns 0.
+        unsafe { tcc_add_include_path(self.inner, path.as_ptr()) };
+        self.settings.push(Setting::IncludePath(from_cstr(&path)));
+        Ok(self)
+    }
 
-#[cfg(target_family = "windows")]
-fn to_cstr<T: AsRef<Path>>(p: T) -> CString {
-    CString::new(p.as_ref().to_string_lossy().to_string().as_bytes()).unwrap()
-}
+    /// add include path searched before those already added, e.g. to shadow a header
+    /// by a patched copy.
+    ///
+    /// Headers denied by [`deny_includes`](Context::deny_includes) are still denied.
+    pub fn prepend_include_path<T: AsRef<Path>>(&mut self, path: T) -> Result<&mut Self, TccError> {
+        let path = to_cstr(self.in_base_dir(path))?;
+        // keep the denied and resolved headers first.
+        let index = self.deny_dir.is_some() as c_int + self.includes.is_some() as c_int;
+        unsafe { tcc_ext_insert_include_path(self.inner, path.as_ptr(), index) };
+        self.settings
+            .push(Setting::PrependIncludePath(from_cstr(&path)));
+        Ok(self)
+    }
 
-// preprocessor
-impl<'a, 'b> Drop for Context<'a, 'b> {
-    fn drop(&mut self) {
-        if !self.inner.is_null() {
-            unsafe { tcc_delete(self.inner) }
-        }
+    /// add in system include path
+    pub fn add_sys_include_path<T: AsRef<Path>>(&mut self, path: T) -> Result<&mut Self, TccError> {
+        let path = to_cstr(self.in_base_dir(path))?;
+        // this api only returns 0.
+        unsafe { tcc_add_sysinclude_path(self.inner, path.as_ptr()) };
+        self.settings
+            .push(Setting::SysIncludePath(from_cstr(&path)));
+        Ok(self)
     }
-}
 
-fn map_c_ret(code: c_int) -> Result<(), ()> {
-    if code == 0 {
-        Ok(())
-    } else {
-        Err(())
+    /// Equivalent to --sysroot option, search headers, libraries and crt objects
+    /// under `path` instead of the host's.
+    ///
+    /// Headers of the host are no longer searched, libraries and crt objects of the
+    /// sysroot take precedence over the host's. Must be called before
+    /// [`set_output_type`](Context::set_output_type), return Err if `path` is not a directory.
+    pub fn set_sysroot<T: AsRef<Path>>(&mut self, path: T) -> Result<&mut Self, TccError> {
+        let dir = path.as_ref();
+        if self.output.is_some() {
+            return Err(TccError::new(
+                ErrorKind::InvalidInput,
+                "sysroot must be set before output type",
+            ));
+        }
+        if !dir.is_dir() {
+            return Err(TccError::new(
+                ErrorKind::Io,
+                format!("sysroot '{}' is not a directory", dir.display()),
+            ));
+        }
+        let dir = to_cstr(dir)?;
+        unsafe { tcc_ext_set_sysroot(self.inner, dir.as_ptr()) };
+        self.settings.push(Setting::Sysroot(from_cstr(&dir)));
+        Ok(self)
     }
-}
 
-/// Relocated compilation context
-pub struct RelocatedCtx {
-    inner: *mut TCCState,
-    _bin: Vec<u8>,
-    phantom: PhantomData<TCCState>,
-}
+    /// compile against headers of `libc`, and link its libraries and crt objects before
+    /// those of the host.
+    ///
+    /// Headers and libraries are looked up where distributions install them, e.g.
+    /// `/usr/include/x86_64-linux-musl` of musl-dev or `/usr/local/musl` of musl-gcc,
+    /// so the toolchain of `libc` must be installed. Only tcc's own headers are searched
+    /// besides, like [`set_sysroot`](Context::set_sysroot). Return Err of
+    /// [`ErrorKind::Io`] listing the missing files if none is complete. Must be called
+    /// before [`set_output_type`](Context::set_output_type).
+    ///
+    /// This is meant for object files, libraries and executables. Executables still
+    /// name the dynamic loader tcc is configured with, and relocating in memory loads
+    /// `libc` into this process, which can not mix with another libc.
+    pub fn use_libc(&mut self, libc: Libc) -> Result<&mut Self, TccError> {
+        if self.output.is_some() {
+            return Err(TccError::new(
+                ErrorKind::InvalidInput,
+                "libc must be set before output type",
+            ));
+        }
+        let (includes, lib) = find_libc(libc)?;
+        let joined = env::join_paths(&includes).map_err(|e| {
+            TccError::new(
+                ErrorKind::InvalidInput,
+                format!("invalid include dir: {}", e),
+            )
+        })?;
+        let joined = to_cstr(PathBuf::from(joined))?;
+        let lib_c = to_cstr(&lib)?;
+        unsafe { tcc_ext_set_libc(self.inner, joined.as_ptr(), lib_c.as_ptr()) };
+        self.settings.push(Setting::Libc(libc, includes, lib));
+        Ok(self)
+    }
 
-impl RelocatedCtx {
-    /// return symbol value or None if not found
+    /// return path of the header that `#include <name>`, or `#include "name"` if not
+    /// `system`, would pick up, or None if it is not found.
     ///
-    /// # Safety
-    /// Returned addr can not outlive RelocatedCtx itself. It's caller's
-    /// responsibility to take care of validity of addr.
-    pub unsafe fn get_symbol(&mut self, sym: &CStr) -> Option<*mut c_void> {
-        let addr = tcc_get_symbol(self.inner, sym.as_ptr());
-        if addr.is_null() {
-            None
+    /// Search order is the same as tcc's: absolute path, then the current dir, which is
+    /// the dir of a string source, for `"name"` only, then include paths and system
+    /// include paths.
+    /// Default system include paths are only searched once an output type is
+    /// [set](Context::set_output_type), which then takes effect.
+    pub fn resolve_include(&self, name: &CStr, system: bool) -> Option<PathBuf> {
+        self.apply_output_type();
+        let mut buf = [0 as c_char; 1024];
+        let ret = unsafe {
+            tcc_ext_resolve_include(
+                self.inner,
+                name.as_ptr(),
+                system as c_int,
+                buf.as_mut_ptr(),
+                buf.len() as c_int,
+            )
+        };
+        if ret == 0 {
+            Some(from_cstr(unsafe { CStr::from_ptr(buf.as_ptr()) }))
         } else {
-            Some(addr)
+            None
         }
     }
-}
 
-impl Drop for RelocatedCtx {
-    fn drop(&mut self) {
-        unsafe { tcc_delete(self.inner) }
+    /// fail compilation of sources nesting includes deeper than `depth`, e.g. a header
+    /// including itself.
+    ///
+    /// tcc always fails beyond 32 levels, which is the largest `depth` accepted. A lower
+    /// limit is checked by [`compile_string`](Context::compile_string) before tcc
+    /// sees the source: includes are followed as tcc would find them, skipping headers
+    /// already entered that have an include guard or `#pragma once`. This is
+    /// conservative, includes in disabled `#if` branches and those found in the
+    /// current dir count as well.
+    pub fn set_max_include_depth(&mut self, depth: u32) -> Result<&mut Self, TccError> {
+        if depth == 0 || depth > MAX_INCLUDE_DEPTH {
+            return Err(TccError::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "include depth must be within 1 and {}, not {}",
+                    MAX_INCLUDE_DEPTH, depth
+                ),
+            ));
+        }
+        self.max_include_depth = Some(depth);
+        Ok(self)
+    }
+
+    /// check that includes of `src`, in `dir` if it is a file, nest no deeper than the limit.
+    fn check_include_depth(
+        &self,
+        src: &[u8],
+        dir: Option<&Path>,
+        depth: u32,
+        entered: &mut HashSet<PathBuf>,
+    ) -> Result<(), TccError> {
+        let max = match self.max_include_depth {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+        for (name, system) in scan_includes(src) {
+            let c_name = match CString::new(name.as_str()) {
+                Ok(c_name) => c_name,
+                Err(_) => continue,
+            };
+            let path = match dir {
+                Some(dir) if !system && dir.join(&name).is_file() => Some(dir.join(&name)),
+                // the current dir is only searched from a string source.
+                Some(_) => self.resolve_include(&c_name, true),
+                None => self.resolve_include(&c_name, system),
+            };
+            // not found is reported by tcc.
+            let path = match path {
+                Some(path) if !entered.contains(&path) => path,
+                _ => continue,
+            };
+            if depth >= max {
+                return Err(TccError::new(
+                    ErrorKind::Compile,
+                    format!("include of '{}' nested deeper than {}", path.display(), max),
+                ));
+            }
+            let header = match fs::read(&path) {
+                Ok(header) => header,
+                Err(_) => continue,
+            };
+            if has_include_guard(&header) {
+                entered.insert(path.clone());
+            }
+            self.check_include_depth(&header, path.parent(), depth + 1, entered)?;
+        }
+        Ok(())
+    }
+
+    /// make `#include` of any of `names` fail compilation, e.g. to keep snippets from
+    /// doing I/O by denying `stdio.h`.
+    ///
+    /// Names are matched as written in the include directive, for both `<name>` and
+    /// `"name"`, wherever the include appears. Each name is shadowed by a header
+    /// reporting `include of 'name' is denied`, which is searched before all the
+    /// include paths. Includes by absolute path, and of headers in the directory of
+    /// the including file, are not covered.
+    pub fn deny_includes(&mut self, names: &[&str]) -> Result<&mut Self, TccError> {
+        for name in names {
+            let path = Path::new(name);
+            if name.is_empty()
+                || name.contains('\0')
+                || path.is_absolute()
+                || path.components().any(|c| c == Component::ParentDir)
+            {
+                return Err(TccError::new(
+                    ErrorKind::InvalidInput,
+                    format!("can not deny include of '{}'", name),
+                ));
+            }
+        }
+        let dir = match &self.deny_dir {
+            Some(dir) => dir.clone(),
+            None => {
+                let dir = temp_include_dir("deny")?;
+                let c_dir = to_cstr(&dir)?;
+                unsafe { tcc_ext_insert_include_path(self.inner, c_dir.as_ptr(), 0) };
+                self.settings
+                    .push(Setting::DenyIncludes(dir.clone(), Vec::new()));
+                self.deny_dir = Some(dir.clone());
+                dir
+            }
+        };
+        for name in names {
+            let header = dir.join(name);
+            if let Some(parent) = header.parent() {
+                fs::create_dir_all(parent).map_err(io_error)?;
+            }
+            fs::write(&header, format!("#error include of '{}' is denied\n", name))
+                .map_err(io_error)?;
+        }
+        for setting in &mut self.settings {
+            if let Setting::DenyIncludes(_, denied) = setting {
+                denied.extend(names.iter().map(|n| n.to_string()));
+            }
+        }
+        Ok(self)
+    }
+
+    /// define preprocessor symbol 'sym'. Can put optional value
+    ///
+    /// Return Err if tcc can not parse the definition, e.g. 'sym' is not an identifier.
+    pub fn define_symbol(&mut self, sym: &CStr, val: &CStr) -> Result<&mut Self, TccError> {
+        self.check(ErrorKind::InvalidInput, |s| unsafe {
+            tcc_ext_define_symbol(s, sym.as_ptr(), val.as_ptr())
+        })?;
+        self.settings
+            .push(Setting::Define(sym.to_owned(), val.to_owned()));
+        Ok(self)
+    }
+
+    /// define function-like macro, e.g. `SQ(x)` as `((x) * (x))`.
+    ///
+    /// `signature` is the name immediately followed by the parenthesized parameters,
+    /// `body` is taken as is, so parentheses and commas need no escaping, unlike on
+    /// a shell command line. Return Err if `signature` is not of that form, or tcc
+    /// can not parse it.
+    pub fn define_macro(&mut self, signature: &CStr, body: &CStr) -> Result<&mut Self, TccError> {
+        let sig = signature.to_bytes();
+        let name_len = sig
+            .iter()
+            .position(|&c| c == b'(')
+            .filter(|&i| i > 0 && sig.ends_with(b")"));
+        match name_len {
+            Some(i)
+                if sig[..i]
+                    .iter()
+                    .all(|&c| c.is_ascii_alphanumeric() || c == b'_') =>
+            {
+                self.define_symbol(signature, body)
+            }
+            _ => Err(TccError::new(
+                ErrorKind::InvalidInput,
+                format!("{:?} is not a macro signature", signature),
+            )),
+        }
+    }
+
+    /// define preprocessor symbol 'sym' to the formatted `val`, e.g. a computed constant.
+    ///
+    /// Return Err if formatted `val` contains nul byte.
+    pub fn define_display<T: Display + ?Sized>(
+        &mut self,
+        sym: &CStr,
+        val: &T,
+    ) -> Result<&mut Self, TccError> {
+        let val = CString::new(val.to_string())
+            .map_err(|_| TccError::new(ErrorKind::InvalidInput, "value contains nul byte"))?;
+        self.define_symbol(sym, &val)
+    }
+
+    /// undefine preprocess symbol 'sym'
+    pub fn undefine_symbol(&mut self, sym: &CStr) -> &mut Self {
+        unsafe { tcc_undefine_symbol(self.inner, sym.as_ptr()) }
+        self.settings.push(Setting::Undefine(sym.to_owned()));
+        self
+    }
+
+    /// list active macros and their values, like `gcc -dM -E` with an empty input.
+    ///
+    /// Macros defined by [`define_symbol`](Context::define_symbol) are listed as well.
+    /// Function-like macros keep their parameters in the name, e.g. `MAX(a,b)`,
+    /// and the value of a macro defined without one is empty.
+    pub fn predefined_macros(&self) -> Vec<(CString, CString)> {
+        let out = self
+            .preprocess_raw(&CString::default(), DFLAG_DUMP_MACROS)
+            .unwrap_or_default();
+        out.split(|&c| c == b'\n')
+            .filter_map(parse_define)
+            .collect()
+    }
+
+    /// write [`predefined_macros`](Context::predefined_macros) to `path` as `#define` lines,
+    /// to restore them by [`load_defines`](Context::load_defines).
+    pub fn dump_defines<T: AsRef<Path>>(&self, path: T) -> Result<(), TccError> {
+        let mut out = Vec::new();
+        for (name, val) in self.predefined_macros() {
+            out.extend_from_slice(b"#define ");
+            out.extend_from_slice(name.to_bytes());
+            if !val.to_bytes().is_empty() {
+                out.push(b' ');
+                out.extend_from_slice(val.to_bytes());
+            }
+            out.push(b'\n');
+        }
+        fs::write(path, out).map_err(io_error)
+    }
+
+    /// define the macros of `path` written by [`dump_defines`](Context::dump_defines), by
+    /// [`define_symbol`](Context::define_symbol).
+    ///
+    /// Blank lines are skipped, Err of [`ErrorKind::InvalidInput`] names the first line
+    /// that is not a `#define`, or is rejected by tcc. Macros before it stay defined.
+    pub fn load_defines<T: AsRef<Path>>(&mut self, path: T) -> Result<&mut Self, TccError> {
+        let path = path.as_ref();
+        let defines = fs::read(path).map_err(io_error)?;
+        for (i, line) in defines.split(|&c| c == b'\n').enumerate() {
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            let invalid = |msg: &str| {
+                let msg = format!("{}:{}: {}", path.display(), i + 1, msg);
+                TccError::new(ErrorKind::InvalidInput, msg)
+            };
+            let (name, val) = parse_define(line).ok_or_else(|| invalid("not a #define"))?;
+            self.define_symbol(&name, &val)
+                .map_err(|e| invalid(e.message()))?;
+        }
+        Ok(self)
+    }
+
+    /// preprocess `src`, and write the output to `w` chunk by chunk, e.g. straight
+    /// to a file.
+    ///
+    /// The output is buffered in a temporary file by tcc, and nothing is written if
+    /// preprocessing fails. Return Err of [`ErrorKind::Io`] if writing to `w` fails,
+    /// and of [`ErrorKind::InvalidInput`] if `src` contains nul byte.
+    pub fn preprocess_to_writer<W: Write>(
+        &mut self,
+        src: &[u8],
+        w: &mut W,
+    ) -> Result<(), TccError> {
+        let src = CString::new(src)
+            .map_err(|_| TccError::new(ErrorKind::InvalidInput, "source contains nul byte"))?;
+        self.apply_output_type();
+        self.resolve_includes(src.to_bytes())?;
+        self.report_phase(Phase::Preprocess);
+        let mut sink: (&mut dyn Write, io::Result<()>) = (w, Ok(()));
+        self.check(ErrorKind::Compile, |s| unsafe {
+            tcc_ext_preprocess(
+                s,
+                src.as_ptr(),
+                0,
+                &mut sink as *mut _ as *mut c_void,
+                Some(write_sink),
+            )
+        })?;
+        sink.1.map_err(io_error)
+    }
+
+    /// preprocess `src`, and report which macros are expanded on each line.
+    ///
+    /// tcc has no expansion trace, so this is reconstructed from the output of
+    /// preprocessing and the macros defined at its end. Granularity is a source line:
+    /// the macros used on it, including those reached through other macros, and the
+    /// line after expansion. Directives, e.g. `#if`, and macros redefined or undefined
+    /// midway are not traced.
+    pub fn preprocess_verbose(&mut self, src: &CStr) -> Result<Vec<Expansion>, TccError> {
+        self.apply_output_type();
+        self.resolve_includes(src.to_bytes())?;
+        self.report_phase(Phase::Preprocess);
+        self.diag.msgs.clear();
+        let out = self
+            .preprocess_raw(src, 0)
+            .map_err(|_| TccError::from_diagnostics(ErrorKind::Compile, &self.diag.msgs))?;
+        let defines = self
+            .preprocess_raw(src, DFLAG_DUMP_MACROS)
+            .unwrap_or_default();
+        Ok(expand::trace(
+            &src.to_string_lossy(),
+            &String::from_utf8_lossy(&defines),
+            &String::from_utf8_lossy(&out),
+        ))
+    }
+
+    fn preprocess_raw(&self, src: &CStr, dflag: c_int) -> Result<Vec<u8>, ()> {
+        self.apply_output_type();
+        let mut out: Vec<u8> = Vec::new();
+        let ret = unsafe {
+            tcc_ext_preprocess(
+                self.inner,
+                src.as_ptr(),
+                dflag,
+                &mut out as *mut _ as *mut c_void,
+                Some(write_buf),
+            )
+        };
+        if ret == 0 {
+            Ok(out)
+        } else {
+            Err(())
+        }
+    }
+
+    /// output an executable, library or object file. DO NOT call tcc_relocate() before
+    ///
+    /// [`OutputType::Memory`] is the default, set by the first compilation if no type is
+    /// set by then. The type takes effect at the first compilation, preprocessing or
+    /// [`resolve_include`](Context::resolve_include), until then it can be changed
+    /// freely. Setting the same type again is a no-op, changing it afterwards is an error.
+    pub fn set_output_type(&mut self, output: OutputType) -> Result<&mut Self, TccError> {
+        match self.output {
+            Some(prev) if prev == output => return Ok(self),
+            Some(prev) if self.output_applied.get() => {
+                let msg = format!(
+                    "can not change output type from {:?} to {:?} after compilation started",
+                    prev, output
+                );
+                return Err(TccError::new(ErrorKind::InvalidInput, msg));
+            }
+            _ => (),
+        }
+        self.output = Some(output);
+        self.settings.push(Setting::OutputType(output));
+        Ok(self)
+    }
+
+    /// pass the output type to tcc, once, if one is set.
+    ///
+    /// Every call of tcc_set_output_type adds the default paths again, and for Exe and
+    /// Dll the crt objects, which stay in the state whatever type follows.
+    fn apply_output_type(&self) {
+        if let Some(output) = self.output {
+            if !self.output_applied.replace(true) {
+                // this api only returns 0.
+                unsafe { tcc_set_output_type(self.inner, output as c_int) };
+            }
+        }
+    }
+
+    /// equivalent tcc command line of the settings applied so far, in order of application.
+    ///
+    /// Covers lib path, options, include and library paths, defines and output type,
+    /// including the default one, but not the input files. Useful to reproduce a
+    /// compilation with tcc itself.
+    pub fn effective_options(&self) -> String {
+        self.config().effective_options()
+    }
+
+    /// snapshot of the configuration applied so far, to create contexts configured the
+    /// same by [`ContextBuilder::from_context_config`].
+    pub fn config(&self) -> ContextConfig {
+        ContextConfig {
+            settings: self.settings.clone(),
+        }
+    }
+
+    /// C standard the sources are compiled with, which is always [`CStd::C99`].
+    ///
+    /// tcc 0.9.27 accepts `-std=` options but ignores them. It implements most of C99,
+    /// except complex types, plus `_Generic` of C11 and many GNU extensions, e.g.
+    /// `typeof` and statement expressions. Other C11 keywords like `_Static_assert`,
+    /// `_Alignas` and `_Noreturn` are not supported.
+    pub fn c_std(&self) -> CStd {
+        CStd::C99
+    }
+
+    /// output type set by [`set_output_type`](Context::set_output_type), None if it is
+    /// not set yet, not even to the default.
+    pub fn output_type(&self) -> Option<OutputType> {
+        self.output
+    }
+
+    /// set the default output type if none is set.
+    fn default_output_type(&mut self) -> Result<(), TccError> {
+        if self.output.is_none() {
+            self.set_output_type(OutputType::Memory)?;
+        }
+        self.apply_output_type();
+        Ok(())
+    }
+
+    /// add a file (C file, dll, object, library, ld script).
+    ///
+    /// `file` is passed to tcc as is, so a relative path is resolved against the
+    /// working directory of the moment, see [`add_file_abs`](Context::add_file_abs),
+    /// unless a [base dir](Context::set_base_dir) is set.
+    pub fn add_file<T: AsRef<Path>>(&mut self, file: T) -> Result<(), TccError> {
+        let path = &self.in_base_dir(file);
+        let scan = self.transform.is_some() || !self.pragmas.is_empty();
+        if scan && path.extension().is_some_and(|e| e == "c") {
+            let src = fs::read(path).map_err(|e| {
+                TccError::new(
+                    ErrorKind::Io,
+                    format!("can not read '{}': {}", path.display(), e),
+                )
+            })?;
+            if let Some(src) = self.transform_source(&src)? {
+                return self.compile_source(&with_line_directive(&to_cstr(path)?, &src));
+            }
+        }
+        let source = path
+            .extension()
+            .is_some_and(|e| ["c", "i", "s", "S"].iter().any(|ext| e == *ext));
+        let file = to_cstr(path)?;
+        self.default_output_type()?;
+        self.started = true;
+        if source {
+            self.report_phase(Phase::Compile);
+        }
+        self.check(ErrorKind::Link, |s| unsafe {
+            tcc_ext_add_file(s, file.as_ptr())
+        })?;
+        Ok(())
+    }
+
+    /// [`add_file`](Context::add_file) each `.c` file in `dir`, and in its subdirectories
+    /// if `recursive`, returning how many are added.
+    ///
+    /// Files are added in order of their paths, so builds are reproducible. Symlinked
+    /// subdirectories are not followed. Err names the file failing to compile, files
+    /// before it stay added.
+    pub fn add_source_dir<T: AsRef<Path>>(
+        &mut self,
+        dir: T,
+        recursive: bool,
+    ) -> Result<usize, TccError> {
+        let mut files = Vec::new();
+        collect_sources(&self.in_base_dir(dir), recursive, &mut files)?;
+        files.sort();
+        for file in &files {
+            self.add_file(file).map_err(|e| {
+                let msg = format!("fail to add {}: {}", file.display(), e.message());
+                TccError::new(e.kind(), msg)
+            })?;
+        }
+        Ok(files.len())
+    }
+
+    /// same as [`add_file`](Context::add_file), but canonicalize `file` first.
+    ///
+    /// Return Err of [`ErrorKind::Io`] if `file` can not be canonicalized, e.g. it does not exist.
+    pub fn add_file_abs<T: AsRef<Path>>(&mut self, file: T) -> Result<(), TccError> {
+        let file = &self.in_base_dir(file);
+        let abs = fs::canonicalize(file).map_err(|e| {
+            TccError::new(
+                ErrorKind::Io,
+                format!("can not canonicalize '{}': {}", file.display(), e),
+            )
+        })?;
+        self.add_file(abs)
+    }
+
+    /// forget diagnostics collected so far, e.g. between compile units of a batch.
+    ///
+    /// Compiled code and the tcc state are kept. Each operation starts collecting
+    /// anew as well, so an error carries only the diagnostics of the failing one.
+    pub fn clear_diagnostics(&mut self) -> &mut Self {
+        self.diag.msgs.clear();
+        self
+    }
+
+    /// record `name` as the source file in output files, e.g. for byte-identical objects
+    /// built in different directories.
+    ///
+    /// tcc records the name of each source compiled, `<string>` for
+    /// [`compile_string`](Context::compile_string) and the path as given for
+    /// [`add_file`](Context::add_file), as file symbol. With -g, the current directory
+    /// is recorded in debug info as well, it is replaced by `./`. The name applies to all
+    /// sources of the context, and is set by [`output_file`](Context::output_file).
+    /// Diagnostics are not affected, see [`set_input_name`](Context::set_input_name).
+    pub fn set_object_name(&mut self, name: &CStr) -> &mut Self {
+        self.object_name = Some(name.to_owned());
+        self
+    }
+
+    /// set file name used in diagnostics of subsequent `compile_string`, `<string>` by default.
+    ///
+    /// The name applies from the first line, `#line` directives of the source still take
+    /// precedence from where they appear, e.g. to map generated code back to its origin.
+    pub fn set_input_name(&mut self, name: &CStr) -> &mut Self {
+        self.input_name = Some(name.to_owned());
+        self
+    }
+
+    ///  compile a string containing a C source.
+    pub fn compile_string(&mut self, p: &CStr) -> Result<(), TccError> {
+        let transformed = self.transform_source(p.to_bytes())?;
+        let p = transformed.as_deref().unwrap_or(p);
+        let named;
+        let p = match self.input_name {
+            Some(ref name) => {
+                named = with_line_directive(name, p);
+                &named
+            }
+            None => p,
+        };
+        self.compile_source(p)
+    }
+
+    /// compile `p` as is.
+    fn compile_source(&mut self, p: &CStr) -> Result<(), TccError> {
+        self.resolve_includes(p.to_bytes())?;
+        self.default_output_type()?;
+        self.check_include_depth(p.to_bytes(), None, 0, &mut HashSet::new())?;
+        self.started = true;
+        self.report_phase(Phase::Compile);
+        self.check(ErrorKind::Compile, |s| unsafe {
+            tcc_compile_string(s, p.as_ptr())
+        })?;
+        Ok(())
+    }
+
+    /// assemble a string containing assembly source, like a `.s` file.
+    ///
+    /// The source is not preprocessed, and uses the GNU syntax of the target, e.g.
+    /// AT&T syntax on x86. Return Err of [`ErrorKind::InvalidInput`] if `src` contains
+    /// nul byte.
+    pub fn compile_asm(&mut self, src: &[u8]) -> Result<(), TccError> {
+        let src = CString::new(src)
+            .map_err(|_| TccError::new(ErrorKind::InvalidInput, "source contains nul byte"))?;
+        self.default_output_type()?;
+        self.started = true;
+        self.report_phase(Phase::Compile);
+        self.check(ErrorKind::Compile, |s| unsafe {
+            tcc_ext_compile_asm(s, src.as_ptr())
+        })?;
+        Ok(())
+    }
+
+    /// same as [`compile_string`](Context::compile_string), but return how long it takes.
+    pub fn compile_timed(&mut self, p: &CStr) -> Result<Duration, TccError> {
+        let start = Instant::now();
+        self.compile_string(p)?;
+        Ok(start.elapsed())
+    }
+
+    /// bytes tcc holds for what is compiled so far, e.g. to detect inputs ballooning
+    /// memory of a compile service.
+    ///
+    /// tcc 0.9.27 counts its allocations only in debug builds, so this is the memory of
+    /// its sections: code, data, symbol tables, relocations and debug info, rounded up
+    /// as tcc grows them. It is exact for those, but leaves out what tcc frees after
+    /// each compile, e.g. tokens and macros, and its fixed overhead.
+    pub fn compile_memory_used(&self) -> usize {
+        unsafe { tcc_ext_memory_used(self.inner) }
+    }
+
+    /// Equivalent to -Lpath option.
+    pub fn add_library_path<T: AsRef<Path>>(&mut self, path: T) -> Result<&mut Self, TccError> {
+        let path = to_cstr(self.in_base_dir(path))?;
+        // this api only returns 0.
+        unsafe { tcc_add_library_path(self.inner, path.as_ptr()) };
+        self.settings.push(Setting::LibraryPath(from_cstr(&path)));
+        Ok(self)
+    }
+
+    /// The library name is the same as the argument of the '-l' option.
+    pub fn add_library(&mut self, lib_name: &CStr) -> Result<(), TccError> {
+        self.default_output_type()?;
+        self.check(ErrorKind::Link, |s| unsafe {
+            tcc_add_library(s, lib_name.as_ptr())
+        })?;
+        Ok(())
+    }
+
+    /// Add a symbol to the compiled program.
+    ///
+    /// Symbols are resolved during [`relocate`](Context::relocate), so this can be
+    /// called at any point before it, either before or after the `compile_string`
+    /// that references `sym`. Adding a symbol that compiled code already defines
+    /// is reported as "defined twice" through the error callback.
+    ///
+    /// # Safety
+    /// Symbol need satisfy ABI requirement.
+    pub unsafe fn add_symbol(&mut self, sym: &CStr, val: *const c_void) {
+        // this api only returns 0.
+        tcc_add_symbol(self.inner, sym.as_ptr(), val);
+    }
+
+    /// Add all symbols of `table`, e.g. built-in functions exposed to every program.
+    ///
+    /// # Safety
+    /// Each symbol need satisfy ABI requirement, same as [`add_symbol`](Context::add_symbol).
+    pub unsafe fn add_symbols(&mut self, table: &[(&CStr, *const c_void)]) -> &mut Self {
+        for (sym, val) in table {
+            self.add_symbol(sym, *val);
+        }
+        self
+    }
+
+    /// provide libc functions by `shims`, e.g. for code compiled with
+    /// [`no_std_lib`](Context::no_std_lib).
+    ///
+    /// Shims are added as by [`add_symbol`](Context::add_symbol), so they take
+    /// precedence over libc if it is linked as well.
+    pub fn install_libc_shims(&mut self, shims: LibcShims) -> &mut Self {
+        LibcShims::init_output();
+        for (name, val) in &shims.entries {
+            // entries are host functions, or set unsafely by the user.
+            unsafe { self.add_symbol(name, *val) };
+        }
+        self
+    }
+
+    /// add the symbols of `shared`, so this module resolves them to the same addresses
+    /// as every other module they are added to.
+    ///
+    /// Symbols are added as by [`add_symbol`](Context::add_symbol), and `shared` is
+    /// kept with the context and the module it relocates into, so storage of
+    /// [`SharedSymbols::add_global`] outlives them.
+    pub fn add_shared_symbols(&mut self, shared: &SharedSymbols) -> &mut Self {
+        for (name, val) in &shared.entries {
+            // entries are storage of shared, or set unsafely by the user.
+            unsafe { self.add_symbol(name, *val) };
+        }
+        self.shared.push(shared.clone());
+        self
+    }
+
+    /// output an executable, library or object file.
+    pub fn output_file<T: AsRef<Path>>(self, file_name: T) -> Result<(), TccError> {
+        let mut ctx = self;
+        let file_name = to_cstr(ctx.in_base_dir(file_name))?;
+        ctx.default_output_type()?;
+        ctx.emit(&file_name)
+    }
+
+    /// same as [`output_file`](Context::output_file), but write `ty` without consuming
+    /// the context, switching the output type if needed.
+    ///
+    /// tcc 0.9.27 links in place, so a state can be emitted only once, with one
+    /// exception: an object file, which is not linked, can be followed by one more
+    /// output, an executable or library here, or [`relocate`](Context::relocate).
+    /// Anything else after an output is an [`ErrorKind::InvalidInput`], as is
+    /// [`OutputType::Memory`], which has no file.
+    ///
+    /// When switching from an object to an executable or library, tcc adds its crt
+    /// objects, e.g. `crt1.o` and `crti.o`, at the switch, so they are linked after the
+    /// code compiled so far rather than before it as usual. The switch is recorded in
+    /// [`config`](Context::config).
+    pub fn output_file_ref<T: AsRef<Path>>(
+        &mut self,
+        file_name: T,
+        ty: OutputType,
+    ) -> Result<(), TccError> {
+        if ty == OutputType::Memory {
+            return Err(TccError::new(
+                ErrorKind::InvalidInput,
+                "no output file for output type Memory",
+            ));
+        }
+        let file_name = to_cstr(self.in_base_dir(file_name))?;
+        self.check_emitted(ty)?;
+        self.default_output_type()?;
+        if self.output != Some(ty) {
+            self.switch_output_type(ty);
+        }
+        self.emit(&file_name)
+    }
+
+    /// change the output type of a state already compiled, after it is emitted as an
+    /// object, which is not linked, so the code generated so far fits any type.
+    fn switch_output_type(&mut self, ty: OutputType) {
+        // this api only returns 0.
+        unsafe { tcc_set_output_type(self.inner, ty as c_int) };
+        self.output = Some(ty);
+        self.settings.push(Setting::OutputType(ty));
+    }
+
+    /// Write the output of the current type to `file_name`.
+    fn emit(&mut self, file_name: &CStr) -> Result<(), TccError> {
+        let output = self.output.unwrap_or(OutputType::Memory);
+        self.check_emitted(output)?;
+        match self.entry {
+            Some(ref entry) if output == OutputType::Exe && entry.as_bytes() != b"_start" => {
+                let msg = format!("entry of executable can not be {:?}", entry);
+                return Err(TccError::new(ErrorKind::InvalidInput, msg));
+            }
+            _ => (),
+        }
+        self.apply_visibility();
+        self.apply_object_name();
+        match output {
+            OutputType::Obj => (),
+            OutputType::Preprocess => self.report_phase(Phase::Preprocess),
+            _ => self.report_phase(Phase::Link),
+        }
+        self.emitted = Some(output);
+        self.check(ErrorKind::Link, |s| unsafe {
+            tcc_output_file(s, file_name.as_ptr())
+        })?;
+        self.write_deps(file_name)
+    }
+
+    /// Return Err if the state can not be emitted as `ty` after what is emitted so far.
+    fn check_emitted(&self, ty: OutputType) -> Result<(), TccError> {
+        match self.emitted {
+            Some(prev) if prev != OutputType::Obj || ty == OutputType::Obj => {
+                let msg = format!("can not output {:?} after {:?} is written", ty, prev);
+                Err(TccError::new(ErrorKind::InvalidInput, msg))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// compile `src` and write an executable to `out`, in one call.
+    ///
+    /// Output type is set to [`OutputType::Exe`], settings applied so far are kept.
+    /// Return Err of [`ErrorKind::InvalidInput`] if `src` contains nul byte.
+    pub fn build_executable<T: AsRef<Path>>(mut self, src: &[u8], out: T) -> Result<(), TccError> {
+        let src = CString::new(src)
+            .map_err(|_| TccError::new(ErrorKind::InvalidInput, "source contains nul byte"))?;
+        self.set_output_type(OutputType::Exe)?;
+        self.compile_string(&src)?;
+        self.output_file(out)
+    }
+
+    fn apply_visibility(&mut self) {
+        if self.visibility == Visibility::Hidden {
+            let local = self.output == Some(OutputType::Dll);
+            unsafe { tcc_ext_hide_symbols(self.inner, local as c_int) };
+        }
+    }
+
+    fn apply_object_name(&mut self) {
+        if let Some(ref name) = self.object_name {
+            let dir = CStr::from_bytes_with_nul(b"./\0").unwrap();
+            unsafe { tcc_ext_set_file_name(self.inner, dir.as_ptr(), name.as_ptr()) };
+        }
+    }
+
+    /// same as [`output_file`](Context::output_file), but name the file `stem` followed by
+    /// [`default_extension`](OutputType::default_extension) of the output type, and
+    /// return the name.
+    ///
+    /// Extension of `stem`, if any, is kept, e.g. `libfoo.1` becomes `libfoo.1.so`.
+    /// Return Err if the output type is [`OutputType::Memory`], which has no file.
+    pub fn output_file_auto<T: AsRef<Path>>(mut self, stem: T) -> Result<PathBuf, TccError> {
+        self.default_output_type()?;
+        let output = self.output.unwrap_or(OutputType::Memory);
+        if output == OutputType::Memory {
+            return Err(TccError::new(
+                ErrorKind::InvalidInput,
+                "no output file for output type Memory",
+            ));
+        }
+        let mut file = self.in_base_dir(stem).into_os_string();
+        let ext = output.default_extension();
+        if !ext.is_empty() {
+            file.push(".");
+            file.push(ext);
+        }
+        let file = PathBuf::from(file);
+        self.output_file(&file)?;
+        Ok(file)
+    }
+
+    /// output an object file to `obj_out`, then relocate the same compilation in memory.
+    ///
+    /// Sources are parsed only once. Output type must be [`OutputType::Obj`], which is
+    /// switched to [`OutputType::Memory`] for relocation.
+    pub fn compile_dual<T: AsRef<Path>>(
+        mut self,
+        obj_out: T,
+    ) -> Result<RelocatedCtx<'a>, TccError> {
+        if self.output != Some(OutputType::Obj) {
+            return Err(TccError::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "compile_dual requires output type Obj, not {:?}",
+                    self.output
+                ),
+            ));
+        }
+        let obj_out = to_cstr(self.in_base_dir(obj_out))?;
+        self.emit(&obj_out)?;
+        self.switch_output_type(OutputType::Memory);
+        self.relocate()
+    }
+
+    /// build several outputs of `src` in one go, e.g. an object file and a relocation:
+    /// ```
+    /// use libtcc::{Context, Guard};
+    /// let mut g = Guard::new().unwrap();
+    /// let ctx = Context::new(&mut g).unwrap();
+    /// let obj = std::env::temp_dir().join("libtcc_doc_multi.o");
+    /// let (_, relocated) = ctx
+    ///     .multi_output(b"int f(void){ return 1; }")
+    ///     .object(&obj)
+    ///     .memory()
+    ///     .finish()
+    ///     .unwrap();
+    /// assert!(relocated.is_some());
+    /// # std::fs::remove_file(&obj).unwrap();
+    /// ```
+    /// See [`MultiOutput`] for the cost.
+    pub fn multi_output(self, src: &[u8]) -> MultiOutput<'a, 'b> {
+        MultiOutput::new(self, src)
+    }
+
+    /// compile `src` into a shared library and load it into the process.
+    ///
+    /// Unlike [`relocate`](Context::relocate), which links into memory owned by the
+    /// module, the library is linked by tcc and loaded by the dynamic loader, which
+    /// resolves the libraries it needs and exports its dynamic symbols only, e.g. not
+    /// those of static functions. tcc 0.9.27 emits no `.init_array` entries, so
+    /// constructors are not run, and exports hidden symbols, see
+    /// [`set_default_visibility`](Context::set_default_visibility). Calls between global
+    /// functions of the library go through its PLT, which tcc 0.9.27 gets wrong on
+    /// x86_64, so helpers should be static or hidden. The library is written to a temporary file,
+    /// which is removed once loaded. Output type is set to [`OutputType::Dll`].
+    pub fn compile_dll_and_load(mut self, src: &[u8]) -> Result<RelocatedCtx<'a>, TccError> {
+        let src = CString::new(src)
+            .map_err(|_| TccError::new(ErrorKind::InvalidInput, "source contains nul byte"))?;
+        self.set_output_type(OutputType::Dll)?;
+        self.compile_string(&src)?;
+        let dir = temp_include_dir("dll")?;
+        let lib = dir.join(format!("module.{}", OutputType::Dll.default_extension()));
+        let loaded = self.output_file(&lib).and_then(|_| {
+            // the guard borrowed by self is passed to the module, and the state of
+            // self is deleted by output_file.
+            let mut ctx = unsafe { Context::<'a, 'static>::new_unguarded() }
+                .map_err(|_| TccError::new(ErrorKind::Io, "out of memory"))?;
+            ctx.set_output_type(OutputType::Memory)?.apply_output_type();
+            let path = to_cstr(&lib)?;
+            ctx.check(ErrorKind::Link, |s| unsafe {
+                tcc_ext_load_dll(s, path.as_ptr())
+            })?;
+            ctx.relocate()
+        });
+        // a mapped library stays loaded without its file.
+        let _ = fs::remove_dir_all(&dir);
+        loaded
+    }
+
+    /// global symbols defined and referenced by the sources compiled so far, without linking.
+    ///
+    /// With [`OutputType::Obj`], this is the symbol table of the object written by
+    /// [`output_file`](Context::output_file). Files added by [`add_file`](Context::add_file)
+    /// are included, as are the startup objects tcc adds for [`OutputType::Exe`].
+    /// Static and hidden symbols are not listed.
+    pub fn object_symbols(&self) -> Result<ObjectSymbols, TccError> {
+        if !self.started {
+            return Err(TccError::new(ErrorKind::InvalidInput, "nothing compiled"));
+        }
+        let mut defined: Vec<(CString, *mut c_void)> = Vec::new();
+        let mut undefined: Vec<(CString, *mut c_void)> = Vec::new();
+        unsafe {
+            tcc_ext_list_symbols(
+                self.inner,
+                &mut defined as *mut _ as *mut c_void,
+                Some(push_symbol),
+            );
+            tcc_ext_list_undefined(
+                self.inner,
+                &mut undefined as *mut _ as *mut c_void,
+                Some(push_symbol),
+            );
+        }
+        Ok(ObjectSymbols {
+            defined: defined.into_iter().map(|(name, _)| name).collect(),
+            undefined: undefined.into_iter().map(|(name, _)| name).collect(),
+        })
+    }
+
+    /// size of memory needed by [`relocate`](Context::relocate).
+    ///
+    /// Linking is done at the first call, i.e. nothing should be added to the
+    /// context afterwards, the size is cached for later calls and relocation.
+    pub fn relocation_size(&mut self) -> Result<usize, TccError> {
+        if let Some(len) = self.reloc_size {
+            return Ok(len);
+        }
+        self.check_emitted(OutputType::Memory)?;
+        self.default_output_type()?;
+        let bcheck = self.lib_path().join("bcheck.o");
+        if self.bounds_check && bcheck.is_file() {
+            self.add_file(bcheck)?;
+        }
+        if let Some(mut resolve) = self.resolver.take() {
+            let mut undefined: Vec<(CString, *mut c_void)> = Vec::new();
+            unsafe {
+                tcc_ext_list_undefined(
+                    self.inner,
+                    &mut undefined as *mut _ as *mut c_void,
+                    Some(push_symbol),
+                )
+            };
+            for (name, _) in undefined {
+                if let Some(addr) = resolve(&name) {
+                    unsafe { self.add_symbol(&name, addr) };
+                }
+            }
+        }
+        self.report_phase(Phase::Link);
+        self.apply_visibility();
+        // pass null ptr to get required length
+        let len = self.check(ErrorKind::Relocate, |s| unsafe {
+            tcc_ext_relocate(s, null_mut())
+        })? as usize;
+        self.reloc_size = Some(len);
+        Ok(len)
+    }
+
+    /// do all relocations (needed before get symbol)
+    pub fn relocate(mut self) -> Result<RelocatedCtx<'a>, TccError> {
+        let len = self.relocation_size()?;
+        self.relocate_with(len, MIN_CODE_ALIGN)
+    }
+
+    /// same as [`relocate`](Context::relocate), but start the module at an address
+    /// aligned to `align` bytes, e.g. a cache line or a page.
+    ///
+    /// [`base_ptr`](RelocatedCtx::base_ptr) is aligned, tcc aligns sections within the
+    /// module as usual. Return Err of [`ErrorKind::InvalidInput`] if `align` is not a
+    /// power of two, or less than the alignment of a pointer.
+    pub fn relocate_aligned(mut self, align: usize) -> Result<RelocatedCtx<'a>, TccError> {
+        if !align.is_power_of_two() || align < MIN_CODE_ALIGN {
+            let msg = format!(
+                "alignment {} is not a power of two of at least {}",
+                align, MIN_CODE_ALIGN
+            );
+            return Err(TccError::new(ErrorKind::InvalidInput, msg));
+        }
+        let len = self.relocation_size()?;
+        self.relocate_with(len, align)
+    }
+
+    /// relocate into a buffer of `len` bytes aligned to `align`, or of the size tcc needs
+    /// if it is larger.
+    fn relocate_with(mut self, mut len: usize, align: usize) -> Result<RelocatedCtx<'a>, TccError> {
+        self.report_phase(Phase::Relocate);
+        let mut bin = CodeBuf::new(0, align)?;
+        // nothing to relocate, and ptr of an empty buffer is dangling.
+        if len > 0 {
+            // layout depends on alignment of the buffer, so the size needed may differ
+            // from relocation_size, retry with the size reported before anything is written.
+            let mut tries = 0;
+            loop {
+                bin = CodeBuf::new(len, align)?;
+                let needed = self.check(ErrorKind::Relocate, |s| unsafe {
+                    tcc_ext_relocate_checked(s, bin.ptr as *mut c_void, len)
+                })?;
+                if needed == 0 {
+                    break;
+                }
+                tries += 1;
+                if tries == 3 {
+                    return Err(TccError::new(
+                        ErrorKind::Relocate,
+                        format!("relocation needs {} bytes, more than {}", needed, len),
+                    ));
+                }
+                // room for the worst alignment of the next buffer, as tcc does.
+                len = needed as usize + 64;
+            }
+        }
+        // diagnostics are dropped with self.
+        unsafe { tcc_set_error_func(self.inner, null_mut(), None) };
+        let tcc_handle = self.inner;
+        self.inner = null_mut();
+
+        let mut relocated = RelocatedCtx {
+            inner: tcc_handle,
+            bin,
+            stats: ModuleStats::default(),
+            reloc_stats: RelocStats::default(),
+            entry: None,
+            _shared: std::mem::take(&mut self.shared),
+            _g: PhantomData,
+            phantom: PhantomData,
+        };
+        relocated.stats = ModuleStats {
+            symbol_count: relocated.list_symbols().len(),
+            code_bytes: relocated.code_size(),
+        };
+        let stats = &mut relocated.reloc_stats;
+        unsafe {
+            tcc_ext_reloc_stats(
+                tcc_handle,
+                &mut stats.code_size,
+                &mut stats.data_size,
+                &mut stats.reloc_count,
+            )
+        };
+        if self.bounds_check {
+            // same as tcc_run.
+            let sym = unsafe { CStr::from_bytes_with_nul_unchecked(b"__bound_init\0") };
+            let init: extern "C" fn() = unsafe { relocated.get_function(sym) }
+                .ok_or_else(|| TccError::new(ErrorKind::Link, "undefined symbol '__bound_init'"))?;
+            init();
+        }
+        if let Some(ref entry) = self.entry {
+            let addr = unsafe { relocated.get_symbol(entry) }.ok_or_else(|| {
+                let msg = format!("undefined entry symbol '{}'", entry.to_string_lossy());
+                TccError::new(ErrorKind::Link, msg)
+            })?;
+            relocated.entry = Some(addr);
+        }
+        Ok(relocated)
+    }
+
+    /// relocate, call `void init_sym(void)` once, and return the still-live module.
+    ///
+    /// Library-style code without `main` should be loaded by [`relocate`](Context::relocate)
+    /// and `get_symbol`, this is a shorthand for plugins with an init function.
+    /// Missing `init_sym` is reported as [`ErrorKind::Link`].
+    pub fn run_init(self, init_sym: &CStr) -> Result<RelocatedCtx<'a>, TccError> {
+        let mut relocated = self.relocate()?;
+        let init: extern "C" fn() =
+            unsafe { relocated.get_function(init_sym) }.ok_or_else(|| {
+                TccError::new(
+                    ErrorKind::Link,
+                    format!("undefined symbol '{}'", init_sym.to_string_lossy()),
+                )
+            })?;
+        init();
+        Ok(relocated)
+    }
+
+    /// compile `src`, and return function `name` as a closure owning the relocated module.
+    ///
+    /// Arguments are passed as a tuple of `A`:
+    /// ```
+    /// use libtcc::{Context, Guard};
+    /// use std::ffi::CString;
+    /// let p = CString::new("int add(int a, int b){ return a + b; }").unwrap();
+    /// let name = CString::new("add").unwrap();
+    /// let mut g = Guard::new().unwrap();
+    /// let ctx = Context::new(&mut g).unwrap();
+    /// let add = unsafe { ctx.compile_fn::<(i32, i32), i32>(&p, &name) }.unwrap();
+    /// assert_eq!(add((1, 2)), 3);
+    /// ```
+    ///
+    /// # Safety
+    /// `A` and `R` must match the signature of the compiled function.
+    pub unsafe fn compile_fn<A: FnArgs + 'static, R: 'static>(
+        mut self,
+        src: &CStr,
+        name: &CStr,
+    ) -> Result<Box<dyn Fn(A) -> R + 'a>, TccError> {
+        self.compile_string(src)?;
+        let mut relocated = self.relocate()?;
+        let f = relocated.get_symbol(name).ok_or_else(|| {
+            TccError::new(
+                ErrorKind::Link,
+                format!("undefined symbol '{}'", name.to_string_lossy()),
+            )
+        })?;
+        Ok(Box::new(move |args: A| {
+            // the module lives as long as the closure.
+            let _ = &relocated;
+            args.call(f)
+        }))
+    }
+
+    /// relocate and call `main` with `args` as argv, like `tcc -run`.
+    ///
+    /// `Ok` holds return value of `main`, nonzero included, while `Err` means the
+    /// program could not be run, e.g. relocation fails or `main` is missing.
+    pub fn run(self, args: &[&CStr]) -> Result<i32, TccError> {
+        let mut relocated = self.relocate()?;
+        let main = relocated.main()?;
+        Ok(call_main(main, args))
+    }
+
+    /// same as [`run`](Context::run), with argv split from `cmdline`, e.g. a shebang line.
+    ///
+    /// Arguments are separated by whitespace, and quoting follows a subset of sh:
+    /// - `'...'` keeps everything inside literally.
+    /// - `"..."` keeps everything inside, except that `\"` and `\\` are escapes.
+    /// - `\` outside quotes escapes the next character.
+    ///
+    /// Unbalanced quotes, trailing `\` or nul are reported as [`ErrorKind::InvalidInput`].
+    pub fn run_cmdline(self, cmdline: &str) -> Result<i32, TccError> {
+        let args = split_cmdline(cmdline)?;
+        let args: Vec<&CStr> = args.iter().map(|a| a.as_c_str()).collect();
+        self.run(&args)
+    }
+
+    /// relocate and run `main` with `args` as argv in a child process, capturing its
+    /// exit code and output.
+    ///
+    /// The child is a fork of this process, so the program can not corrupt it, and
+    /// crashing, e.g. by `abort()` or stack overflow, or running beyond the timeout
+    /// only ends the child. Output is captured at the file descriptors, so anything
+    /// written by the program is included. Not supported on windows.
+    ///
+    /// # Multithreaded hosts
+    /// The child is not exec'ed, it only has a copy of the calling thread, and locks
+    /// held by other threads at the time of the fork, e.g. of malloc or stdio, stay
+    /// locked in it. If other threads may allocate or print meanwhile, which includes
+    /// the test harness, a program calling `malloc`, `printf` and the like can
+    /// deadlock in the child, which only [`timeout`](ExecuteOptions::timeout) ends.
+    pub fn execute(self, args: &[&CStr], opts: ExecuteOptions) -> Result<ExecuteResult, TccError> {
+        self.execute_with_input(args, opts, None)
+            .map(|(result, _)| result)
+    }
+
+    /// same as [`run`](Context::run), but a crash of the program is returned as
+    /// [`RunOutcome::Crashed`] with the signal, instead of taking this process down.
+    ///
+    /// Isolation comes from running `main` in a forked child like
+    /// [`execute`](Context::execute), rather than from signal handlers in this process:
+    /// recovering from `SIGSEGV` in place leaves locks and memory of the program in an
+    /// unknown state, while a child dies cleanly. The flip side is that writes of the
+    /// program to globals or the heap are not visible after it returns, and that output
+    /// is forwarded to stdout and stderr of this process only once the child ends, see
+    /// [`run_sandboxed_to`](Context::run_sandboxed_to) to capture it instead.
+    ///
+    /// Not supported on windows, where starting the program fails with [`ErrorKind::Io`].
+    ///
+    /// # Multithreaded hosts
+    /// The child is forked without exec, so the hazard described for
+    /// [`execute`](Context::execute) applies: locks held by other threads at the time
+    /// of the fork stay locked in the child, and a program calling `malloc`, `printf`
+    /// and the like may deadlock instead of crashing. Since there is no timeout, this
+    /// call then never returns. Prefer it in single-threaded hosts, or use `execute`
+    /// with a timeout otherwise.
+    pub fn run_sandboxed(self, args: &[&CStr]) -> Result<RunOutcome, TccError> {
+        self.run_sandboxed_to(args, io::stdout(), io::stderr())
+    }
+
+    /// same as [`run_sandboxed`](Context::run_sandboxed), but write stdout and stderr
+    /// of the program to `out` and `err`.
+    ///
+    /// Failing to write them is reported as [`ErrorKind::Io`].
+    pub fn run_sandboxed_to<O: Write, E: Write>(
+        self,
+        args: &[&CStr],
+        mut out: O,
+        mut err: E,
+    ) -> Result<RunOutcome, TccError> {
+        let (result, sig) = self.execute_with_input(args, ExecuteOptions::default(), None)?;
+        out.write_all(&result.stdout).map_err(io_error)?;
+        err.write_all(&result.stderr).map_err(io_error)?;
+        Ok(match sig {
+            0 => RunOutcome::Exited(result.code),
+            sig => RunOutcome::Crashed(sig),
+        })
+    }
+
+    /// same as [`execute`](Context::execute), but feed `input` to stdin of the program
+    /// instead of inheriting it, and return exit code and stdout.
+    ///
+    /// The program sees end of file after `input`. Redirection happens in the child,
+    /// so descriptors of this process are never touched, even if the program aborts.
+    /// stderr of the program is written to stderr of this process.
+    ///
+    /// The program runs in a forked child, see [`execute`](Context::execute)
+    /// for the hazard in multithreaded hosts.
+    pub fn run_with_stdin(self, args: &[&CStr], input: &[u8]) -> Result<(i32, Vec<u8>), TccError> {
+        let (result, _) = self.execute_with_input(args, ExecuteOptions::default(), Some(input))?;
+        // stderr is best-effort, like that of the program itself.
+        let _ = io::stderr().write_all(&result.stderr);
+        Ok((result.code, result.stdout))
+    }
+
+    fn execute_with_input(
+        self,
+        args: &[&CStr],
+        opts: ExecuteOptions,
+        input: Option<&[u8]>,
+    ) -> Result<(ExecuteResult, c_int), TccError> {
+        let mut relocated = self.relocate()?;
+        let main = relocated.main()?;
+        let mut argv: Vec<*mut c_char> = args.iter().map(|a| a.as_ptr() as *mut c_char).collect();
+        argv.push(null_mut());
+        let timeout = opts
+            .timeout
+            .map_or(-1, |t| t.as_millis().min(c_int::MAX as u128) as c_int);
+        let mut result = ExecuteResult::default();
+        let mut sig = 0;
+        let ret = unsafe {
+            tcc_ext_execute(
+                Some(main),
+                args.len() as c_int,
+                argv.as_mut_ptr(),
+                opts.stack_size.unwrap_or(0),
+                timeout,
+                // a non-null ptr even if input is empty, which is still redirected.
+                input.map_or(std::ptr::null(), |i| i.as_ptr() as *const c_char),
+                input.map_or(0, <[u8]>::len),
+                &mut result.code,
+                &mut sig,
+                &mut result.stdout as *mut _ as *mut c_void,
+                &mut result.stderr as *mut _ as *mut c_void,
+                Some(write_buf),
+            )
+        };
+        if ret < 0 {
+            return Err(TccError::new(
+                ErrorKind::Io,
+                format!("can not start the program: {}", io::Error::last_os_error()),
+            ));
+        }
+        result.timed_out = ret == 1;
+        Ok((result, sig))
+    }
+
+    /// same as [`run`](Context::run), with `env` set in the environment of this process
+    /// while `main` runs, e.g. to control what `getenv` of the program sees.
+    ///
+    /// Variables not in `env` are inherited. Previous values are restored after `main`
+    /// returns, and tcc's own runtime is not affected.
+    ///
+    /// The environment is process-wide and `setenv` is not thread-safe, so no other
+    /// thread should read or write it during the run. A name that is empty, or has
+    /// `=` or nul, and a value with nul are reported as [`ErrorKind::InvalidInput`].
+    pub fn run_with_env(self, args: &[&CStr], env: &[(&str, &str)]) -> Result<i32, TccError> {
+        for (name, val) in env {
+            if name.is_empty() || name.contains(['=', '\0'].as_ref()) || val.contains('\0') {
+                return Err(TccError::new(
+                    ErrorKind::InvalidInput,
+                    format!("invalid environment variable {:?}={:?}", name, val),
+                ));
+            }
+        }
+        let mut relocated = self.relocate()?;
+        let main = relocated.main()?;
+        let _restore = EnvRestore::set(env);
+        Ok(call_main(main, args))
+    }
+
+    /// same as [`run`](Context::run), but call `main` on a new thread with `stack` bytes of stack.
+    ///
+    /// This contains deep recursion of compiled code within a known limit. Catching an
+    /// actual overflow is best-effort: the guard page makes it crash the whole process
+    /// rather than corrupting memory, but it is not turned into an `Err`.
+    pub fn run_with_stack(self, args: &[&CStr], stack: usize) -> Result<i32, TccError> {
+        let mut relocated = self.relocate()?;
+        let main = relocated.main()?;
+        thread::scope(|s| {
+            let t = thread::Builder::new()
+                .stack_size(stack)
+                .spawn_scoped(s, || call_main(main, args))
+                .map_err(io_error)?;
+            Ok(t.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+        })
+    }
+}
+
+impl<'a, 'b> Context<'a, 'b> {
+    /// Run `f` on the tcc state, negative return of which is turned into error of `kind`.
+    fn check<F>(&mut self, kind: ErrorKind, f: F) -> Result<c_int, TccError>
+    where
+        F: FnOnce(*mut TCCState) -> c_int,
+    {
+        self.diag.msgs.clear();
+        // verbose messages are printed to stdout by tcc.
+        let capture = self.verbosity > 0 && unsafe { tcc_ext_stdout_begin() } == 0;
+        let ret = f(self.inner);
+        if capture {
+            let mut out = Vec::new();
+            unsafe { tcc_ext_stdout_end(&mut out as *mut _ as *mut c_void, Some(write_buf)) };
+            for line in out.split(|&c| c == b'\n').filter(|l| !l.is_empty()) {
+                if let Ok(line) = CString::new(line) {
+                    self.diag.report(&line);
+                }
+            }
+        }
+        self.diag.result(kind, ret)
+    }
+}
+
+impl<'b> Diagnostics<'b> {
+    /// pass `msg` to user's call back.
+    fn report(&mut self, msg: &CStr) {
+        match self.err_func {
+            Some(ref mut f) => f(msg),
+            // same as tcc without call back
+            None => eprintln!("{}", msg.to_string_lossy()),
+        }
+    }
+
+    fn result(&mut self, kind: ErrorKind, ret: c_int) -> Result<c_int, TccError> {
+        if ret < 0 {
+            Err(TccError::from_diagnostics(kind, &self.msgs))
+        } else {
+            Ok(ret)
+        }
+    }
+}
+
+#[cfg(target_family = "unix")]
+fn to_cstr<T: AsRef<Path>>(p: T) -> Result<CString, TccError> {
+    use std::os::unix::ffi::OsStrExt;
+    CString::new(p.as_ref().as_os_str().as_bytes())
+        .map_err(|_| TccError::new(ErrorKind::InvalidInput, "path contains nul byte"))
+}
+
+#[cfg(target_family = "windows")]
+fn to_cstr<T: AsRef<Path>>(p: T) -> Result<CString, TccError> {
+    CString::new(p.as_ref().to_string_lossy().to_string().as_bytes())
+        .map_err(|_| TccError::new(ErrorKind::InvalidInput, "path contains nul byte"))
+}
+
+#[cfg(target_family = "unix")]
+fn from_cstr(s: &CStr) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(s.to_bytes()))
+}
+
+#[cfg(target_family = "windows")]
+fn from_cstr(s: &CStr) -> PathBuf {
+    PathBuf::from(s.to_string_lossy().to_string())
+}
+
+// preprocessor
+impl<'a, 'b> Drop for Context<'a, 'b> {
+    fn drop(&mut self) {
+        if !self.inner.is_null() {
+            unsafe { tcc_delete(self.inner) }
+        }
+        if let Some(dir) = self.deny_dir.take() {
+            let _ = fs::remove_dir_all(dir);
+        }
+        if let Some(includes) = self.includes.take() {
+            let _ = fs::remove_dir_all(includes.dir);
+        }
+    }
+}
+
+/// Create an empty directory to be used as include path, e.g. `libtcc-deny-<pid>-<n>`.
+fn temp_include_dir(kind: &str) -> Result<PathBuf, TccError> {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let dir = env::temp_dir().join(format!(
+        "libtcc-{}-{}-{}",
+        kind,
+        process::id(),
+        COUNT.fetch_add(1, Ordering::SeqCst)
+    ));
+    fs::create_dir_all(&dir).map_err(io_error)?;
+    Ok(dir)
+}
+
+/// Return include dirs and library dir of the first complete installation of `libc`.
+fn find_libc(libc: Libc) -> Result<(Vec<PathBuf>, PathBuf), TccError> {
+    let arch = match env::consts::ARCH {
+        "x86" => "i386",
+        arch => arch,
+    };
+    let gnu = format!("{}-linux-gnu", arch);
+    let musl = format!("{}-linux-musl", arch);
+    // include dirs, library dir, and a header only this libc has.
+    let (candidates, header): (Vec<(Vec<PathBuf>, PathBuf)>, &str) = match libc {
+        Libc::Glibc => (
+            vec![
+                (
+                    vec![Path::new("/usr/include").join(&gnu), "/usr/include".into()],
+                    Path::new("/usr/lib").join(&gnu),
+                ),
+                (vec!["/usr/include".into()], "/usr/lib64".into()),
+                (vec!["/usr/include".into()], "/usr/lib".into()),
+            ],
+            "gnu/libc-version.h",
+        ),
+        Libc::Musl => (
+            vec![
+                (
+                    vec![Path::new("/usr/include").join(&musl)],
+                    Path::new("/usr/lib").join(&musl),
+                ),
+                (
+                    vec!["/usr/lib/musl/include".into()],
+                    "/usr/lib/musl/lib".into(),
+                ),
+                (
+                    vec!["/usr/local/musl/include".into()],
+                    "/usr/local/musl/lib".into(),
+                ),
+            ],
+            "stdio.h",
+        ),
+    };
+    let mut missing = Vec::new();
+    for (includes, lib) in candidates {
+        let mut absent = Vec::new();
+        if !includes.iter().any(|dir| dir.join(header).is_file()) {
+            absent.push(includes[0].join(header));
+        }
+        if !lib.join("crt1.o").is_file() {
+            absent.push(lib.join("crt1.o"));
+        }
+        if !lib.join("libc.so").is_file() && !lib.join("libc.a").is_file() {
+            absent.push(lib.join("libc.so"));
+        }
+        if absent.is_empty() {
+            return Ok((includes, lib));
+        }
+        missing.extend(absent);
+    }
+    let missing: Vec<String> = missing.iter().map(|p| p.display().to_string()).collect();
+    Err(TccError::new(
+        ErrorKind::Io,
+        format!("{:?} not found, missing {}", libc, missing.join(", ")),
+    ))
+}
+
+/// Push `.c` files in `dir` to `files`, and those in subdirectories if `recursive`.
+fn collect_sources(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) -> Result<(), TccError> {
+    let io_error = |e: io::Error| {
+        TccError::new(
+            ErrorKind::Io,
+            format!("can not read '{}': {}", dir.display(), e),
+        )
+    };
+    for entry in fs::read_dir(dir).map_err(io_error)? {
+        let entry = entry.map_err(io_error)?;
+        let path = entry.path();
+        let file_type = entry.file_type().map_err(io_error)?;
+        if file_type.is_dir() {
+            if recursive {
+                collect_sources(&path, recursive, files)?;
+            }
+        } else if path.extension().is_some_and(|e| e == "c") && path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Return true if `header` has `#pragma once`, or starts with an include guard.
+fn has_include_guard(header: &[u8]) -> bool {
+    let header = String::from_utf8_lossy(header);
+    let mut directives = header
+        .lines()
+        .filter_map(|l| l.trim_start().strip_prefix('#'))
+        .map(|d| d.split_whitespace().collect::<Vec<_>>());
+    if header
+        .lines()
+        .any(|l| l.trim_start().starts_with("#pragma once"))
+    {
+        return true;
+    }
+    match (directives.next(), directives.next()) {
+        (Some(ifndef), Some(define)) => {
+            ifndef.len() == 2
+                && ifndef[0] == "ifndef"
+                && define.len() >= 2
+                && define[0] == "define"
+                && define[1] == ifndef[1]
+        }
+        _ => false,
+    }
+}
+
+/// Names included by `src`, with true for `<name>`, false for `"name"`.
+fn scan_includes(src: &[u8]) -> Vec<(String, bool)> {
+    let mut names = Vec::new();
+    for line in String::from_utf8_lossy(src).lines() {
+        let directive = match line.trim_start().strip_prefix('#') {
+            Some(directive) => directive.trim_start(),
+            None => continue,
+        };
+        let rest = match directive.strip_prefix("include") {
+            Some(rest) => rest.trim_start(),
+            None => continue,
+        };
+        let (close, system) = match rest.chars().next() {
+            Some('<') => ('>', true),
+            Some('"') => ('"', false),
+            _ => continue,
+        };
+        if let Some(end) = rest[1..].find(close) {
+            names.push((rest[1..end + 1].to_string(), system));
+        }
+    }
+    names
+}
+
+/// Split `cmdline` into arguments, see [`Context::run_cmdline`].
+fn split_cmdline(cmdline: &str) -> Result<Vec<CString>, TccError> {
+    let invalid = |msg: &str| TccError::new(ErrorKind::InvalidInput, msg);
+    let mut args = Vec::new();
+    let mut chars = cmdline.chars();
+    let mut arg: Option<String> = None;
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(a) = arg.take() {
+                    args.push(a);
+                }
+            }
+            '\'' => {
+                let a = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => a.push(c),
+                        None => return Err(invalid("unbalanced single quote")),
+                    }
+                }
+            }
+            '"' => {
+                let a = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) if c == '"' || c == '\\' => a.push(c),
+                            Some(c) => {
+                                a.push('\\');
+                                a.push(c);
+                            }
+                            None => return Err(invalid("unbalanced double quote")),
+                        },
+                        Some(c) => a.push(c),
+                        None => return Err(invalid("unbalanced double quote")),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => arg.get_or_insert_with(String::new).push(c),
+                None => return Err(invalid("trailing backslash")),
+            },
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    args.into_iter()
+        .map(|a| CString::new(a).map_err(|_| invalid("argument contains nul byte")))
+        .collect()
+}
+
+type MainFn = extern "C" fn(c_int, *mut *mut c_char) -> c_int;
+
+/// Call `main` with `args` as argv, which ends with null like C's.
+fn call_main(main: MainFn, args: &[&CStr]) -> i32 {
+    let mut argv: Vec<*mut c_char> = args.iter().map(|a| a.as_ptr() as *mut c_char).collect();
+    argv.push(null_mut());
+    main(args.len() as c_int, argv.as_mut_ptr())
+}
+
+/// Environment variables replaced by [`Context::run_with_env`], restored on drop.
+struct EnvRestore(Vec<(OsString, Option<OsString>)>);
+
+impl EnvRestore {
+    fn set(vars: &[(&str, &str)]) -> Self {
+        let mut old = Vec::with_capacity(vars.len());
+        for (name, val) in vars {
+            old.push((OsString::from(name), env::var_os(name)));
+            env::set_var(name, val);
+        }
+        EnvRestore(old)
+    }
+}
+
+impl Drop for EnvRestore {
+    fn drop(&mut self) {
+        // restore in reverse, so the first value of a name set twice wins.
+        for (name, val) in self.0.drain(..).rev() {
+            match val {
+                Some(val) => env::set_var(name, val),
+                None => env::remove_var(name),
+            }
+        }
+    }
+}
+
+fn io_error(e: io::Error) -> TccError {
+    TccError::new(ErrorKind::Io, e.to_string())
+}
+
+/// Prepend `#line 1 "name"` to source, so that tcc reports it as `name`.
+fn with_line_directive(name: &CStr, src: &CStr) -> CString {
+    let mut buf = Vec::from(&b"#line 1 \""[..]);
+    for &c in name.to_bytes() {
+        if c == b'"' || c == b'\\' {
+            buf.push(b'\\');
+        }
+        buf.push(c);
+    }
+    buf.extend_from_slice(b"\"\n");
+    buf.extend_from_slice(src.to_bytes());
+    // neither name nor src contains nul.
+    CString::new(buf).unwrap()
+}
+
+/// Append preprocessor output to a `Vec<u8>`.
+extern "C" fn write_buf(opaque: *mut c_void, buf: *const c_char, len: usize) {
+    let out = opaque as *mut Vec<u8>;
+    unsafe { (*out).extend_from_slice(slice::from_raw_parts(buf as *const u8, len)) }
+}
+
+/// Write preprocessor output to a `(&mut dyn Write, io::Result<()>)`, keeping the first error.
+extern "C" fn write_sink(opaque: *mut c_void, buf: *const c_char, len: usize) {
+    let sink = unsafe { &mut *(opaque as *mut (&mut dyn Write, io::Result<()>)) };
+    if sink.1.is_ok() {
+        let buf = unsafe { slice::from_raw_parts(buf as *const u8, len) };
+        sink.1 = sink.0.write_all(buf);
+    }
+}
+
+/// Append symbol listed by tcc to a `Vec<(CString, *mut c_void)>`.
+extern "C" fn push_symbol(opaque: *mut c_void, name: *const c_char, val: *const c_void) {
+    let out = opaque as *mut Vec<(CString, *mut c_void)>;
+    unsafe { (*out).push((CStr::from_ptr(name).to_owned(), val as *mut c_void)) }
+}
+
+/// Append a dependency to a `Vec<CString>`.
+extern "C" fn push_dep(opaque: *mut c_void, name: *const c_char) {
+    let out = opaque as *mut Vec<CString>;
+    unsafe { (*out).push(CStr::from_ptr(name).to_owned()) }
+}
+
+/// Escape a path as a word of a makefile rule.
+fn escape_make(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            ' ' | '\t' | '#' => out.push('\\'),
+            '$' => out.push('$'),
+            _ => (),
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Position of the `:` ending targets of a makefile rule, skipping escaped ones and
+/// drive letters, e.g. `C:\`.
+fn find_rule_colon(rule: &str) -> Option<usize> {
+    let bytes = rule.as_bytes();
+    (0..bytes.len()).find(|&i| {
+        bytes[i] == b':'
+            && (i == 0 || bytes[i - 1] != b'\\')
+            && !(i == 1 && bytes[0].is_ascii_alphabetic() && bytes.get(2) == Some(&b'\\'))
+    })
+}
+
+/// Split prerequisites of a makefile rule into words, unescaping them.
+fn split_make_words(s: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if matches!(chars.peek(), Some(' ' | '\t' | '#' | ':')) => {
+                word.push(chars.next().unwrap())
+            }
+            '$' if chars.peek() == Some(&'$') => {
+                chars.next();
+                word.push('$');
+            }
+            c if c.is_whitespace() => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// Split a `#define NAME VALUE` line into name and value.
+fn parse_define(line: &[u8]) -> Option<(CString, CString)> {
+    let def = line.strip_prefix(b"#define ")?;
+    let (name, val) = match def.iter().position(|&c| c == b' ') {
+        Some(i) => (&def[..i], &def[i + 1..]),
+        None => (def, &def[def.len()..]),
+    };
+    Some((CString::new(name).ok()?, CString::new(val).ok()?))
+}
+
+/// Relocated compilation context
+///
+/// It keeps borrowing the guard of its [`Context`], so it can not outlive the
+/// guard, and a new context can not be created by the same guard meanwhile.
+/// [`Session`] and [`CachingJit`] keep several modules alive at once:
+/// ```compile_fail
+/// use libtcc::{Context, Guard};
+/// let mut g = Guard::new().unwrap();
+/// let relocated = Context::new(&mut g).unwrap().relocate().unwrap();
+/// drop(g);
+/// drop(relocated);
+/// ```
+pub struct RelocatedCtx<'a> {
+    inner: *mut TCCState,
+    bin: CodeBuf,
+    stats: ModuleStats,
+    reloc_stats: RelocStats,
+    entry: Option<*mut c_void>,
+    _shared: Vec<SharedSymbols>,
+    _g: PhantomData<&'a mut Guard>,
+    phantom: PhantomData<TCCState>,
+}
+
+/// Least alignment of relocated modules, see [`Context::relocate_aligned`].
+const MIN_CODE_ALIGN: usize = std::mem::align_of::<usize>();
+
+/// Memory holding relocated code and data, zeroed and aligned as requested.
+struct CodeBuf {
+    ptr: *mut u8,
+    len: usize,
+    align: usize,
+}
+
+impl CodeBuf {
+    /// Allocate `len` bytes aligned to `align`, a power of two.
+    fn new(len: usize, align: usize) -> Result<Self, TccError> {
+        if len == 0 {
+            // defensive: tcc 0.9.27 sizes even an empty module to at least
+            // RUN_SECTION_ALIGNMENT. Dangling but aligned, like an empty Vec.
+            let ptr = align as *mut u8;
+            return Ok(CodeBuf { ptr, len, align });
+        }
+        let layout = Layout::from_size_align(len, align).map_err(|e| {
+            TccError::new(
+                ErrorKind::Io,
+                format!("can not allocate {} bytes: {}", len, e),
+            )
+        })?;
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            return Err(TccError::new(ErrorKind::Io, "out of memory"));
+        }
+        Ok(CodeBuf { ptr, len, align })
+    }
+}
+
+impl Deref for CodeBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for CodeBuf {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            // same layout as allocated.
+            unsafe {
+                alloc::dealloc(
+                    self.ptr,
+                    Layout::from_size_align_unchecked(self.len, self.align),
+                )
+            };
+        }
+    }
+}
+
+/// Summary of a relocated module, see [`RelocatedCtx::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModuleStats {
+    /// number of exported symbols, same as `symbols().len()`.
+    pub symbol_count: usize,
+    /// size of relocated code and data, same as `code_size()`.
+    pub code_bytes: usize,
+}
+
+/// Sizes and relocations of a relocated module, see [`RelocatedCtx::reloc_stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RelocStats {
+    /// bytes of code, including PLT stubs of tcc.
+    pub code_size: usize,
+    /// bytes of data, initialized or not, including GOT and read-only data.
+    pub data_size: usize,
+    /// number of relocation entries applied by tcc.
+    pub reloc_count: usize,
+}
+
+/// Global symbols of compiled sources, see [`Context::object_symbols`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjectSymbols {
+    /// functions and variables defined, in order of definition.
+    pub defined: Vec<CString>,
+    /// symbols referenced but not defined, to be resolved by linking.
+    pub undefined: Vec<CString>,
+}
+
+/// Kind of memory a symbol is in, see [`RelocatedCtx::symbol_kind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SymbolKind {
+    /// executable code, e.g. a function.
+    Code,
+    /// data, e.g. a global variable.
+    Data,
+    /// not in memory of the module, e.g. in a loaded library.
+    Unknown,
+}
+
+impl<'a> RelocatedCtx<'a> {
+    /// Load a shared library, e.g. one built with [`OutputType::Dll`].
+    ///
+    /// Symbols of the library are available through `get_symbol`, and the library
+    /// stays mapped until RelocatedCtx is dropped.
+    pub fn from_dll<T: AsRef<Path>>(g: &'a mut Guard, path: T) -> Result<Self, TccError> {
+        let path = to_cstr(path)?;
+        let mut ctx = Context::new(g).map_err(|_| TccError::new(ErrorKind::Io, "out of memory"))?;
+        ctx.set_output_type(OutputType::Memory)?.apply_output_type();
+        ctx.check(ErrorKind::Link, |s| unsafe {
+            tcc_ext_load_dll(s, path.as_ptr())
+        })?;
+        ctx.relocate()
+    }
+
+    /// return symbol value or None if not found
+    ///
+    /// # Safety
+    /// Returned addr can not outlive RelocatedCtx itself. It's caller's
+    /// responsibility to take care of validity of addr.
+    pub unsafe fn get_symbol(&mut self, sym: &CStr) -> Option<*mut c_void> {
+        self.symbol_addr(sym)
+    }
+
+    fn symbol_addr(&self, sym: &CStr) -> Option<*mut c_void> {
+        let mut addr = unsafe { tcc_get_symbol(self.inner, sym.as_ptr()) };
+        if addr.is_null() {
+            addr = unsafe { tcc_ext_get_dll_symbol(self.inner, sym.as_ptr()) };
+        }
+        if addr.is_null() {
+            None
+        } else {
+            Some(addr)
+        }
+    }
+
+    /// return value of global variable `sym` read as `T`, or None if not found
+    ///
+    /// # Safety
+    /// `sym` must be a variable whose type has the same size and layout as `T`.
+    pub unsafe fn read_global<T: Copy>(&self, sym: &CStr) -> Option<T> {
+        self.symbol_addr(sym).map(|addr| (addr as *const T).read())
+    }
+
+    /// write `val` to global variable `sym`, return false if it is not found
+    ///
+    /// # Safety
+    /// `sym` must be a writable variable whose type has the same size and layout as `T`.
+    pub unsafe fn write_global<T: Copy>(&mut self, sym: &CStr, val: T) -> bool {
+        match self.symbol_addr(sym) {
+            Some(addr) => {
+                (addr as *mut T).write(val);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// return function `sym` as typed function pointer `F`, or None if not found
+    ///
+    /// Rust `extern "C" fn`s can be passed to the returned function, e.g. to register
+    /// a hook in compiled code, see `examples/callback.rs`. Structs passed or returned
+    /// by value must be `#[repr(C)]` with fields of the same C types in the same order,
+    /// see `examples/struct_abi.rs`.
+    ///
+    /// # Safety
+    /// `F` must match the signature of the compiled function, and the returned
+    /// pointer can not outlive RelocatedCtx itself.
+    pub unsafe fn get_function<F: FnPtr>(&mut self, sym: &CStr) -> Option<F> {
+        self.get_symbol(sym).map(|addr| F::from_ptr(addr))
+    }
+
+    /// kind of memory symbol `sym` is in, or None if not found, e.g. to check a symbol
+    /// is a function before transmuting it to one.
+    ///
+    /// This is best-effort: a symbol is [`SymbolKind::Code`] if tcc puts it in an
+    /// executable section, e.g. `.text`, and [`SymbolKind::Data`] if in another one
+    /// within [`code_bytes`](RelocatedCtx::code_bytes). Anything else, e.g. symbols of
+    /// libraries or added by [`Context::add_symbol`], is [`SymbolKind::Unknown`].
+    pub fn symbol_kind(&self, sym: &CStr) -> Option<SymbolKind> {
+        // flags of ELF sections.
+        const SHF_ALLOC: c_long = 1 << 1;
+        const SHF_EXECINSTR: c_long = 1 << 2;
+        let addr = self.symbol_addr(sym)? as usize;
+        let base = self.base_ptr() as usize;
+        if addr < base || addr >= base + self.code_size() {
+            return Some(SymbolKind::Unknown);
+        }
+        let flags = unsafe { tcc_ext_symbol_section_flags(self.inner, sym.as_ptr()) };
+        Some(if flags < 0 {
+            SymbolKind::Unknown
+        } else if flags & SHF_EXECINSTR != 0 {
+            SymbolKind::Code
+        } else if flags & SHF_ALLOC != 0 {
+            SymbolKind::Data
+        } else {
+            SymbolKind::Unknown
+        })
+    }
+
+    /// write address of each of `names` to `out` in order, e.g. to fill a table of
+    /// function pointers expected by a host.
+    ///
+    /// Return Err of [`ErrorKind::Link`] if any of them is not found, and of
+    /// [`ErrorKind::InvalidInput`] if `out` is not as long as `names`, `out` may be
+    /// partly written then. The addresses can not outlive RelocatedCtx itself.
+    pub fn fill_vtable(&self, names: &[&CStr], out: &mut [*mut c_void]) -> Result<(), TccError> {
+        if names.len() != out.len() {
+            return Err(TccError::new(
+                ErrorKind::InvalidInput,
+                format!("{} names for {} entries", names.len(), out.len()),
+            ));
+        }
+        for (name, entry) in names.iter().zip(out) {
+            *entry = self.symbol_addr(name).ok_or_else(|| {
+                TccError::new(
+                    ErrorKind::Link,
+                    format!("undefined symbol '{}'", name.to_string_lossy()),
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    fn main(&mut self) -> Result<MainFn, TccError> {
+        unsafe { self.get_function(CStr::from_bytes_with_nul_unchecked(b"main\0")) }
+            .ok_or_else(|| TccError::new(ErrorKind::Link, "undefined symbol 'main'"))
+    }
+
+    /// names of global symbols defined by the compiled code, including runtime
+    /// support linked by tcc.
+    ///
+    /// Symbols of libraries loaded by [`from_dll`](RelocatedCtx::from_dll) are not listed,
+    /// nor are hidden ones, see [`Context::set_default_visibility`].
+    pub fn symbols(&self) -> Vec<CString> {
+        self.list_symbols()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    /// addresses of all symbols listed by [`symbols`](RelocatedCtx::symbols), at once.
+    ///
+    /// A duplicated name, which is not expected, maps to the last address, see
+    /// [`duplicate_symbols`](RelocatedCtx::duplicate_symbols) to detect it.
+    pub fn symbol_map(&self) -> HashMap<CString, *mut c_void> {
+        self.list_symbols().into_iter().collect()
+    }
+
+    /// names listed more than once by [`symbols`](RelocatedCtx::symbols), with all
+    /// their addresses in order, empty for a well-formed module.
+    pub fn duplicate_symbols(&self) -> Vec<(CString, Vec<*mut c_void>)> {
+        let mut seen: Vec<(CString, Vec<*mut c_void>)> = Vec::new();
+        let mut index: HashMap<CString, usize> = HashMap::new();
+        for (name, addr) in self.list_symbols() {
+            match index.get(&name) {
+                Some(&i) => seen[i].1.push(addr),
+                None => {
+                    index.insert(name.clone(), seen.len());
+                    seen.push((name, vec![addr]));
+                }
+            }
+        }
+        seen.retain(|(_, addrs)| addrs.len() > 1);
+        seen
+    }
+
+    /// write a map of the module, like a map file of a linker, e.g. to find the function
+    /// at a faulting address.
+    ///
+    /// The first line is `base <addr> size <bytes>`, followed by a line of
+    /// `<addr> <name>` for each of [`symbols`](RelocatedCtx::symbols), sorted by address.
+    /// Addresses and sizes are in hex.
+    pub fn write_map<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let mut symbols = self.list_symbols();
+        symbols.sort_by_key(|(_, addr)| *addr as usize);
+        writeln!(
+            w,
+            "base {:#018x} size {:#x}",
+            self.base_ptr() as usize,
+            self.code_size()
+        )?;
+        for (name, addr) in symbols {
+            writeln!(w, "{:#018x} {}", addr as usize, name.to_string_lossy())?;
+        }
+        Ok(())
+    }
+
+    /// byte range of each of [`symbols`](RelocatedCtx::symbols) within
+    /// [`code_bytes`](RelocatedCtx::code_bytes), sorted by offset, e.g. to disassemble
+    /// function by function.
+    ///
+    /// tcc does not record symbol sizes, so a range ends where the next symbol starts,
+    /// and the last one at [`code_size`](RelocatedCtx::code_size). Aliases share a range.
+    /// Ranges may cover padding, or data following the last function.
+    pub fn symbol_ranges(&self) -> Vec<(CString, Range<usize>)> {
+        let base = self.base_ptr() as usize;
+        let size = self.code_size();
+        let mut starts: Vec<(CString, usize)> = self
+            .list_symbols()
+            .into_iter()
+            .map(|(name, addr)| (name, (addr as usize).wrapping_sub(base)))
+            .filter(|(_, off)| *off < size)
+            .collect();
+        starts.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+        let mut ends: Vec<usize> = starts.iter().map(|(_, off)| *off).collect();
+        let mut end = size;
+        for i in (0..ends.len()).rev() {
+            let start = ends[i];
+            ends[i] = end;
+            if i > 0 && ends[i - 1] < start {
+                end = start;
+            }
+        }
+        starts
+            .into_iter()
+            .zip(ends)
+            .map(|((name, start), end)| (name, start..end))
+            .collect()
+    }
+
+    /// size in bytes of the memory holding relocated code and data.
+    pub fn code_size(&self) -> usize {
+        self.bin.len()
+    }
+
+    /// start address of the memory holding relocated code and data.
+    pub fn base_ptr(&self) -> *const c_void {
+        self.bin.as_ptr() as *const c_void
+    }
+
+    /// relocated code and data as bytes, e.g. to feed a disassembler.
+    ///
+    /// The slice starts at [`base_ptr`](RelocatedCtx::base_ptr), has
+    /// [`code_size`](RelocatedCtx::code_size) bytes, and is only valid while
+    /// RelocatedCtx lives.
+    pub fn code_bytes(&self) -> &[u8] {
+        &self.bin
+    }
+
+    /// return address at `off` bytes from [`base_ptr`](RelocatedCtx::base_ptr), or None
+    /// if `off` is beyond [`code_size`](RelocatedCtx::code_size).
+    ///
+    /// # Safety
+    /// Returned addr can not outlive RelocatedCtx itself. Nothing guarantees there is
+    /// a valid entry point, or data of any type at `off`.
+    pub unsafe fn ptr_at_offset(&self, off: usize) -> Option<*mut c_void> {
+        if off < self.code_size() {
+            Some(self.bin.as_ptr().add(off) as *mut c_void)
+        } else {
+            None
+        }
+    }
+
+    /// address of the entry set by [`Context::set_entry_symbol`], or of `main` if not
+    /// set, e.g. to call it with argv built by the caller. None if `main` is not found.
+    pub fn entry_point(&self) -> Option<*mut c_void> {
+        self.entry
+            .or_else(|| self.symbol_addr(CStr::from_bytes_with_nul(b"main\0").unwrap()))
+    }
+
+    /// symbol count and code size, computed once at relocation.
+    pub fn stats(&self) -> ModuleStats {
+        self.stats
+    }
+
+    /// code and data sizes, and relocations applied, taken from tcc's sections at
+    /// relocation, e.g. to profile which sources are expensive to link.
+    ///
+    /// Sizes are exact, and exclude the padding between sections, so their sum is at
+    /// most [`code_size`](RelocatedCtx::code_size). Relocations include those of debug
+    /// info, e.g. with `-g`, and runtime support linked by tcc.
+    pub fn reloc_stats(&self) -> RelocStats {
+        self.reloc_stats
+    }
+
+    fn list_symbols(&self) -> Vec<(CString, *mut c_void)> {
+        let mut out: Vec<(CString, *mut c_void)> = Vec::new();
+        unsafe {
+            tcc_ext_list_symbols(
+                self.inner,
+                &mut out as *mut _ as *mut c_void,
+                Some(push_symbol),
+            )
+        };
+        out
+    }
+}
+
+impl Drop for RelocatedCtx<'_> {
+    fn drop(&mut self) {
+        unsafe { tcc_delete(self.inner) }
+    }
+}
+
+#[allow(clippy::string_lit_as_bytes)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, remove_dir_all, remove_file, write};
+    use std::mem::transmute;
+
+    #[test]
+    #[cfg(not(feature = "no-guard"))]
+    fn guard_multiple_creat() {
+        {
+            let _g1 = Guard::wait();
+            let g2 = Guard::new();
+            assert!(matches!(g2, Err(GuardError::AlreadyHeld)));
+        }
+        let _g3 = Guard::wait();
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-guard"))]
+    fn serial_tcc() {
+        let p = CString::new("int f(void){ return 1; }".as_bytes()).unwrap();
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let p = p.clone();
+                std::thread::spawn(move || {
+                    super::serial_tcc(|g| {
+                        let mut ctx = Context::new(g).unwrap();
+                        ctx.compile_string(&p).is_ok()
+                    })
+                })
+            })
+            .collect();
+        for t in threads {
+            assert!(t.join().unwrap());
+        }
+    }
+
+    #[test]
+    fn set_call_back() {
+        let err_p = CString::new("error".as_bytes()).unwrap();
+        let mut call_back_ret = None;
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_call_back(|_| call_back_ret = Some("called"));
+        assert!(ctx.compile_string(&err_p).is_err());
+        drop(ctx);
+        assert_eq!(call_back_ret, Some("called"));
+    }
+
+    #[test]
+    fn error_kind() {
+        let err_p = CString::new("error".as_bytes()).unwrap();
+        let p = CString::new("int f(void); int g(void){ return f(); }".as_bytes()).unwrap();
+        let mut g = Guard::wait();
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_call_back(|_| ());
+        let err = ctx.compile_string(&err_p).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Compile);
+        assert!(err.message().contains("error"));
+        let err = ctx.add_file("libtcc_missing.c").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Io);
+        drop(ctx);
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_call_back(|_| ())
+            .set_output_type(OutputType::Memory)
+            .unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        let err = ctx.relocate().err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::Link);
+        assert!(err.message().contains("'f'"));
+    }
+
+    #[test]
+    fn add_file_abs() {
+        let dir = temp_dir().join("libtcc_add_file_abs");
+        create_dir_all(&dir).unwrap();
+        let file = dir.join("abs.c");
+        write(&file, "int abs_test(void){ return 1; }").unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        let err = ctx.add_file_abs(dir.join("missing.c")).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Io);
+        assert!(err.message().contains("missing.c"));
+        ctx.add_file_abs(dir.join("../libtcc_add_file_abs/abs.c"))
+            .unwrap();
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn set_input_name() {
+        let err_p = CString::new("\nerror".as_bytes()).unwrap();
+        let name = CString::new("snippet_1.c".as_bytes()).unwrap();
+        let mut msg = None;
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_call_back(|m| msg = Some(m.to_str().unwrap().to_owned()))
+            .set_input_name(&name);
+        assert!(ctx.compile_string(&err_p).is_err());
+        drop(ctx);
+        assert!(msg.unwrap().starts_with("snippet_1.c:2:"));
+    }
+
+    #[test]
+    fn line_directive() {
+        let src = CString::new(
+            "int ok(void) { return 0; }\n#line 10 \"orig.tpl\"\nint f(void) { return x; }",
+        )
+        .unwrap();
+        let mut g = Guard::wait();
+        for name in [None, Some("gen.c")] {
+            let mut ctx = Context::new(&mut g).unwrap();
+            let diagnostics = ctx.diagnostics_channel();
+            if let Some(name) = name {
+                ctx.set_input_name(&CString::new(name).unwrap());
+            }
+            assert!(ctx.compile_string(&src).is_err());
+            drop(ctx);
+            let diag = diagnostics.try_iter().next().unwrap();
+            assert_eq!(diag.file.as_deref(), Some("orig.tpl"));
+            assert_eq!(diag.line, Some(10));
+        }
+    }
+
+    #[test]
+    fn invalid_input() {
+        let sym = CString::new("1abc".as_bytes()).unwrap();
+        let val = CString::new("1".as_bytes()).unwrap();
+        let opt = CString::new("-I".as_bytes()).unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_call_back(|_| ());
+        assert!(ctx.define_symbol(&sym, &val).is_err());
+        assert!(ctx.set_options(&opt).is_err());
+        assert!(ctx.add_include_path("a\0b").is_err());
+        assert!(ctx.add_file("a\0b.c").is_err());
+
+        let p = CString::new("int f(void){ return 1; }".as_bytes()).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+    }
+
+    #[test]
+    fn set_diagnostic_file() {
+        let p = CString::new("int f(void){ return }".as_bytes()).unwrap();
+        let log = temp_dir().join("libtcc_diagnostic.log");
+        let _ = remove_file(&log);
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_diagnostic_file(&log).unwrap();
+        assert!(ctx.compile_string(&p).is_err());
+        drop(ctx);
+        let content = std::fs::read_to_string(&log).unwrap();
+        assert!(content.contains("<string>:1: error:"));
+        assert!(content.ends_with('\n'));
+        remove_file(&log).unwrap();
+    }
+
+    #[test]
+    fn add_sys_include_path() {
+        let p = CString::new("#include<libtcc_test_0_9_27.h>").unwrap();
+        let header = "#define TEST";
+        let dir = temp_dir().join("libtcc_add_sys_include_path");
+        create_dir_all(&dir).unwrap();
+        write(dir.join("libtcc_test_0_9_27.h"), header).unwrap();
+
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        assert!(ctx
+            .add_sys_include_path(&dir)
+            .unwrap()
+            .compile_string(&p)
+            .is_ok());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn add_include_path() {
+        let p = CString::new("#include\"libtcc_test_0_9_27.h\"").unwrap();
+        let header = "#define TEST";
+        let dir = temp_dir().join("libtcc_add_include_path");
+        create_dir_all(&dir).unwrap();
+        write(dir.join("libtcc_test_0_9_27.h"), header).unwrap();
+
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        assert!(ctx
+            .add_include_path(&dir)
+            .unwrap()
+            .compile_string(&p)
+            .is_ok());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn set_sysroot() {
+        let p = CString::new(
+            r#"
+        #include <stddef.h>
+        #include <sysroot_test.h>
+        int f(){
+            return SYSROOT_TEST;
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+        let stdio = CString::new("#include <stdio.h>".as_bytes()).unwrap();
+        let sysroot = temp_dir().join("libtcc_sysroot");
+        create_dir_all(sysroot.join("usr/include")).unwrap();
+        write(
+            sysroot.join("usr/include/sysroot_test.h"),
+            "#define SYSROOT_TEST 1\n",
+        )
+        .unwrap();
+
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        let err = ctx.set_sysroot(sysroot.join("missing")).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::Io);
+        ctx.set_sysroot(&sysroot).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        assert!(ctx.compile_string(&stdio).is_err());
+        let err = ctx.set_sysroot(&sysroot).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        remove_dir_all(&sysroot).unwrap();
+    }
+
+    #[test]
+    fn lib_path() {
+        let p = CString::new("#include <lib_path_test.h>".as_bytes()).unwrap();
+        let dir = temp_dir().join("libtcc_lib_path");
+        create_dir_all(dir.join("include")).unwrap();
+        write(dir.join("include/lib_path_test.h"), "").unwrap();
+        let mut g = Guard::wait();
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_lib_path(&dir).unwrap();
+        assert_eq!(ctx.lib_path(), dir);
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        drop(ctx);
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        assert_ne!(ctx.lib_path(), dir);
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        assert!(ctx.compile_string(&p).is_err());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn prepend_include_path() {
+        let p = CString::new("#include \"prepend.h\"\nint f(void){ return PREPENDED; }".as_bytes())
+            .unwrap();
+        let dir = temp_dir().join("libtcc_prepend_include_path");
+        let (old, new) = (dir.join("old"), dir.join("new"));
+        create_dir_all(&old).unwrap();
+        create_dir_all(&new).unwrap();
+        write(old.join("prepend.h"), "#define PREPENDED 0\n").unwrap();
+        write(new.join("prepend.h"), "#define PREPENDED 1\n").unwrap();
+        let name = CString::new("prepend.h".as_bytes()).unwrap();
+        let f = CString::new("f".as_bytes()).unwrap();
+
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.add_include_path(&old).unwrap();
+        ctx.deny_includes(&["denied.h"]).unwrap();
+        ctx.prepend_include_path(&new).unwrap();
+        assert!(ctx.resolve_include(&name, true).unwrap().starts_with(&new));
+        write(new.join("denied.h"), "").unwrap();
+        let denied = CString::new("#include <denied.h>".as_bytes()).unwrap();
+        ctx.set_call_back(|_| ());
+        assert!(ctx.compile_string(&denied).is_err());
+        assert!(ctx.compile_string(&p).is_ok());
+        let mut relocated = ctx.relocate().unwrap();
+        let f: extern "C" fn() -> c_int = unsafe { relocated.get_function(&f).unwrap() };
+        assert_eq!(f(), 1);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resolve_include() {
+        let dir = temp_dir().join("libtcc_resolve_include");
+        let (inc, sys) = (dir.join("inc"), dir.join("sys"));
+        create_dir_all(&inc).unwrap();
+        create_dir_all(&sys).unwrap();
+        write(inc.join("both.h"), "").unwrap();
+        write(sys.join("both.h"), "").unwrap();
+        write(sys.join("sys_only.h"), "").unwrap();
+        let name = |n: &str| CString::new(n.as_bytes()).unwrap();
+
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.add_sys_include_path(&sys).unwrap();
+        ctx.add_include_path(&inc).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        let both = ctx.resolve_include(&name("both.h"), true).unwrap();
+        assert!(both.starts_with(&inc));
+        let sys_only = ctx.resolve_include(&name("sys_only.h"), false).unwrap();
+        assert!(sys_only.starts_with(&sys));
+        assert!(ctx.resolve_include(&name("stddef.h"), true).is_some());
+        assert!(ctx.resolve_include(&name("missing.h"), true).is_none());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn deny_includes() {
+        let p = CString::new(
+            r#"
+        #include <stdio.h>
+        void f(){
+            puts("hi");
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+        let ok = CString::new("#include <stddef.h>\nint g(void){ return 1; }".as_bytes()).unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        let err = ctx.deny_includes(&["../stdio.h"]).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        ctx.deny_includes(&["stdio.h", "sys/socket.h"]).unwrap();
+        let dir = ctx.deny_dir.clone().unwrap();
+        let err = ctx.compile_string(&p).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Compile);
+        assert!(err.message().contains("include of 'stdio.h' is denied"));
+        assert!(ctx.compile_string(&ok).is_ok());
+        drop(ctx);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn symbol_define() {
+        let p = CString::new(
+            r#"#ifdef TEST
+        typedef __unknown_type a1;
+        #endif
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+        let sym = CString::new("TEST".as_bytes()).unwrap();
+        let val = CString::new("1".as_bytes()).unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.define_symbol(&sym, &val).unwrap();
+        assert!(ctx.compile_string(&p).is_err());
+        ctx.undefine_symbol(&sym);
+        assert!(ctx.compile_string(&p).is_ok());
+    }
+
+    #[test]
+    fn define_display() {
+        let p = CString::new(
+            r#"
+        int answer(){
+            return ANSWER;
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+        let sym = CString::new("ANSWER".as_bytes()).unwrap();
+        let answer = CString::new("answer".as_bytes()).unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        ctx.define_display(&sym, &(6 * 7)).unwrap();
+        let err = ctx.define_display(&sym, "4\u{0}2").err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(ctx.compile_string(&p).is_ok());
+        let mut relocated = ctx.relocate().unwrap();
+        let answer: extern "C" fn() -> c_int = unsafe { relocated.get_function(&answer).unwrap() };
+        assert_eq!(answer(), 42);
+    }
+
+    #[test]
+    fn predefined_macros() {
+        let sym = CString::new("LIBTCC_TEST".as_bytes()).unwrap();
+        let val = CString::new("".as_bytes()).unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.define_symbol(&sym, &val).unwrap();
+        let macros = ctx.predefined_macros();
+        let find = |name: &str| {
+            macros
+                .iter()
+                .find(|(n, _)| n.to_str().unwrap() == name)
+                .map(|(_, v)| v.to_str().unwrap())
+        };
+        assert_eq!(find("__TINYC__"), Some("927"));
+        assert_eq!(find("LIBTCC_TEST"), Some(""));
+        assert_eq!(find("__builtin_extract_return_addr(x)"), Some("x"));
+    }
+
+    #[test]
+    fn dump_defines() {
+        let c = |s: &str| CString::new(s).unwrap();
+        let path = temp_dir().join("libtcc_dump_defines.h");
+        let mut g = Guard::wait();
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.define_symbol(&c("ANSWER"), &c("42"))
+            .unwrap()
+            .define_symbol(&c("EMPTY"), &c(""))
+            .unwrap()
+            .define_macro(&c("SQ(x)"), &c("((x) * (x))"))
+            .unwrap();
+        ctx.dump_defines(&path).unwrap();
+        let mut macros = ctx.predefined_macros();
+        drop(ctx);
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.load_defines(&path).unwrap();
+        let mut loaded = ctx.predefined_macros();
+        macros.sort();
+        loaded.sort();
+        assert_eq!(macros, loaded);
+        ctx.compile_string(&c(
+            "#ifndef EMPTY\n#error\n#endif\nint f(void) { return SQ(ANSWER) EMPTY; }",
+        ))
+        .unwrap();
+        drop(ctx);
+
+        fs::write(&path, "#define OK 1\n\n#undef OK\n").unwrap();
+        let mut ctx = Context::new(&mut g).unwrap();
+        let err = ctx.load_defines(&path).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(err.message().contains(":3:"), "{}", err.message());
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn default_output_type() {
+        let p = CString::new("int add(int a, int b){ return a+b; }".as_bytes()).unwrap();
+        let add = CString::new("add".as_bytes()).unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        assert_eq!(ctx.output_type(), None);
+        assert!(ctx.compile_string(&p).is_ok());
+        assert_eq!(ctx.output_type(), Some(OutputType::Memory));
+        let mut relocated = ctx.relocate().unwrap();
+        let add: extern "C" fn(c_int, c_int) -> c_int =
+            unsafe { relocated.get_function(&add).unwrap() };
+        assert_eq!(add(1, 2), 3);
+        drop(relocated);
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Obj).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        assert_eq!(ctx.output_type(), Some(OutputType::Obj));
+    }
+
+    #[test]
+    fn set_output_type_twice() {
+        let p = CString::new("int f(void){ return 1; }".as_bytes()).unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        assert!(ctx.set_output_type(OutputType::Memory).is_ok());
+        let err = ctx.set_output_type(OutputType::Obj).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert_eq!(ctx.output_type(), Some(OutputType::Memory));
+        assert!(ctx.relocate().is_ok());
+    }
+
+    #[test]
+    fn change_output_type_before_compile() {
+        let p = CString::new("int f(void){ return 1; }").unwrap();
+        let f = CString::new("f").unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        // Exe would load crt1.o, whose _start references main.
+        ctx.set_output_type(OutputType::Exe)
+            .unwrap()
+            .set_output_type(OutputType::Memory)
+            .unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        let mut relocated = ctx.relocate().unwrap();
+        let f: extern "C" fn() -> c_int = unsafe { relocated.get_function(&f).unwrap() };
+        assert_eq!(f(), 1);
+    }
+
+    #[test]
+    fn output_exe_file() {
+        let p = CString::new(
+            r#"
+        #include<stdio.h>
+        int main(int argc, char **argv){
+            printf("hello world");
+            return 0;
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Exe).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        let dir = temp_dir();
+        let exe = dir.join("a.out");
+        ctx.output_file(&exe).unwrap();
+        assert!(exe.exists());
+        remove_file(&exe).unwrap();
+    }
+
+    #[test]
+    fn build_executable() {
+        let src = b"int main(void){ return 3; }";
+        let exe = temp_dir().join("libtcc_build_executable.out");
+
+        let mut g = Guard::wait();
+        let ctx = Context::new(&mut g).unwrap();
+        ctx.build_executable(src, &exe).unwrap();
+        assert!(exe.exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = exe.metadata().unwrap().permissions().mode();
+            assert_ne!(mode & 0o111, 0);
+            let status = std::process::Command::new(&exe).status().unwrap();
+            assert_eq!(status.code(), Some(3));
+        }
+        remove_file(&exe).unwrap();
+
+        let ctx = Context::new(&mut g).unwrap();
+        let err = ctx.build_executable(b"int main(void){ return x; }", &exe);
+        assert_eq!(err.unwrap_err().kind(), ErrorKind::Compile);
+        assert!(!exe.exists());
+    }
+
+    #[test]
+    fn add_shared_symbols() {
+        let c = |s: &str| CString::new(s).unwrap();
+        let mut shared = SharedSymbols::new();
+        let counter = shared.add_global(&c("counter"), 40 as c_int);
+        assert_eq!(shared.get(&c("counter")), Some(counter as *const c_void));
+        assert_eq!(
+            shared.names().collect::<Vec<_>>(),
+            [c("counter").as_c_str()]
+        );
+        let mut g = Guard::wait();
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.add_shared_symbols(&shared);
+        ctx.compile_string(&c("extern int counter; void inc(void){ counter++; }"))
+            .unwrap();
+        let mut a = ctx.relocate().unwrap();
+        let inc: extern "C" fn() = unsafe { a.get_function(&c("inc")).unwrap() };
+        inc();
+        inc();
+        drop(a);
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.add_shared_symbols(&shared);
+        ctx.compile_string(&c("extern int counter; int get(void){ return counter; }"))
+            .unwrap();
+        let mut b = ctx.relocate().unwrap();
+        let get: extern "C" fn() -> c_int = unsafe { b.get_function(&c("get")).unwrap() };
+        assert_eq!(get(), 42);
+        // the storage is kept by the module.
+        drop(shared);
+        unsafe { *counter = 7 };
+        assert_eq!(get(), 7);
+    }
+
+    #[test]
+    fn set_dep_output() {
+        let dir = temp_dir().join("libtcc_dep_output");
+        let _ = remove_dir_all(&dir);
+        create_dir_all(dir.join("my inc")).unwrap();
+        write(dir.join("my inc/answer.h"), "#define ANSWER 42\n").unwrap();
+        write(
+            dir.join("a.c"),
+            "#include <answer.h>\n#include <stddef.h>\nint answer(void){ return ANSWER; }\n",
+        )
+        .unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Obj).unwrap();
+        ctx.add_include_path(dir.join("my inc")).unwrap();
+        ctx.set_dep_output(dir.join("a.d"));
+        ctx.add_file(dir.join("a.c")).unwrap();
+        ctx.output_file(dir.join("a.o")).unwrap();
+
+        let rule = fs::read_to_string(dir.join("a.d")).unwrap();
+        let target = format!("{}: \\\n", dir.join("a.o").display());
+        assert!(rule.starts_with(&target), "{}", rule);
+        assert!(rule.contains("my\\ inc/answer.h"), "{}", rule);
+        let deps = Context::read_deps(dir.join("a.d")).unwrap();
+        assert_eq!(deps[0], dir.join("a.c"));
+        assert_eq!(deps[1], dir.join("my inc/answer.h"));
+        assert!(deps.iter().any(|d| d.ends_with("stddef.h")), "{:?}", deps);
+
+        write(dir.join("b.d"), "b.o c.o: b.c \\\n  cost$$.h\nc.o: b.c\n").unwrap();
+        let deps = Context::read_deps(dir.join("b.d")).unwrap();
+        assert_eq!(deps, [PathBuf::from("b.c"), PathBuf::from("cost$.h")]);
+        write(dir.join("bad.d"), "b.o b.c\n").unwrap();
+        let err = Context::read_deps(dir.join("bad.d")).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn set_base_dir() {
+        let dir = temp_dir().join("libtcc_base_dir");
+        let _ = remove_dir_all(&dir);
+        create_dir_all(dir.join("inc")).unwrap();
+        create_dir_all(dir.join("src")).unwrap();
+        write(dir.join("inc/answer.h"), "#define ANSWER 42\n").unwrap();
+        write(
+            dir.join("src/a.c"),
+            "#include <answer.h>\nint answer(void){ return ANSWER; }\n",
+        )
+        .unwrap();
+        let mut g = Guard::wait();
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        assert_eq!(ctx.base_dir(), None);
+        let err = ctx.set_base_dir("relative").err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        ctx.set_base_dir(&dir).unwrap();
+        assert_eq!(ctx.base_dir(), Some(dir.as_path()));
+        ctx.set_output_type(OutputType::Obj).unwrap();
+        ctx.add_include_path("inc").unwrap();
+        ctx.add_file("src/a.c").unwrap();
+        ctx.output_file("a.o").unwrap();
+        assert!(dir.join("a.o").is_file());
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_base_dir(&dir).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        ctx.add_file("a.o").unwrap();
+        let mut r = ctx.relocate().unwrap();
+        let answer: extern "C" fn() -> c_int =
+            unsafe { r.get_function(&CString::new("answer").unwrap()).unwrap() };
+        assert_eq!(answer(), 42);
+        drop(r);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recognize_pragma() {
+        let p = CString::new(
+            "#pragma export add\n#pragma meta v=1\nint add(int a, int b){ return a + b; }",
+        )
+        .unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        ctx.set_call_back(move |msg| sender.send(msg.to_string_lossy().into_owned()).unwrap());
+        ctx.set_options(&CString::new("-Wunsupported").unwrap())
+            .unwrap();
+        ctx.recognize_pragma("export", true)
+            .recognize_pragma("meta", false);
+        ctx.compile_string(&p).unwrap();
+        assert_eq!(
+            ctx.collected_pragmas(),
+            [
+                Pragma {
+                    name: "export".into(),
+                    args: "add".into(),
+                    line: 1
+                },
+                Pragma {
+                    name: "meta".into(),
+                    args: "v=1".into(),
+                    line: 2
+                },
+            ]
+        );
+        let warnings: Vec<String> = receiver.try_iter().collect();
+        assert!(
+            warnings.iter().any(|w| w.contains("meta")),
+            "{:?}",
+            warnings
+        );
+        assert!(
+            !warnings.iter().any(|w| w.contains("export")),
+            "{:?}",
+            warnings
+        );
+        let mut r = ctx.relocate().unwrap();
+        assert!(unsafe { r.get_symbol(&CString::new("add").unwrap()) }.is_some());
+    }
+
+    #[test]
+    fn on_phase() {
+        let p = CString::new("int f(void){ return 1; }").unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        ctx.on_phase(move |phase| sender.send(phase).unwrap());
+        ctx.preprocess_to_writer(b"int x;", &mut Vec::new())
+            .unwrap();
+        ctx.compile_string(&p).unwrap();
+        ctx.compile_asm(b".globl g\ng: ret\n").unwrap();
+        drop(ctx.relocate().unwrap());
+        use Phase::*;
+        let phases: Vec<Phase> = receiver.try_iter().collect();
+        assert_eq!(phases, [Preprocess, Compile, Compile, Link, Relocate]);
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        ctx.on_phase(move |phase| sender.send(phase).unwrap());
+        ctx.set_output_type(OutputType::Obj).unwrap();
+        assert!(ctx
+            .compile_string(&CString::new("int f(void){ return x; }").unwrap())
+            .is_err());
+        let obj = temp_dir().join("libtcc_on_phase.o");
+        ctx.output_file(&obj).unwrap();
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), [Compile]);
+        let _ = remove_file(&obj);
+    }
+
+    #[test]
+    fn use_libc() {
+        let p = CString::new("#include <stdio.h>\nint main(void){ return puts(\"\") == EOF; }")
+            .unwrap();
+        let mut g = Guard::wait();
+        if find_libc(Libc::Glibc).is_ok() {
+            let mut ctx = Context::new(&mut g).unwrap();
+            ctx.use_libc(Libc::Glibc).unwrap();
+            assert!(ctx.effective_options().contains("-nostdinc -isystem"));
+            ctx.compile_string(&p).unwrap();
+            assert!(ctx
+                .resolve_include(&CString::new("stdio.h").unwrap(), true)
+                .is_some());
+            assert_eq!(ctx.run(&[]).unwrap(), 0);
+        }
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        match ctx.use_libc(Libc::Musl) {
+            Ok(_) => {
+                ctx.set_output_type(OutputType::Obj).unwrap();
+                ctx.compile_string(&p).unwrap();
+            }
+            Err(e) => {
+                assert_eq!(e.kind(), ErrorKind::Io);
+                assert!(e.message().contains("Musl not found"), "{}", e.message());
+                assert!(e.message().contains("crt1.o"), "{}", e.message());
+            }
+        }
+        drop(ctx);
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Obj).unwrap();
+        let err = ctx.use_libc(Libc::Glibc).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn output_file_ref() {
+        let src =
+            CString::new("int three(void){ return 3; } int main(void){ return three(); }").unwrap();
+        let dir = temp_dir().join("libtcc_output_file_ref");
+        create_dir_all(&dir).unwrap();
+        let obj = dir.join("a.o");
+        let exe = dir.join("a.out");
+        let mut g = Guard::wait();
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Obj).unwrap();
+        ctx.compile_string(&src).unwrap();
+        ctx.output_file_ref(&obj, OutputType::Obj).unwrap();
+        let err = ctx.output_file_ref(&obj, OutputType::Obj).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(ctx.output_file_ref(&obj, OutputType::Memory).is_err());
+        ctx.output_file_ref(&exe, OutputType::Exe).unwrap();
+        assert_eq!(ctx.output_type(), Some(OutputType::Exe));
+        assert!(matches!(
+            ctx.config().settings.last(),
+            Some(Setting::OutputType(OutputType::Exe))
+        ));
+        let err = ctx.output_file_ref(&exe, OutputType::Exe).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(ctx.relocate().is_err());
+        #[cfg(unix)]
+        {
+            let status = std::process::Command::new(&exe).status().unwrap();
+            assert_eq!(status.code(), Some(3));
+        }
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        ctx.add_file(&obj).unwrap();
+        assert_eq!(ctx.run(&[]).unwrap(), 3);
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Obj).unwrap();
+        ctx.compile_string(&src).unwrap();
+        ctx.output_file_ref(&obj, OutputType::Obj).unwrap();
+        let mut r = ctx.relocate().unwrap();
+        let three: extern "C" fn() -> c_int =
+            unsafe { r.get_function(&CString::new("three").unwrap()).unwrap() };
+        assert_eq!(three(), 3);
+        drop(r);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn set_source_transform() {
+        let fn_to_int = |src: &[u8]| {
+            String::from_utf8_lossy(src)
+                .replace("fn ", "int ")
+                .into_bytes()
+        };
+        let dir = temp_dir().join("libtcc_source_transform");
+        create_dir_all(&dir).unwrap();
+        let file = dir.join("sub.c");
+        write(&file, "fn sub(int a, int b) { return a - b; }\n").unwrap();
+        let mut g = Guard::wait();
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_source_transform(fn_to_int);
+        ctx.compile_string(&CString::new("fn add(int a, int b) { return a + b; }").unwrap())
+            .unwrap();
+        ctx.add_file(&file).unwrap();
+        let mut r = ctx.relocate().unwrap();
+        let add: extern "C" fn(c_int, c_int) -> c_int =
+            unsafe { r.get_function(&CString::new("add").unwrap()).unwrap() };
+        let sub: extern "C" fn(c_int, c_int) -> c_int =
+            unsafe { r.get_function(&CString::new("sub").unwrap()).unwrap() };
+        assert_eq!(add(1, 2), 3);
+        assert_eq!(sub(5, 2), 3);
+        drop(r);
+
+        // diagnostics refer to the transformed source.
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_source_transform(|src: &[u8]| [&b"\n\n"[..], src].concat());
+        let err = ctx
+            .compile_string(&CString::new("int f(void) { return x; }").unwrap())
+            .unwrap_err();
+        assert!(err.message().contains(":3:"), "{}", err.message());
+        ctx.set_source_transform(|_: &[u8]| b"\0".to_vec());
+        let err = ctx.compile_string(&CString::new("").unwrap()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        drop(ctx);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn install_libc_shims() {
+        let src = CString::new(
+            r#"
+        void *malloc(unsigned long size);
+        void free(void *p);
+        unsigned long strlen(const char *s);
+        int printf(const char *fmt, ...);
+        int sum(int n) {
+            int *a = malloc(n * sizeof(int)), s = 0, i;
+            for (i = 0; i < n; i++)
+                a[i] = i + 1;
+            for (i = 0; i < n; i++)
+                s += a[i];
+            free(a);
+            printf("sum of %d: %d\n", n, s);
+            return s;
+        }
+        unsigned long len(const char *s) { return strlen(s); }
+        "#,
+        )
+        .unwrap();
+        extern "C" fn fake_strlen(_: *const c_char) -> usize {
+            42
+        }
+        let mut g = Guard::wait();
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.no_std_lib().unwrap();
+        let mut shims = LibcShims::default();
+        unsafe {
+            shims.set(
+                &CString::new("strlen").unwrap(),
+                fake_strlen as *const c_void,
+            )
+        };
+        ctx.install_libc_shims(shims);
+        ctx.compile_string(&src).unwrap();
+        let mut r = ctx.relocate().unwrap();
+        let sum: extern "C" fn(c_int) -> c_int =
+            unsafe { r.get_function(&CString::new("sum").unwrap()).unwrap() };
+        let len: extern "C" fn(*const c_char) -> usize =
+            unsafe { r.get_function(&CString::new("len").unwrap()).unwrap() };
+        LibcShims::take_output();
+        assert_eq!(sum(100), 5050);
+        assert_eq!(LibcShims::take_output(), b"sum of 100: 5050\n");
+        assert_eq!(len(b"abc\0".as_ptr() as *const c_char), 42);
+        drop(r);
+
+        // removed shims are left to libc, i.e. printf writes to stdout.
+        let mut ctx = Context::new(&mut g).unwrap();
+        let mut shims = LibcShims::default();
+        shims.remove(&CString::new("printf").unwrap());
+        assert!(shims.names().all(|n| n.to_bytes() != b"printf"));
+        ctx.install_libc_shims(shims);
+        ctx.compile_string(&src).unwrap();
+        let mut r = ctx.relocate().unwrap();
+        let sum: extern "C" fn(c_int) -> c_int =
+            unsafe { r.get_function(&CString::new("sum").unwrap()).unwrap() };
+        assert_eq!(sum(3), 6);
+        assert!(LibcShims::take_output().is_empty());
+    }
+
+    #[test]
+    fn reloc_stats() {
+        let stats = |src: &str| {
+            let mut g = Guard::wait();
+            let mut ctx = Context::new(&mut g).unwrap();
+            ctx.compile_string(&CString::new(src).unwrap()).unwrap();
+            let r = ctx.relocate().unwrap();
+            let stats = r.reloc_stats();
+            assert!(stats.code_size > 0);
+            assert!(stats.code_size + stats.data_size <= r.code_size());
+            stats
+        };
+        let one = stats("int data[64] = {1}; int get(void) { return data[0]; }");
+        assert!(one.data_size >= 64 * std::mem::size_of::<c_int>());
+        let more = stats(
+            "int data[64] = {1}; int *p = &data[1];
+            int get(void) { return data[0] + data[1] + *p; }",
+        );
+        assert!(more.reloc_count > one.reloc_count, "{:?} {:?}", one, more);
+    }
+
+    #[test]
+    fn relocate_aligned() {
+        let src = CString::new("int data[64] = {1}; int get(int i) { return data[i]; }").unwrap();
+        let mut g = Guard::wait();
+
+        for align in [MIN_CODE_ALIGN, 64, 4096] {
+            let mut ctx = Context::new(&mut g).unwrap();
+            ctx.compile_string(&src).unwrap();
+            let mut r = ctx.relocate_aligned(align).unwrap();
+            assert_eq!(r.base_ptr() as usize % align, 0);
+            let get: extern "C" fn(c_int) -> c_int =
+                unsafe { r.get_function(&CString::new("get").unwrap()).unwrap() };
+            assert_eq!(get(0), 1);
+            assert_eq!(get(63), 0);
+        }
+
+        for align in [0, 3, 1] {
+            let mut ctx = Context::new(&mut g).unwrap();
+            ctx.compile_string(&src).unwrap();
+            let err = ctx.relocate_aligned(align).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn relocate_retry() {
+        let src = CString::new("int data[64] = {1}; int get(int i) { return data[i]; }").unwrap();
+        let mut g = Guard::wait();
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.compile_string(&src).unwrap();
+        ctx.relocation_size().unwrap();
+        // a buffer too small is replaced before tcc writes to it.
+        let mut r = ctx.relocate_with(1, MIN_CODE_ALIGN).unwrap();
+        assert!(r.code_size() > 1);
+        let get: extern "C" fn(c_int) -> c_int =
+            unsafe { r.get_function(&CString::new("get").unwrap()).unwrap() };
+        assert_eq!(get(0), 1);
+        assert_eq!(get(63), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn compile_dll_and_load() {
+        let src = br#"
+            int counter = 40;
+            static int step(void) { return 2; }
+            int next(void) { return counter += step(); }
+        "#;
+        let mut g = Guard::wait();
+
+        let ctx = Context::new(&mut g).unwrap();
+        let mut r = ctx.compile_dll_and_load(src).unwrap();
+        let next: extern "C" fn() -> c_int =
+            unsafe { r.get_function(&CString::new("next").unwrap()).unwrap() };
+        assert_eq!(next(), 42);
+        assert_eq!(
+            unsafe { r.read_global::<c_int>(&CString::new("counter").unwrap()) },
+            Some(42)
+        );
+        // static symbols are not exported by the library.
+        assert!(unsafe { r.get_symbol(&CString::new("step").unwrap()) }.is_none());
+        drop(r);
+
+        let ctx = Context::new(&mut g).unwrap();
+        let err = ctx.compile_dll_and_load(b"int f(void) { return }");
+        assert_eq!(err.err().unwrap().kind(), ErrorKind::Compile);
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    #[test]
+    fn set_default_visibility() {
+        let module = |name: &str, n: i32| {
+            format!(
+                "int helper(void) {{ return {}; }}
+                __attribute__((visibility(\"protected\"))) int {}(void) {{ return helper(); }}",
+                n, name
+            )
+        };
+        let c = |s: &str| CString::new(s).unwrap();
+        let mut g = Guard::wait();
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_default_visibility(Visibility::Hidden);
+        let mut a = ctx.compile_dll_and_load(module("a", 1).as_bytes()).unwrap();
+        // both libraries are loaded into the global namespace, each calls its own helper.
+        let mut ctx = unsafe { Context::new_unguarded() }.unwrap();
+        ctx.set_default_visibility(Visibility::Hidden);
+        let mut b = ctx.compile_dll_and_load(module("b", 2).as_bytes()).unwrap();
+        let fa: extern "C" fn() -> c_int = unsafe { a.get_function(&c("a")).unwrap() };
+        let fb: extern "C" fn() -> c_int = unsafe { b.get_function(&c("b")).unwrap() };
+        assert_eq!((fa(), fb()), (1, 2));
+        assert!(unsafe { b.get_symbol(&c("helper")) }.is_none());
+        drop((a, b));
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_default_visibility(Visibility::Hidden);
+        ctx.compile_string(&c(&module("a", 1))).unwrap();
+        let mut r = ctx.relocate().unwrap();
+        assert_eq!(r.symbols(), [c("a")]);
+        assert!(unsafe { r.get_symbol(&c("helper")) }.is_some());
+    }
+
+    #[test]
+    fn compile_memory_used() {
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        let empty = ctx.compile_memory_used();
+        ctx.compile_string(&CString::new("int f(void) { return 0; }").unwrap())
+            .unwrap();
+        let small = ctx.compile_memory_used();
+        assert!(small > empty);
+        let big = format!("int big[] = {{ {} }};", "1,".repeat(100_000));
+        ctx.compile_string(&CString::new(big).unwrap()).unwrap();
+        assert!(ctx.compile_memory_used() >= small + 100_000 * 4);
+    }
+
+    #[test]
+    fn add_source_dir() {
+        let dir = temp_dir().join("libtcc_add_source_dir");
+        let _ = remove_dir_all(&dir);
+        create_dir_all(dir.join("sub")).unwrap();
+        write(dir.join("b.c"), "int b(void) { return 2; }").unwrap();
+        write(dir.join("a.c"), "int a(void) { return 1; }").unwrap();
+        write(dir.join("a.h"), "int a(void);").unwrap();
+        write(dir.join("sub/c.c"), "int c(void) { return 3; }").unwrap();
+        let mut g = Guard::wait();
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        ctx.set_verbosity(2);
+        let (sender, receiver) = mpsc::channel();
+        ctx.set_call_back(move |msg| sender.send(msg.to_string_lossy().into_owned()).unwrap());
+        assert_eq!(ctx.add_source_dir(&dir, false).unwrap(), 2);
+        let read: Vec<String> = receiver.try_iter().filter(|m| m.contains("-> ")).collect();
+        assert!(
+            read[0].ends_with("a.c") && read[1].ends_with("b.c"),
+            "{:?}",
+            read
+        );
+        let mut r = ctx.relocate().unwrap();
+        assert!(unsafe { r.get_symbol(&CString::new("b").unwrap()) }.is_some());
+        assert!(unsafe { r.get_symbol(&CString::new("c").unwrap()) }.is_none());
+        drop(r);
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        assert_eq!(ctx.add_source_dir(&dir, true).unwrap(), 3);
+        drop(ctx);
+
+        write(dir.join("sub/bad.c"), "int bad(void) { return }").unwrap();
+        let mut ctx = Context::new(&mut g).unwrap();
+        let err = ctx.add_source_dir(&dir, true).unwrap_err();
+        assert!(err.message().contains("bad.c"), "{}", err.message());
+        assert!(ctx.add_source_dir(dir.join("none"), true).is_err());
+        drop(ctx);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn object_symbols() {
+        let src = CString::new(
+            "extern int ext(void); int g; static int h; int f(void) { return ext() + h; }",
+        )
+        .unwrap();
+        let mut g = Guard::wait();
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Obj).unwrap();
+        assert!(ctx.object_symbols().is_err());
+        ctx.compile_string(&src).unwrap();
+        let syms = ctx.object_symbols().unwrap();
+        let names = |v: &[CString]| {
+            v.iter()
+                .map(|s| s.to_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&syms.defined), ["g", "f"]);
+        assert_eq!(names(&syms.undefined), ["ext"]);
+    }
+
+    #[test]
+    fn output_lib() {
+        let p = CString::new(
+            r#"
+        int add(int a, int b){
+            return a+b;
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Dll).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        let dir = temp_dir();
+        let lib = dir.join("lib");
+        ctx.output_file(&lib).unwrap();
+        assert!(lib.exists());
+        remove_file(&lib).unwrap();
+    }
+
+    #[test]
+    #[cfg(not(target_env = "msvc"))]
+    fn from_dll() {
+        let p = CString::new(
+            r#"
+        int add(int a, int b){
+            return a+b;
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+        let add = CString::new("add".as_bytes()).unwrap();
+
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Dll).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        let lib = temp_dir().join("libfrom_dll.so");
+        ctx.output_file(&lib).unwrap();
+
+        let mut relocated = RelocatedCtx::from_dll(&mut g, &lib).unwrap();
+        let add: extern "C" fn(c_int, c_int) -> c_int =
+            unsafe { relocated.get_function(&add).unwrap() };
+        assert_eq!(add(1, 2), 3);
+        drop(relocated);
+        remove_file(&lib).unwrap();
+
+        let err = RelocatedCtx::from_dll(&mut g, "/not/exist.so")
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::Io);
+    }
+
+    #[test]
+    fn stats() {
+        let p = CString::new(
+            r#"
+        int counter;
+        static int helper(int a){
+            return a;
+        }
+        int add(int a, int b){
+            return helper(a)+b;
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        let relocated = ctx.relocate().unwrap();
+        let symbols = relocated.symbols();
+        let has = |name: &str| symbols.iter().any(|s| s.to_str().unwrap() == name);
+        assert!(has("add") && has("counter"));
+        assert!(!has("helper"));
+        let mut relocated = relocated;
+        let map = relocated.symbol_map();
+        assert_eq!(map.len(), symbols.len());
+        assert!(relocated.duplicate_symbols().is_empty());
+        let add = CString::new("add".as_bytes()).unwrap();
+        assert_eq!(map[&add], unsafe { relocated.get_symbol(&add).unwrap() });
+        let stats = relocated.stats();
+        assert_eq!(stats.symbol_count, symbols.len());
+        assert_eq!(stats.code_bytes, relocated.code_size());
+        assert!(stats.code_bytes > 0);
+    }
+
+    #[test]
+    fn relocate_declarations_only() {
+        let p = CString::new("int add(int a, int b); extern int counter;".as_bytes()).unwrap();
+        let add = CString::new("add".as_bytes()).unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        let mut relocated = ctx.relocate().unwrap();
+        assert!(relocated.symbols().is_empty());
+        assert!(unsafe { relocated.get_symbol(&add) }.is_none());
+        assert_eq!(relocated.code_bytes().len(), relocated.code_size());
+        // tcc reserves some bytes even then, the empty buffer is only reached directly.
+        assert!(relocated.code_size() > 0);
+        let empty = CodeBuf::new(0, 64).unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(empty.as_ptr() as usize % 64, 0);
+        drop(empty);
+    }
+
+    #[test]
+    fn ptr_at_offset() {
+        let p = CString::new("int add(int a, int b){ return a+b; }".as_bytes()).unwrap();
+        let add = CString::new("add".as_bytes()).unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        let mut relocated = ctx.relocate().unwrap();
+        let addr = unsafe { relocated.get_symbol(&add).unwrap() };
+        let off = addr as usize - relocated.base_ptr() as usize;
+        let add: extern "C" fn(c_int, c_int) -> c_int =
+            unsafe { FnPtr::from_ptr(relocated.ptr_at_offset(off).unwrap()) };
+        assert_eq!(add(1, 2), 3);
+        assert!(unsafe { relocated.ptr_at_offset(relocated.code_size()) }.is_none());
+    }
+
+    #[test]
+    fn code_bytes() {
+        let p = CString::new("int add(int a, int b){ return a+b; }".as_bytes()).unwrap();
+        let add = CString::new("add".as_bytes()).unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        let mut relocated = ctx.relocate().unwrap();
+        let addr = unsafe { relocated.get_symbol(&add).unwrap() };
+        let code = relocated.code_bytes();
+        assert_eq!(code.len(), relocated.code_size());
+        assert_eq!(code.as_ptr() as *const c_void, relocated.base_ptr());
+        let off = addr as usize - relocated.base_ptr() as usize;
+        assert!(code[off..].iter().any(|&b| b != 0));
+    }
+
+    #[test]
+    fn output_obj() {
+        let p = CString::new(
+            r#"
+        int add(int a, int b){
+            return a+b;
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Obj).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        let dir = temp_dir();
+        let obj = dir.join("obj");
+        ctx.output_file(&obj).unwrap();
+        assert!(obj.exists());
+        remove_file(&obj).unwrap();
+    }
+
+    #[test]
+    fn werror() {
+        let p = CString::new("int f(void){ int a = \"x\"; return a; }".as_bytes()).unwrap();
+        let mut g = Guard::wait();
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_call_back(|_| ());
+        assert!(ctx.compile_string(&p).is_ok());
+        drop(ctx);
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_call_back(|_| ()).werror(true);
+        let err = ctx.compile_string(&p).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Compile);
+        assert!(err.message().contains("warning"));
+        drop(ctx);
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_call_back(|_| ()).werror(true).werror(false);
+        assert!(ctx.compile_string(&p).is_ok());
+    }
+
+    #[test]
+    fn strict_decl() {
+        let p = CString::new("int f(void){ return g(); }".as_bytes()).unwrap();
+        let mut g = Guard::wait();
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_call_back(|_| ());
+        assert!(ctx.compile_string(&p).is_ok());
+        drop(ctx);
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_call_back(|_| ()).strict_decl();
+        let err = ctx.compile_string(&p).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Compile);
+        assert!(err
+            .message()
+            .contains("implicit declaration of function 'g'"));
+    }
+
+    #[test]
+    fn compile_timed() {
+        let p = CString::new("int f(void){ return 1; }".as_bytes()).unwrap();
+        let bad = CString::new("int g(void){ return }".as_bytes()).unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        assert!(ctx.compile_timed(&p).unwrap() > Duration::ZERO);
+        assert_eq!(
+            ctx.compile_timed(&bad).unwrap_err().kind(),
+            ErrorKind::Compile
+        );
+    }
+
+    #[test]
+    fn compile_dual() {
+        let p = CString::new(
+            r#"
+        #include <string.h>
+        int counter = 40;
+        static int inc(void){
+            return counter += strlen("a");
+        }
+        int next(void){
+            return inc();
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+        let next = CString::new("next".as_bytes()).unwrap();
+        let obj = temp_dir().join("libtcc_dual.o");
+        let mut g = Guard::wait();
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        let err = ctx.compile_dual(&obj).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Obj).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        let mut relocated = ctx.compile_dual(&obj).unwrap();
+        let f: extern "C" fn() -> c_int = unsafe { relocated.get_function(&next).unwrap() };
+        assert_eq!(f(), 41);
+        assert_eq!(f(), 42);
+        drop(relocated);
+
+        let ctx = Context::link_objects(&mut g, &[&obj], OutputType::Memory).unwrap();
+        let mut relocated = ctx.relocate().unwrap();
+        let f: extern "C" fn() -> c_int = unsafe { relocated.get_function(&next).unwrap() };
+        assert_eq!(f(), 41);
+        drop(relocated);
+        remove_file(&obj).unwrap();
+    }
+
+    #[test]
+    fn link_objects() {
+        let p = CString::new(
+            r#"
+        int add(int a, int b){
+            return a+b;
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+        let p2 = CString::new(
+            r#"
+        int add(int a, int b);
+        int add2(int a, int b){
+            return add(a, b) + add(a, b);
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+        let sym2 = CString::new("add2".as_bytes()).unwrap();
+        let dir = temp_dir();
+        let objs = [dir.join("libtcc_add.o"), dir.join("libtcc_add2.o")];
+
+        let mut g = Guard::wait();
+        for (obj, p) in objs.iter().zip([&p, &p2].iter()) {
+            let mut ctx = Context::new(&mut g).unwrap();
+            ctx.set_output_type(OutputType::Obj).unwrap();
+            assert!(ctx.compile_string(p).is_ok());
+            ctx.output_file(obj).unwrap();
+        }
+
+        let missing = dir.join("libtcc_missing.o");
+        let err = Context::link_objects(&mut g, &[&objs[0], &missing], OutputType::Memory)
+            .err()
+            .unwrap();
+        assert!(err.message().contains("libtcc_missing.o"));
+
+        let ctx = Context::link_objects(&mut g, &objs, OutputType::Memory).unwrap();
+        let mut relocated = ctx.relocate().unwrap();
+        let add2: extern "C" fn(c_int, c_int) -> c_int =
+            unsafe { transmute(relocated.get_symbol(&sym2).unwrap()) };
+        assert_eq!(add2(1, 1), 4);
+        drop(relocated);
+        for obj in objs.iter() {
+            remove_file(obj).unwrap();
+        }
+    }
+
+    #[test]
+    fn run_func() {
+        let p = CString::new(
+            r#"
+        int add(int a, int b){
+            return a+b;
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+        let sym = CString::new("add".as_bytes()).unwrap();
+
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        let mut relocated = ctx.relocate().unwrap();
+
+        let add: fn(c_int, c_int) -> c_int =
+            unsafe { transmute(relocated.get_symbol(&sym).unwrap()) };
+        assert_eq!(add(1, 1), 2);
+    }
+
+    #[test]
+    fn add_symbol() {
+        let p = CString::new(
+            r#"
+        int add(int a, int b){
+            return a+b;
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+        let sym = CString::new("add".as_bytes()).unwrap();
+        let p2 = CString::new(
+            r#"
+        int add(int a, int b);
+        int add2(int a, int b){
+            return add(a, b) + add(a, b);
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+        let sym2 = CString::new("add2".as_bytes()).unwrap();
+
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        let mut relocated = ctx.relocate().unwrap();
+        let add = unsafe { relocated.get_symbol(&sym).unwrap() };
+
+        // g is borrowed by relocated, and held until the end.
+        let mut ctx2 = unsafe { Context::new_unguarded() }.unwrap();
+        ctx2.set_output_type(OutputType::Memory).unwrap();
+        assert!(ctx2.compile_string(&p2).is_ok());
+        unsafe {
+            ctx2.add_symbol(&sym, add);
+        }
+        let mut relocated = ctx2.relocate().unwrap();
+        let add2: fn(c_int, c_int) -> c_int =
+            unsafe { transmute(relocated.get_symbol(&sym2).unwrap()) };
+
+        assert_eq!(add2(1, 1), 4);
     }
-}
 
-#[allow(clippy::string_lit_as_bytes)]
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::env::temp_dir;
-    use std::fs::{remove_file, write};
-    use std::intrinsics::transmute;
+    #[test]
+    fn add_symbols() {
+        let p = CString::new(
+            r#"
+        int host_add(int a, int b);
+        int host_mul(int a, int b);
+        int calc(int a, int b){
+            return host_mul(host_add(a, b), b);
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+        extern "C" fn host_add(a: c_int, b: c_int) -> c_int {
+            a + b
+        }
+        extern "C" fn host_mul(a: c_int, b: c_int) -> c_int {
+            a * b
+        }
+        let add = CString::new("host_add".as_bytes()).unwrap();
+        let mul = CString::new("host_mul".as_bytes()).unwrap();
+        let calc = CString::new("calc".as_bytes()).unwrap();
+
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        unsafe {
+            ctx.add_symbols(&[
+                (&add, host_add as *const c_void),
+                (&mul, host_mul as *const c_void),
+            ]);
+        }
+        assert!(ctx.compile_string(&p).is_ok());
+        let mut relocated = ctx.relocate().unwrap();
+        let calc: extern "C" fn(c_int, c_int) -> c_int =
+            unsafe { relocated.get_function(&calc).unwrap() };
+        assert_eq!(calc(1, 2), 6);
+    }
+
+    #[test]
+    fn add_symbol_between_compiles() {
+        let p = CString::new(
+            r#"
+        int add(int a, int b);
+        int add2(int a, int b){
+            return add(a, b) + add(a, b);
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+        let p2 = CString::new(
+            r#"
+        int add(int a, int b);
+        int add3(int a, int b){
+            return add(a, b) + add(a, b) + add(a, b);
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+        let sym = CString::new("add".as_bytes()).unwrap();
+        let sym2 = CString::new("add2".as_bytes()).unwrap();
+        let sym3 = CString::new("add3".as_bytes()).unwrap();
+
+        extern "C" fn add(a: c_int, b: c_int) -> c_int {
+            a + b
+        }
+
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        unsafe {
+            ctx.add_symbol(&sym, add as *const c_void);
+        }
+        assert!(ctx.compile_string(&p2).is_ok());
+        let mut relocated = ctx.relocate().unwrap();
+
+        let add2: extern "C" fn(c_int, c_int) -> c_int =
+            unsafe { transmute(relocated.get_symbol(&sym2).unwrap()) };
+        let add3: extern "C" fn(c_int, c_int) -> c_int =
+            unsafe { transmute(relocated.get_symbol(&sym3).unwrap()) };
+        assert_eq!(add2(1, 1), 4);
+        assert_eq!(add3(1, 1), 6);
+    }
+
+    #[test]
+    fn get_function() {
+        let p = CString::new(
+            r#"
+        typedef int (*hook_t)(int);
+        static hook_t hook;
+        void set_hook(hook_t h){
+            hook = h;
+        }
+        int run_hook(int x){
+            return hook(x);
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+        let set_hook = CString::new("set_hook".as_bytes()).unwrap();
+        let run_hook = CString::new("run_hook".as_bytes()).unwrap();
+
+        extern "C" fn double(x: c_int) -> c_int {
+            x * 2
+        }
+
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        let mut relocated = ctx.relocate().unwrap();
+        let set_hook: extern "C" fn(extern "C" fn(c_int) -> c_int) =
+            unsafe { relocated.get_function(&set_hook).unwrap() };
+        let run_hook: extern "C" fn(c_int) -> c_int =
+            unsafe { relocated.get_function(&run_hook).unwrap() };
+        set_hook(double);
+        assert_eq!(run_hook(21), 42);
+    }
+
+    #[test]
+    fn run_init() {
+        let p = CString::new(
+            r#"
+        static int counter;
+        void init(){
+            counter = 41;
+        }
+        int get(){
+            return counter + 1;
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+        let init = CString::new("init".as_bytes()).unwrap();
+        let get = CString::new("get".as_bytes()).unwrap();
+        let mut g = Guard::wait();
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        let mut relocated = ctx.run_init(&init).unwrap();
+        let get: extern "C" fn() -> c_int = unsafe { relocated.get_function(&get).unwrap() };
+        assert_eq!(get(), 42);
+        drop(relocated);
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        let missing = CString::new("missing".as_bytes()).unwrap();
+        let err = ctx.run_init(&missing).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::Link);
+    }
+
+    #[test]
+    fn compile_fn() {
+        let p = CString::new(
+            r#"
+        static int counter;
+        int next(void){
+            return ++counter;
+        }
+        double scale(double x, int n){
+            return x * n;
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+        let next = CString::new("next".as_bytes()).unwrap();
+        let scale = CString::new("scale".as_bytes()).unwrap();
+        let mut g = Guard::wait();
+
+        let ctx = Context::new(&mut g).unwrap();
+        let next = unsafe { ctx.compile_fn::<(), c_int>(&p, &next) }.unwrap();
+        assert_eq!(next(()), 1);
+        assert_eq!(next(()), 2);
+        drop(next);
+
+        let ctx = Context::new(&mut g).unwrap();
+        let scale = unsafe { ctx.compile_fn::<(f64, c_int), f64>(&p, &scale) }.unwrap();
+        assert_eq!(scale((1.5, 2)), 3.0);
+        drop(scale);
+
+        let ctx = Context::new(&mut g).unwrap();
+        let missing = CString::new("missing".as_bytes()).unwrap();
+        let err = unsafe { ctx.compile_fn::<(), c_int>(&p, &missing) }
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::Link);
+    }
+
+    #[test]
+    fn run() {
+        let p = CString::new(
+            r#"
+        int main(int argc, char **argv){
+            return argv[argc] == 0 ? argc + 1 : -1;
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+        let arg = CString::new("prog".as_bytes()).unwrap();
+        let mut g = Guard::wait();
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        assert_eq!(ctx.run(&[&arg, &arg]), Ok(3));
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        let lib = CString::new("int f(void){ return 1; }".as_bytes()).unwrap();
+        assert!(ctx.compile_string(&lib).is_ok());
+        assert_eq!(ctx.run(&[]).unwrap_err().kind(), ErrorKind::Link);
+    }
+
+    #[test]
+    fn split_cmdline() {
+        let split = |s: &str| {
+            super::split_cmdline(s).map(|args| {
+                args.into_iter()
+                    .map(|a| a.into_string().unwrap())
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(
+            split(r#" prog  'a b' "c \"d\" \n" e\ f ''"#).unwrap(),
+            ["prog", "a b", r#"c "d" \n"#, "e f", ""]
+        );
+        assert!(split("").unwrap().is_empty());
+        for bad in &["'a", "\"a", "a\\", "a\0"] {
+            assert_eq!(split(bad).unwrap_err().kind(), ErrorKind::InvalidInput);
+        }
+
+        let p = CString::new(
+            r#"
+        #include <string.h>
+        int main(int argc, char **argv){
+            return argc == 3 && strcmp(argv[2], "a b") == 0 ? 3 : -1;
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        assert_eq!(ctx.run_cmdline("prog -x 'a b'"), Ok(3));
+    }
+
+    #[test]
+    fn run_with_stack() {
+        let p = CString::new(
+            r#"
+        int depth(int n){
+            volatile char buf[1024];
+            buf[0] = n;
+            return n == 0 ? buf[0] : depth(n - 1) + 1;
+        }
+        int main(int argc, char **argv){
+            return depth(4000) == 4000 ? argc : -1;
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+        let arg = CString::new("prog".as_bytes()).unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Memory).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        assert_eq!(ctx.run_with_stack(&[&arg], 16 << 20), Ok(1));
+    }
+
+    #[test]
+    #[cfg(not(target_env = "msvc"))]
+    fn link_lib() {
+        let p = CString::new(
+            r#"
+        int add(int a, int b){
+            return a+b;
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Dll).unwrap();
+        assert!(ctx.compile_string(&p).is_ok());
+        let dir = temp_dir();
+        let lib = dir.join("libadd.a");
+        ctx.output_file(&lib).unwrap();
+        assert!(lib.exists());
+
+        let p2 = CString::new(
+            r#"
+        int add2(int a, int b){
+            return add(a, b) + add(a, b);
+        }
+        "#
+            .as_bytes(),
+        )
+        .unwrap();
+        let lib_name = CString::new("add".as_bytes()).unwrap();
+        let sym2 = CString::new("add2".as_bytes()).unwrap();
+        let mut ctx2 = Context::new(&mut g).unwrap();
+        ctx2.set_output_type(OutputType::Memory)
+            .unwrap()
+            .add_library_path(&dir)
+            .unwrap()
+            .add_library(&lib_name)
+            .unwrap();
+
+        assert!(ctx2.compile_string(&p2).is_ok());
+        let mut r = ctx2.relocate().unwrap();
+
+        let add2: fn(c_int, c_int) -> c_int = unsafe { transmute(r.get_symbol(&sym2).unwrap()) };
+
+        assert_eq!(add2(1, 1), 4);
+        remove_file(lib).unwrap();
+    }
+    #[test]
+    fn read_global() {
+        let p = CString::new("int counter = 41; int next(void){ return ++counter; }").unwrap();
+        let counter = CString::new("counter").unwrap();
+        let next = CString::new("next").unwrap();
+        let missing = CString::new("missing").unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.compile_string(&p).unwrap();
+        let mut r = ctx.relocate().unwrap();
+        unsafe {
+            assert_eq!(r.read_global::<c_int>(&counter), Some(41));
+            assert!(r.write_global::<c_int>(&counter, 99));
+            let next: extern "C" fn() -> c_int = r.get_function(&next).unwrap();
+            assert_eq!(next(), 100);
+            assert_eq!(r.read_global::<c_int>(&counter), Some(100));
+            assert_eq!(r.read_global::<c_int>(&missing), None);
+            assert!(!r.write_global::<c_int>(&missing, 1));
+        }
+    }
+
+    #[test]
+    fn define_macro() {
+        let p = CString::new("int f(void){ return SQ(1 + 2) + MAX(1, 2); }").unwrap();
+        let f = CString::new("f").unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.define_macro(
+            &CString::new("SQ(x)").unwrap(),
+            &CString::new("((x) * (x))").unwrap(),
+        )
+        .unwrap()
+        .define_macro(
+            &CString::new("MAX(a,b)").unwrap(),
+            &CString::new("((a) > (b) ? (a) : (b))").unwrap(),
+        )
+        .unwrap();
+        assert!(ctx
+            .define_macro(
+                &CString::new("SQ (x)").unwrap(),
+                &CString::new("x").unwrap()
+            )
+            .is_err());
+        ctx.compile_string(&p).unwrap();
+        let mut r = ctx.relocate().unwrap();
+        let f: extern "C" fn() -> c_int = unsafe { r.get_function(&f).unwrap() };
+        assert_eq!(f(), 11);
+    }
+
+    /// lib path with bcheck.o next to libtcc1.a, return the original lib path.
+    fn bcheck_lib_path(g: &mut Guard, dir: &Path) -> PathBuf {
+        create_dir_all(dir).unwrap();
+        // build the runtime from tcc's source, without malloc hooks which glibc removed.
+        let mut ctx = Context::new(g).unwrap();
+        ctx.define_symbol(
+            &CString::new("__dietlibc__").unwrap(),
+            &CString::new("1").unwrap(),
+        )
+        .unwrap()
+        .set_output_type(OutputType::Obj)
+        .unwrap();
+        let lib = ctx.lib_path();
+        ctx.add_file("src/tcc-0.9.27/lib/bcheck.c").unwrap();
+        ctx.output_file(dir.join("bcheck.o")).unwrap();
+        fs::copy(lib.join("libtcc1.a"), dir.join("libtcc1.a")).unwrap();
+        lib
+    }
+
+    #[test]
+    fn enable_bounds_check() {
+        let p = CString::new(
+            r#"
+        #include <setjmp.h>
+        #include <signal.h>
+        #include <string.h>
+        int arr[4];
+        static sigjmp_buf env;
+        static void on_segv(int sig){ siglongjmp(env, 1); }
+        int get(int i){
+            struct sigaction sa, old;
+            int r;
+            memset(&sa, 0, sizeof sa);
+            sa.sa_handler = on_segv;
+            sigaction(SIGSEGV, &sa, &old);
+            r = sigsetjmp(env, 1) ? -1 : arr[i];
+            sigaction(SIGSEGV, &old, 0);
+            return r;
+        }
+        "#,
+        )
+        .unwrap();
+        let get = CString::new("get").unwrap();
+        let dir = temp_dir().join("libtcc_bcheck");
+        let mut g = Guard::wait();
+        let lib = bcheck_lib_path(&mut g, &dir);
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        assert!(ctx
+            .set_output_type(OutputType::Memory)
+            .unwrap()
+            .enable_bounds_check()
+            .is_err());
+        drop(ctx);
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_lib_path(&dir)
+            .unwrap()
+            .add_sys_include_path(lib.join("include"))
+            .unwrap()
+            .enable_bounds_check()
+            .unwrap();
+        ctx.compile_string(&p).unwrap();
+        let mut r = ctx.relocate().unwrap();
+        let get: extern "C" fn(c_int) -> c_int = unsafe { r.get_function(&get).unwrap() };
+        assert_eq!(get(3), 0);
+        assert_eq!(get(4), -1);
+        drop(r);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn supported_targets() {
+        let targets = super::supported_targets();
+        assert_eq!(targets.len(), 1);
+        if cfg!(target_arch = "x86_64") {
+            assert_eq!(targets, ["x86_64"]);
+        }
+    }
+
+    #[test]
+    fn tcc_capabilities() {
+        let caps = super::tcc_capabilities();
+        if cfg!(all(target_arch = "x86_64", target_env = "gnu")) {
+            assert!(caps.bounds_check && caps.backtrace);
+        }
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        assert_eq!(ctx.enable_bounds_check().is_ok(), caps.bounds_check);
+    }
+
+    #[test]
+    fn force_reset() {
+        let r = panic::catch_unwind(|| {
+            let _g = Guard::wait();
+            panic!("panic while holding the guard");
+        });
+        assert!(r.is_err());
+        let g = Guard::wait();
+        std::mem::forget(g);
+        unsafe { Guard::force_reset() };
+        drop(Guard::wait());
+    }
+
+    #[test]
+    fn set_entry_symbol() {
+        let p = CString::new(
+            "static int state; void my_start(void){ state = 42; } int get(void){ return state; }",
+        )
+        .unwrap();
+        let entry = CString::new("my_start").unwrap();
+        let get = CString::new("get").unwrap();
+        let mut g = Guard::wait();
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.no_std_lib().unwrap().set_entry_symbol(&entry);
+        ctx.compile_string(&p).unwrap();
+        assert!(ctx.effective_options().starts_with("-nostdlib"));
+        let mut r = ctx.relocate().unwrap();
+        let start: extern "C" fn() = unsafe { transmute(r.entry_point().unwrap()) };
+        let get: extern "C" fn() -> c_int = unsafe { r.get_function(&get).unwrap() };
+        start();
+        assert_eq!(get(), 42);
+        drop(r);
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_call_back(|_| ())
+            .no_std_lib()
+            .unwrap()
+            .set_entry_symbol(&CString::new("missing").unwrap());
+        ctx.compile_string(&p).unwrap();
+        let err = ctx.relocate().err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::Link);
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.compile_string(&CString::new("int main(int argc){ return argc; }").unwrap())
+            .unwrap();
+        let r = ctx.relocate().unwrap();
+        let main: extern "C" fn(c_int) -> c_int = unsafe { transmute(r.entry_point().unwrap()) };
+        assert_eq!(main(3), 3);
+        drop(r);
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.compile_string(&p).unwrap();
+        assert!(ctx.relocate().unwrap().entry_point().is_none());
+    }
 
     #[test]
-    fn guard_multiple_creat() {
-        {
-            let g1 = Guard::new();
-            assert!(g1.is_ok());
-            let g2 = Guard::new();
-            assert!(g2.is_err());
+    fn default_extension() {
+        let ext = |output: OutputType| output.default_extension();
+        assert_eq!(ext(OutputType::Memory), "");
+        assert_eq!(ext(OutputType::Obj), "o");
+        assert_eq!(ext(OutputType::Preprocess), "i");
+        if cfg!(windows) {
+            assert_eq!(ext(OutputType::Exe), "exe");
+            assert_eq!(ext(OutputType::Dll), "dll");
+        } else if cfg!(target_os = "macos") {
+            assert_eq!(ext(OutputType::Exe), "");
+            assert_eq!(ext(OutputType::Dll), "dylib");
+        } else {
+            assert_eq!(ext(OutputType::Exe), "");
+            assert_eq!(ext(OutputType::Dll), "so");
         }
-        let g3 = Guard::new();
-        assert!(g3.is_ok());
+
+        let p = CString::new("int f(void){ return 1; }").unwrap();
+        let stem = temp_dir().join("libtcc_auto.1");
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_output_type(OutputType::Obj).unwrap();
+        ctx.compile_string(&p).unwrap();
+        let file = ctx.output_file_auto(&stem).unwrap();
+        assert_eq!(file, temp_dir().join("libtcc_auto.1.o"));
+        assert!(file.exists());
+        remove_file(file).unwrap();
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.compile_string(&p).unwrap();
+        let err = ctx.output_file_auto(&stem).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
     }
 
     #[test]
-    fn set_call_back() {
-        let err_p = CString::new("error".as_bytes()).unwrap();
-        let mut call_back_ret = None;
-        let mut g = Guard::new().unwrap();
+    fn write_map() {
+        let p = CString::new("int data = 1; int f(void){ return data; } int g(void){ return 2; }")
+            .unwrap();
+        let mut g = Guard::wait();
         let mut ctx = Context::new(&mut g).unwrap();
-        ctx.set_call_back(|_| call_back_ret = Some("called"));
-        assert!(ctx.compile_string(&err_p).is_err());
-        drop(ctx);
-        assert_eq!(call_back_ret, Some("called"));
+        ctx.compile_string(&p).unwrap();
+        let r = ctx.relocate().unwrap();
+        let mut out = Vec::new();
+        r.write_map(&mut out).unwrap();
+        let map = String::from_utf8(out).unwrap();
+        let mut lines = map.lines();
+        let header = format!(
+            "base {:#018x} size {:#x}",
+            r.base_ptr() as usize,
+            r.code_size()
+        );
+        assert_eq!(lines.next(), Some(&header[..]));
+        let addrs: Vec<usize> = lines
+            .map(|l| {
+                let (addr, name) = l.split_once(' ').unwrap();
+                let addr = usize::from_str_radix(addr.trim_start_matches("0x"), 16).unwrap();
+                let sym = r.symbol_map()[&CString::new(name).unwrap()];
+                assert_eq!(addr, sym as usize);
+                addr
+            })
+            .collect();
+        assert_eq!(addrs.len(), r.symbols().len());
+        assert!(addrs.windows(2).all(|w| w[0] <= w[1]));
     }
 
     #[test]
-    fn add_sys_include_path() {
-        let p = CString::new("#include<libtcc_test_0_9_27.h>").unwrap();
-        let header = "#define TEST";
-        let dir = temp_dir();
-        write(dir.join("libtcc_test_0_9_27.h"), header).unwrap();
+    fn run_with_env() {
+        let p = CString::new(
+            r#"
+        #include <stdlib.h>
+        #include <string.h>
+        int main(void) {
+            const char *v = getenv("LIBTCC_RUN_ENV");
+            if (!v) return 1;
+            return strcmp(v, "on") == 0 ? 2 : 3;
+        }"#,
+        )
+        .unwrap();
+        env::set_var("LIBTCC_RUN_ENV", "host");
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.compile_string(&p).unwrap();
+        assert_eq!(
+            ctx.run_with_env(&[], &[("LIBTCC_RUN_ENV", "on")]).unwrap(),
+            2
+        );
+        assert_eq!(env::var("LIBTCC_RUN_ENV").unwrap(), "host");
+
+        env::remove_var("LIBTCC_RUN_ENV");
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.compile_string(&p).unwrap();
+        let env = [("LIBTCC_RUN_ENV", "off"), ("LIBTCC_RUN_ENV_2", "x")];
+        assert_eq!(ctx.run_with_env(&[], &env).unwrap(), 3);
+        assert!(env::var_os("LIBTCC_RUN_ENV").is_none());
+        assert!(env::var_os("LIBTCC_RUN_ENV_2").is_none());
 
-        let mut g = Guard::new().unwrap();
         let mut ctx = Context::new(&mut g).unwrap();
-        assert!(ctx.add_sys_include_path(&dir).compile_string(&p).is_ok());
-        remove_file(dir.join("libtcc_test_0_9_27.h")).unwrap();
+        ctx.compile_string(&p).unwrap();
+        let err = ctx.run_with_env(&[], &[("A=B", "1")]).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
     }
 
     #[test]
-    fn add_include_path() {
-        let p = CString::new("#include\"libtcc_test_0_9_27.h\"").unwrap();
-        let header = "#define TEST";
-        let dir = temp_dir();
-        write(dir.join("libtcc_test_0_9_27.h"), header).unwrap();
-
-        let mut g = Guard::new().unwrap();
+    fn symbol_ranges() {
+        let p = CString::new(
+            "int f(void){ return 1; } int g(int a){ return a * 2; } int h(void) __attribute__((alias(\"g\")));",
+        )
+        .unwrap();
+        let mut g = Guard::wait();
         let mut ctx = Context::new(&mut g).unwrap();
-        assert!(ctx.add_include_path(&dir).compile_string(&p).is_ok());
-        remove_file(dir.join("libtcc_test_0_9_27.h")).unwrap();
+        ctx.compile_string(&p).unwrap();
+        let r = ctx.relocate().unwrap();
+        let ranges = r.symbol_ranges();
+        let map = r.symbol_map();
+        let base = r.base_ptr() as usize;
+        assert_eq!(ranges.len(), r.symbols().len());
+        for (name, range) in &ranges {
+            assert_eq!(range.start, map[name] as usize - base);
+            assert!(range.start < range.end && range.end <= r.code_size());
+        }
+        assert!(ranges.windows(2).all(|w| w[0].1.start <= w[1].1.start));
+        assert_eq!(ranges.last().unwrap().1.end, r.code_size());
+        let range = |n: &str| {
+            let n = CString::new(n).unwrap();
+            ranges
+                .iter()
+                .find(|(name, _)| *name == n)
+                .unwrap()
+                .1
+                .clone()
+        };
+        assert_eq!(range("g"), range("h"));
+        let f = range("f");
+        let g = range("g");
+        assert!(f.end <= g.start || g.end <= f.start);
     }
 
     #[test]
-    fn symbol_define() {
+    fn c_std() {
         let p = CString::new(
-            r#"#ifdef TEST
-        typedef __unknown_type a1;
-        #endif
-        "#
-            .as_bytes(),
+            "#if __STDC_VERSION__ != 199901L\n#error not c99\n#endif\n\
+             int f(int n){ int a[n]; for (int i = 0; i < n; i++) a[i] = i; \
+             return _Generic(n, int: a[n - 1], default: 0); }",
         )
         .unwrap();
-        let sym = CString::new("TEST".as_bytes()).unwrap();
-        let val = CString::new("1".as_bytes()).unwrap();
-        let mut g = Guard::new().unwrap();
+        let mut g = Guard::wait();
         let mut ctx = Context::new(&mut g).unwrap();
-        ctx.define_symbol(&sym, &val);
-        assert!(ctx.compile_string(&p).is_err());
-        ctx.undefine_symbol(&sym);
+        ctx.set_options(&CString::new("-std=c11").unwrap()).unwrap();
+        assert_eq!(ctx.c_std(), CStd::C99);
         assert!(ctx.compile_string(&p).is_ok());
     }
 
     #[test]
-    fn output_exe_file() {
-        let p = CString::new(
-            r#"
-        #include<stdio.h>
-        int main(int argc, char **argv){
-            printf("hello world");
-            return 0;
+    fn set_symbol_resolver() {
+        extern "C" fn magic() -> c_int {
+            42
         }
-        "#
-            .as_bytes(),
-        )
-        .unwrap();
+        let p = CString::new("int magic(void); int f(void){ return magic(); }").unwrap();
+        let f = CString::new("f").unwrap();
+        let mut asked = Vec::new();
+        let mut g = Guard::wait();
 
-        let mut g = Guard::new().unwrap();
         let mut ctx = Context::new(&mut g).unwrap();
-        ctx.set_output_type(OutputType::Exe);
-        assert!(ctx.compile_string(&p).is_ok());
-        let dir = temp_dir();
-        let exe = dir.join("a.out");
-        ctx.output_file(&exe).unwrap();
-        assert!(exe.exists());
-        remove_file(&exe).unwrap();
+        ctx.set_call_back(|_| ());
+        ctx.compile_string(&p).unwrap();
+        assert_eq!(ctx.relocate().err().unwrap().kind(), ErrorKind::Link);
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_symbol_resolver(|name| {
+            asked.push(name.to_owned());
+            match name.to_bytes() {
+                b"magic" => Some(magic as *const c_void),
+                _ => None,
+            }
+        });
+        ctx.compile_string(&p).unwrap();
+        let mut r = ctx.relocate().unwrap();
+        let f: extern "C" fn() -> c_int = unsafe { r.get_function(&f).unwrap() };
+        assert_eq!(f(), 42);
+        assert_eq!(asked, [CString::new("magic").unwrap()]);
     }
 
     #[test]
-    fn output_lib() {
-        let p = CString::new(
-            r#"
-        int add(int a, int b){
-            return a+b;
+    #[cfg(target_arch = "x86_64")]
+    fn compile_asm() {
+        let src = b"
+        .text
+        .globl answer
+        answer:
+            mov $42, %eax
+            ret
+        ";
+        let answer = CString::new("answer").unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_call_back(|_| ());
+        let err = ctx.compile_asm(b"bogus %eax").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Compile);
+        drop(ctx);
+
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.compile_asm(src).unwrap();
+        let mut r = ctx.relocate().unwrap();
+        let answer: extern "C" fn() -> c_int = unsafe { r.get_function(&answer).unwrap() };
+        assert_eq!(answer(), 42);
+    }
+
+    #[test]
+    fn set_verbosity() {
+        let dir = temp_dir().join("libtcc_verbosity");
+        create_dir_all(&dir).unwrap();
+        write(dir.join("verbosity.h"), "#define ONE 1\n").unwrap();
+        let p = CString::new("#include <verbosity.h>\nint f(void){ return ONE; }").unwrap();
+        let out = dir.join("verbosity.o");
+        let mut msgs = Vec::new();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_call_back(|m| msgs.push(m.to_string_lossy().into_owned()))
+            .set_verbosity(3)
+            .add_include_path("/nonexistent")
+            .unwrap()
+            .add_include_path(&dir)
+            .unwrap()
+            .set_output_type(OutputType::Obj)
+            .unwrap();
+        ctx.compile_string(&p).unwrap();
+        ctx.output_file(&out).unwrap();
+        let header = dir.join("verbosity.h");
+        assert!(msgs.contains(&String::from("nf /nonexistent/verbosity.h")));
+        assert!(msgs.contains(&format!("-> {}", header.display())));
+        assert!(msgs.contains(&format!("<- {}", out.display())));
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn struct_by_value() {
+        #[repr(C)]
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        struct Range {
+            start: c_int,
+            end: c_int,
         }
-        "#
-            .as_bytes(),
+        let p = CString::new(
+            "struct range { int start, end; };\n\
+             struct range shift(struct range r, int k){ r.start += k; r.end += k; return r; }",
         )
         .unwrap();
+        let shift = CString::new("shift").unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.compile_string(&p).unwrap();
+        let mut r = ctx.relocate().unwrap();
+        let shift: extern "C" fn(Range, c_int) -> Range =
+            unsafe { r.get_function(&shift).unwrap() };
+        let range = Range { start: 1, end: 4 };
+        assert_eq!(shift(range, 2), Range { start: 3, end: 6 });
+    }
 
-        let mut g = Guard::new().unwrap();
+    #[test]
+    fn effective_options() {
+        let sym = CString::new("NAME".as_bytes()).unwrap();
+        let val = CString::new("a b".as_bytes()).unwrap();
+        let opt = CString::new("-g -O2".as_bytes()).unwrap();
+        let p = CString::new("int f(void){ return 1; }".as_bytes()).unwrap();
+        let mut g = Guard::wait();
         let mut ctx = Context::new(&mut g).unwrap();
-        ctx.set_output_type(OutputType::Dll);
-        assert!(ctx.compile_string(&p).is_ok());
-        let dir = temp_dir();
-        let lib = dir.join("lib");
-        ctx.output_file(&lib).unwrap();
-        assert!(lib.exists());
-        remove_file(&lib).unwrap();
+        assert_eq!(ctx.effective_options(), "");
+        ctx.add_include_path("inc")
+            .unwrap()
+            .define_symbol(&sym, &val)
+            .unwrap()
+            .set_options(&opt)
+            .unwrap()
+            .werror(true)
+            .undefine_symbol(&sym)
+            .add_library_path("/opt/my lib")
+            .unwrap();
+        ctx.compile_string(&p).unwrap();
+        assert_eq!(
+            ctx.effective_options(),
+            "-Iinc -D'NAME=a b' -g -O2 -Werror -UNAME -L'/opt/my lib' -run"
+        );
     }
 
     #[test]
-    fn output_obj() {
+    fn relocation_size() {
+        let p = CString::new("int data[64] = {1}; int f(void){ return data[0]; }").unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.compile_string(&p).unwrap();
+        let len = ctx.relocation_size().unwrap();
+        assert!(len >= 64 * std::mem::size_of::<c_int>());
+        assert_eq!(ctx.relocation_size().unwrap(), len);
+        let mut r = ctx.relocate().unwrap();
+        assert_eq!(r.code_size(), len);
+        let sym = CString::new("f").unwrap();
+        let f: fn() -> c_int = unsafe { transmute(r.get_symbol(&sym).unwrap()) };
+        assert_eq!(f(), 1);
+    }
+
+    #[test]
+    fn set_include_resolver() {
         let p = CString::new(
-            r#"
-        int add(int a, int b){
-            return a+b;
-        }
-        "#
-            .as_bytes(),
+            "#include \"proj/config.h\"\n#include <stddef.h>\nint f(void){ return ANSWER + sizeof(size_t) * 0; }",
         )
         .unwrap();
+        let mut asked = Vec::new();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_include_resolver(|name, system| {
+            asked.push((name.to_str().unwrap().to_string(), system));
+            match name.to_bytes() {
+                b"proj/config.h" => Some(b"#include <proj/answer.h>\n".to_vec()),
+                b"proj/answer.h" => Some(b"#define ANSWER 42\n".to_vec()),
+                _ => None,
+            }
+        })
+        .unwrap();
+        ctx.compile_string(&p).unwrap();
+        let mut relocated = ctx.relocate().unwrap();
+        let sym = CString::new("f").unwrap();
+        let f: fn() -> c_int = unsafe { transmute(relocated.get_symbol(&sym).unwrap()) };
+        assert_eq!(f(), 42);
+        drop(relocated);
+        asked.sort();
+        assert_eq!(
+            asked,
+            [
+                ("proj/answer.h".to_string(), true),
+                ("proj/config.h".to_string(), false),
+                ("stddef.h".to_string(), true),
+            ]
+        );
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn run_with_stdin() {
+        let run = |src: &str, input: &[u8]| {
+            let p = CString::new(src).unwrap();
+            let mut g = Guard::wait();
+            let mut ctx = Context::new(&mut g).unwrap();
+            ctx.compile_string(&p).unwrap();
+            ctx.run_with_stdin(&[], input).unwrap()
+        };
+        let upper = r#"#include <stdio.h>
+            #include <ctype.h>
+            int main(void){
+                int c, n = 0;
+                while ((c = getchar()) != EOF)
+                    putchar(toupper(c)), n++;
+                return n % 256;
+            }"#;
+        assert_eq!(run(upper, b"hello"), (5, b"HELLO".to_vec()));
+        assert_eq!(run(upper, b""), (0, Vec::new()));
+        // more than a pipe holds.
+        let input = vec![b'a'; 1 << 20];
+        let (code, out) = run(upper, &input);
+        assert_eq!((code, out.len()), (0, 1 << 20));
+        assert!(out.iter().all(|&c| c == b'A'));
+
+        // input left unread does not affect this process.
+        let (code, out) = run(
+            "#include <stdio.h>\n#include <stdlib.h>\nint main(void){ putchar(getchar()); fflush(stdout); abort(); }",
+            &input,
+        );
+        assert_eq!((code, out), (128 + 6, b"a".to_vec()));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn execute() {
+        let execute = |src: &str, opts: ExecuteOptions| {
+            let p = CString::new(src).unwrap();
+            let mut g = Guard::wait();
+            let mut ctx = Context::new(&mut g).unwrap();
+            ctx.compile_string(&p).unwrap();
+            let arg = CString::new("prog").unwrap();
+            ctx.execute(&[&arg], opts).unwrap()
+        };
+        let r = execute(
+            r#"#include <stdio.h>
+            int main(int argc, char **argv){
+                printf("%s out", argv[0]);
+                fprintf(stderr, "err");
+                return 3;
+            }"#,
+            ExecuteOptions::default(),
+        );
+        assert_eq!(r.code, 3);
+        assert_eq!(r.stdout, b"prog out");
+        assert_eq!(r.stderr, b"err");
+        assert!(!r.timed_out);
+
+        let r = execute(
+            "#include <stdlib.h>\nint main(void){ abort(); }",
+            ExecuteOptions::default(),
+        );
+        assert_eq!(r.code, 128 + 6);
+
+        let r = execute(
+            "int main(void){ for (;;); }",
+            ExecuteOptions {
+                timeout: Some(Duration::from_millis(100)),
+                ..Default::default()
+            },
+        );
+        assert!(r.timed_out);
+        assert_eq!(r.code, 128 + 9);
 
-        let mut g = Guard::new().unwrap();
+        let r = execute(
+            "int depth(int n){ char buf[256]; buf[0] = n; return n ? depth(n - 1) + buf[0] % 2 : 0; }\n\
+             int main(void){ return depth(1 << 20) < 0; }",
+            ExecuteOptions {
+                stack_size: Some(1 << 16),
+                ..Default::default()
+            },
+        );
+        assert_eq!(r.code, 128 + 11);
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn run_sandboxed() {
+        let run = |src: &str| {
+            let p = CString::new(src).unwrap();
+            let mut g = Guard::wait();
+            let mut ctx = Context::new(&mut g).unwrap();
+            ctx.compile_string(&p).unwrap();
+            let (mut out, mut err) = (Vec::new(), Vec::new());
+            let outcome = ctx.run_sandboxed_to(&[], &mut out, &mut err).unwrap();
+            assert!(err.is_empty());
+            (outcome, out)
+        };
+        assert_eq!(
+            run("int main(void){ return *(volatile int *)0; }"),
+            (RunOutcome::Crashed(11), Vec::new())
+        );
+        // output written before the crash is kept.
+        assert_eq!(
+            run("#include <stdio.h>\nint main(void){ puts(\"sum of 3: 6\"); fflush(stdout); return *(volatile int *)0; }"),
+            (RunOutcome::Crashed(11), b"sum of 3: 6\n".to_vec())
+        );
+        assert_eq!(
+            run("#include <stdlib.h>\nint main(void){ abort(); }").0,
+            RunOutcome::Crashed(6)
+        );
+        // an exit code in the range of signal deaths is not mistaken for one.
+        assert_eq!(
+            run("int main(void){ return 128 + 11; }").0,
+            RunOutcome::Exited(139)
+        );
+        assert_eq!(run("int main(void){ return 0; }").0, RunOutcome::Exited(0));
+        // this process survives the crashes and still compiles.
+        assert_eq!(
+            run("#include <stdio.h>\nint main(void){ printf(\"done\"); return 7; }"),
+            (RunOutcome::Exited(7), b"done".to_vec())
+        );
+    }
+
+    #[test]
+    fn clear_diagnostics() {
+        let unit1 = CString::new("#line 1 \"unit1.c\"\nint f(void){ return x; }").unwrap();
+        let unit2 = CString::new("#line 1 \"unit2.c\"\nint g(void){ return y; }").unwrap();
+        let mut g = Guard::wait();
         let mut ctx = Context::new(&mut g).unwrap();
-        ctx.set_output_type(OutputType::Obj);
-        assert!(ctx.compile_string(&p).is_ok());
-        let dir = temp_dir();
-        let obj = dir.join("obj");
-        ctx.output_file(&obj).unwrap();
-        assert!(obj.exists());
-        remove_file(&obj).unwrap();
+        ctx.set_call_back(|_| ());
+        let err = ctx.compile_string(&unit1).unwrap_err();
+        assert!(err.message().contains("unit1.c"));
+        ctx.clear_diagnostics();
+        assert!(ctx.diag.msgs.is_empty());
+        let err = ctx.compile_string(&unit2).unwrap_err();
+        assert!(err.message().contains("unit2.c"));
+        assert!(!err.message().contains("unit1.c"));
     }
 
     #[test]
-    fn run_func() {
-        let p = CString::new(
-            r#"
-        int add(int a, int b){
-            return a+b;
+    fn fill_vtable() {
+        #[repr(C)]
+        struct VTable {
+            add: extern "C" fn(c_int, c_int) -> c_int,
+            sub: extern "C" fn(c_int, c_int) -> c_int,
+            neg: extern "C" fn(c_int) -> c_int,
         }
-        "#
-            .as_bytes(),
+        let p = CString::new(
+            "int add(int a, int b){ return a + b; }\n\
+             int sub(int a, int b){ return a - b; }\n\
+             int neg(int a){ return -a; }",
         )
         .unwrap();
-        let sym = CString::new("add".as_bytes()).unwrap();
-
-        let mut g = Guard::new().unwrap();
+        let c = |s: &str| CString::new(s).unwrap();
+        let (add, sub, neg, mul) = (c("add"), c("sub"), c("neg"), c("mul"));
+        let mut g = Guard::wait();
         let mut ctx = Context::new(&mut g).unwrap();
-        ctx.set_output_type(OutputType::Memory);
-        assert!(ctx.compile_string(&p).is_ok());
-        let mut relocated = ctx.relocate().unwrap();
+        ctx.compile_string(&p).unwrap();
+        let r = ctx.relocate().unwrap();
 
-        let add: fn(c_int, c_int) -> c_int =
-            unsafe { transmute(relocated.get_symbol(&sym).unwrap()) };
-        assert_eq!(add(1, 1), 2);
+        let mut table = [null_mut(); 3];
+        r.fill_vtable(&[&add, &sub, &neg], &mut table).unwrap();
+        let vtable: &VTable = unsafe { &*(table.as_ptr() as *const VTable) };
+        assert_eq!((vtable.add)(1, 2), 3);
+        assert_eq!((vtable.sub)(1, 2), -1);
+        assert_eq!((vtable.neg)(1), -1);
+
+        let err = r.fill_vtable(&[&add, &mul, &neg], &mut table).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Link);
+        assert!(err.message().contains("'mul'"));
+        let err = r.fill_vtable(&[&add], &mut table).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
     }
 
     #[test]
-    fn add_symbol() {
+    fn optimize_for() {
         let p = CString::new(
-            r#"
-        int add(int a, int b){
-            return a+b;
-        }
-        "#
-            .as_bytes(),
+            "#ifndef __OPTIMIZE__\n#error no __OPTIMIZE__\n#endif\n\
+             int size(void){\n#ifdef __OPTIMIZE_SIZE__\nreturn 1;\n#else\nreturn 0;\n#endif\n}",
         )
         .unwrap();
-        let sym = CString::new("add".as_bytes()).unwrap();
-        let p2 = CString::new(
-            r#"
-        int add(int a, int b);
-        int add2(int a, int b){
-            return add(a, b) + add(a, b);
+        let sym = CString::new("size").unwrap();
+        for (goal, size) in [(OptGoal::Size, 1), (OptGoal::Speed, 0)] {
+            let mut g = Guard::wait();
+            let mut ctx = Context::new(&mut g).unwrap();
+            ctx.optimize_for(goal).compile_string(&p).unwrap();
+            let mut r = ctx.relocate().unwrap();
+            let f: fn() -> c_int = unsafe { transmute(r.get_symbol(&sym).unwrap()) };
+            assert_eq!(f(), size);
         }
-        "#
-            .as_bytes(),
-        )
-        .unwrap();
-        let sym2 = CString::new("add2".as_bytes()).unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn enable_checks() {
+        assert_eq!(
+            Checks::supported().contains(Checks::BOUNDS),
+            super::tcc_capabilities().bounds_check
+        );
+        if !Checks::supported().contains(Checks::BOUNDS) {
+            return;
+        }
+        let p =
+            CString::new("int arr[4];\nint main(int argc, char **argv){ return arr[argc + 3]; }")
+                .unwrap();
+        let dir = temp_dir().join("libtcc_checks");
+        let mut g = Guard::wait();
+        let lib = bcheck_lib_path(&mut g, &dir);
 
-        let mut g = Guard::new().unwrap();
         let mut ctx = Context::new(&mut g).unwrap();
-        ctx.set_output_type(OutputType::Memory);
-        assert!(ctx.compile_string(&p).is_ok());
-        let mut relocated = ctx.relocate().unwrap();
-        let add = unsafe { relocated.get_symbol(&sym).unwrap() };
+        ctx.set_lib_path(&dir)
+            .unwrap()
+            .add_sys_include_path(lib.join("include"))
+            .unwrap()
+            .enable_checks(Checks::BOUNDS)
+            .unwrap();
+        ctx.compile_string(&p).unwrap();
+        let arg = CString::new("prog").unwrap();
+        let r = ctx.execute(&[&arg], ExecuteOptions::default()).unwrap();
+        // the access is caught, instead of reading past the array.
+        assert_eq!(r.code, 128 + 11);
+        assert!(String::from_utf8_lossy(&r.stderr).contains("outside of the region"));
+        remove_dir_all(&dir).unwrap();
+    }
 
-        let mut ctx2 = Context::new(&mut g).unwrap();
-        ctx2.set_output_type(OutputType::Memory);
-        assert!(ctx2.compile_string(&p2).is_ok());
-        unsafe {
-            ctx2.add_symbol(&sym, add);
-        }
-        let mut relocated = ctx2.relocate().unwrap();
-        let add2: fn(c_int, c_int) -> c_int =
-            unsafe { transmute(relocated.get_symbol(&sym2).unwrap()) };
+    #[test]
+    fn diagnostics_channel() {
+        let p = CString::new("int f(void){ int x = \"s\"; return y; }").unwrap();
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        let rx = ctx.diagnostics_channel();
+        assert!(ctx.compile_string(&p).is_err());
+        let diags: Vec<Diagnostic> = rx.try_iter().collect();
+        assert_eq!(diags.len(), 2);
+        assert_eq!(diags[0].severity, Severity::Warning);
+        assert_eq!(diags[1].severity, Severity::Error);
+        assert_eq!(diags[1].file.as_deref(), Some("<string>"));
+        assert_eq!(diags[1].line, Some(1));
+        assert_eq!(diags[1].message, "'y' undeclared");
 
-        assert_eq!(add2(1, 1), 4);
+        drop(rx);
+        assert!(ctx.compile_string(&p).is_err());
     }
 
     #[test]
-    #[cfg(not(target_env = "msvc"))]
-    fn link_lib() {
-        let p = CString::new(
-            r#"
-        int add(int a, int b){
-            return a+b;
+    fn preprocess_to_writer() {
+        let mut g = Guard::wait();
+        let mut ctx = Context::new(&mut g).unwrap();
+        ctx.set_call_back(|_| ());
+        let mut out = Vec::new();
+        ctx.preprocess_to_writer(b"#define N 42\nint n = N;\n", &mut out)
+            .unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("int n = 42;"));
+
+        let mut out = Vec::new();
+        let err = ctx
+            .preprocess_to_writer(b"int n;\n#error stop\n", &mut out)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Compile);
+        assert!(out.is_empty());
+
+        let mut full = [0u8; 4];
+        let err = ctx
+            .preprocess_to_writer(b"int n;\n", &mut &mut full[..])
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Io);
+    }
+
+    #[test]
+    fn set_object_name() {
+        let dir = temp_dir().join("libtcc_object_name");
+        create_dir_all(dir.join("a")).unwrap();
+        create_dir_all(dir.join("b")).unwrap();
+        let src = "int f(void){ return 1; }\n";
+        let name = CString::new("lib/f.c").unwrap();
+        let mut objs = Vec::new();
+        let mut g = Guard::wait();
+        for sub in ["a", "b"] {
+            let file = dir.join(sub).join("f.c");
+            write(&file, src).unwrap();
+            let obj = dir.join(sub).join("f.o");
+            let mut ctx = Context::new(&mut g).unwrap();
+            ctx.set_options(&CString::new("-g").unwrap())
+                .unwrap()
+                .set_output_type(OutputType::Obj)
+                .unwrap();
+            ctx.set_object_name(&name).add_file(&file).unwrap();
+            ctx.output_file(&obj).unwrap();
+            objs.push(fs::read(&obj).unwrap());
         }
-        "#
-            .as_bytes(),
+        assert_eq!(objs[0], objs[1]);
+        let obj = String::from_utf8_lossy(&objs[0]);
+        assert!(obj.contains("lib/f.c"));
+        assert!(!obj.contains("libtcc_object_name"));
+        let cwd = env::current_dir().unwrap();
+        assert!(!obj.contains(&*cwd.to_string_lossy()));
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn symbol_kind() {
+        let p = CString::new(
+            "#include <stdio.h>\nint data = 1; int bss; const int ro = 2;\n\
+             int f(void){ return data; }\nvoid *ext(void){ return puts; }",
         )
         .unwrap();
-
-        let mut g = Guard::new().unwrap();
+        let c = |s: &str| CString::new(s).unwrap();
+        let mut g = Guard::wait();
         let mut ctx = Context::new(&mut g).unwrap();
-        ctx.set_output_type(OutputType::Dll);
-        assert!(ctx.compile_string(&p).is_ok());
-        let dir = temp_dir();
-        let lib = dir.join("libadd.a");
-        ctx.output_file(&lib).unwrap();
-        assert!(lib.exists());
+        ctx.compile_string(&p).unwrap();
+        unsafe { ctx.add_symbol(&c("host"), symbol_kind as *const c_void) };
+        let r = ctx.relocate().unwrap();
+        assert_eq!(r.symbol_kind(&c("f")), Some(SymbolKind::Code));
+        assert_eq!(r.symbol_kind(&c("data")), Some(SymbolKind::Data));
+        assert_eq!(r.symbol_kind(&c("bss")), Some(SymbolKind::Data));
+        assert_eq!(r.symbol_kind(&c("ro")), Some(SymbolKind::Data));
+        assert_eq!(r.symbol_kind(&c("host")), Some(SymbolKind::Unknown));
+        assert_eq!(r.symbol_kind(&c("missing")), None);
+    }
 
-        let p2 = CString::new(
-            r#"
-        int add2(int a, int b){
-            return add(a, b) + add(a, b);
-        }
-        "#
-            .as_bytes(),
+    #[test]
+    fn set_max_include_depth() {
+        let dir = temp_dir().join("libtcc_include_depth");
+        create_dir_all(&dir).unwrap();
+        write(dir.join("self.h"), "#include \"self.h\"\n").unwrap();
+        write(
+            dir.join("guarded.h"),
+            "#ifndef GUARDED_H\n#define GUARDED_H\n#include \"guarded.h\"\n#include <nested.h>\n#endif\n",
         )
         .unwrap();
-        let lib_name = CString::new("add".as_bytes()).unwrap();
-        let sym2 = CString::new("add2".as_bytes()).unwrap();
-        let mut ctx2 = Context::new(&mut g).unwrap();
-        ctx2.set_output_type(OutputType::Memory)
-            .add_library_path(&dir)
-            .add_library(&lib_name)
-            .unwrap();
-
-        assert!(ctx2.compile_string(&p2).is_ok());
-        let mut r = ctx2.relocate().unwrap();
+        write(dir.join("nested.h"), "#include \"leaf.h\"\n").unwrap();
+        write(dir.join("leaf.h"), "int leaf;\n").unwrap();
+        let mut g = Guard::wait();
 
-        let add2: fn(c_int, c_int) -> c_int = unsafe { transmute(r.get_symbol(&sym2).unwrap()) };
+        let mut ctx = Context::new(&mut g).unwrap();
+        assert!(ctx.set_max_include_depth(0).is_err());
+        assert!(ctx.set_max_include_depth(33).is_err());
+        ctx.add_include_path(&dir)
+            .unwrap()
+            .set_max_include_depth(3)
+            .unwrap();
+        let err = ctx
+            .compile_string(&CString::new("#include <self.h>\n").unwrap())
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Compile);
+        assert!(err.message().contains("nested deeper than 3"));
+        // guarded.h -> nested.h -> leaf.h
+        ctx.compile_string(&CString::new("#include <guarded.h>\n").unwrap())
+            .unwrap();
 
-        assert_eq!(add2(1, 1), 4);
-        remove_file(lib).unwrap();
+        ctx.set_max_include_depth(2).unwrap();
+        assert!(ctx
+            .compile_string(&CString::new("#include <guarded.h>\n").unwrap())
+            .is_err());
+        drop(ctx);
+        remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,908 @@
+/*
+ * Extensions of libtcc-0.9.27 used by the rust binding.
+ *
+ * libtcc.h only exposes a handful of functions, everything here needs
+ * access to the internals of TCCState and thus is built against tcc.h
+ * of the very same version.
+ */
+#include "tcc.h"
+#include <limits.h>
+
+/*
+ * Preprocess 'str' with the given -d flags (see tcc_parse_args), and pass
+ * the output to 'write_func' chunk by chunk. Nothing is written if
+ * preprocessing fails.
+ */
+int tcc_ext_preprocess(TCCState *s, const char *str, int dflag, void *opaque,
+                       void (*write_func)(void *opaque, const char *buf, size_t len))
+{
+    FILE *fp, *old_fp;
+    int old_output_type, old_dflag, ret;
+    char buf[4096];
+    size_t len;
+
+    fp = tmpfile();
+    if (!fp)
+        return -1;
+
+    old_fp = s->ppfp;
+    old_output_type = s->output_type;
+    old_dflag = s->dflag;
+    s->ppfp = fp;
+    s->output_type = TCC_OUTPUT_PREPROCESS;
+    s->dflag = dflag;
+    ret = tcc_compile_string(s, str);
+    s->ppfp = old_fp;
+    s->output_type = old_output_type;
+    s->dflag = old_dflag;
+
+    if (ret == 0) {
+        rewind(fp);
+        while ((len = fread(buf, 1, sizeof buf, fp)) > 0)
+            write_func(opaque, buf, len);
+    }
+    fclose(fp);
+    return ret;
+}
+
+/*
+ * Assemble 'str' like a .s file, i.e. without preprocessing.
+ */
+int tcc_ext_compile_asm(TCCState *s, const char *str)
+{
+    int old_filetype, ret;
+
+    old_filetype = s->filetype;
+    s->filetype = AFF_TYPE_ASM;
+    ret = tcc_compile_string(s, str);
+    s->filetype = old_filetype;
+    return ret;
+}
+
+/*
+ * Like tcc_add_file, but do not let the type detected from the extension
+ * stick, tcc would take libtcc1.a for C source after adding a .c file.
+ */
+int tcc_ext_add_file(TCCState *s, const char *filename)
+{
+    int old_filetype, ret;
+
+    old_filetype = s->filetype;
+    ret = tcc_add_file(s, filename);
+    s->filetype = old_filetype;
+    return ret;
+}
+
+void tcc_ext_set_verbose(TCCState *s, int level)
+{
+    s->verbose = level;
+}
+
+#ifdef _WIN32
+#include <io.h>
+#define dup _dup
+#define dup2 _dup2
+#define fileno _fileno
+#endif
+
+/*
+ * tcc prints verbose messages to stdout, tcc_ext_stdout_begin() points file
+ * descriptor 1 to a temporary file until tcc_ext_stdout_end(), which passes
+ * what is printed meanwhile to 'write_func'. The 'stdout' object itself is not
+ * assignable on every libc, and descriptors are process-wide anyway, so
+ * output of other threads is captured as well. Only one tcc state exists at a
+ * time, so the redirection is kept in statics.
+ */
+static FILE *capture_fp;
+static int saved_stdout = -1;
+
+int tcc_ext_stdout_begin(void)
+{
+    FILE *fp = tmpfile();
+
+    if (!fp)
+        return -1;
+    fflush(stdout);
+    saved_stdout = dup(1);
+    if (saved_stdout < 0 || dup2(fileno(fp), 1) < 0) {
+        if (saved_stdout >= 0)
+            close(saved_stdout);
+        saved_stdout = -1;
+        fclose(fp);
+        return -1;
+    }
+    capture_fp = fp;
+    return 0;
+}
+
+void tcc_ext_stdout_end(void *opaque,
+                        void (*write_func)(void *opaque, const char *buf, size_t len))
+{
+    FILE *fp = capture_fp;
+    char buf[4096];
+    size_t len;
+
+    fflush(stdout);
+    dup2(saved_stdout, 1);
+    close(saved_stdout);
+    saved_stdout = -1;
+    capture_fp = NULL;
+    rewind(fp);
+    while ((len = fread(buf, 1, sizeof buf, fp)) > 0)
+        write_func(opaque, buf, len);
+    fclose(fp);
+}
+
+#ifndef _WIN32
+#include <poll.h>
+#include <pthread.h>
+#include <signal.h>
+#include <sys/wait.h>
+#ifdef __linux__
+#include <sched.h>
+#include <sys/mman.h>
+#endif
+
+struct ext_child_args {
+    int (*prog_main)(int, char **);
+    int argc;
+    char **argv;
+    int (*pipes)[2];
+};
+
+/*
+ * Body of the child: redirect stdio to the pipes and run 'prog_main'. Only
+ * the thread forking is copied to the child, so no thread is created here,
+ * where locks held by other threads of the parent stay locked.
+ */
+static int ext_child(void *p)
+{
+    struct ext_child_args *args = p;
+    int (*pipes)[2] = args->pipes, ret;
+
+    close(pipes[0][0]);
+    close(pipes[1][0]);
+    if (pipes[2][0] >= 0) {
+        close(pipes[2][1]);
+        dup2(pipes[2][0], 0);
+    }
+    dup2(pipes[0][1], 1);
+    dup2(pipes[1][1], 2);
+    ret = args->prog_main(args->argc, args->argv);
+    fflush(NULL);
+    _exit(ret & 0xff);
+}
+
+/*
+ * Start ext_child() in a child process, on a fresh stack of 'stack_size'
+ * bytes below a guard page unless it is 0. A custom stack needs clone(),
+ * so it is only supported on linux.
+ */
+static pid_t ext_spawn(struct ext_child_args *args, size_t stack_size)
+{
+    pid_t pid;
+#ifdef __linux__
+    size_t page, len;
+    char *stack;
+
+    if (stack_size) {
+        page = sysconf(_SC_PAGESIZE);
+        len = (stack_size + page - 1) / page * page + page;
+        stack = mmap(NULL, len, PROT_READ | PROT_WRITE,
+                     MAP_PRIVATE | MAP_ANONYMOUS | MAP_STACK, -1, 0);
+        if (stack == MAP_FAILED)
+            return -1;
+        /* stacks grow down, an overflow hits the lowest page */
+        mprotect(stack, page, PROT_NONE);
+        /* without CLONE_VM the child gets a copy, the parent's is unmapped */
+        pid = clone(ext_child, stack + len, SIGCHLD, args);
+        munmap(stack, len);
+        return pid;
+    }
+#else
+    if (stack_size) {
+        errno = ENOTSUP;
+        return -1;
+    }
+#endif
+    pid = fork();
+    if (pid == 0)
+        ext_child(args);
+    return pid;
+}
+#endif
+
+/*
+ * Run 'prog_main' in a child process, with stdout and stderr of the child
+ * passed to 'write_func' with 'out' and 'err' as opaque. The child reads
+ * 'input_len' bytes of 'input' from stdin unless 'input' is null, in which
+ * case stdin is inherited. The child is killed after 'timeout_ms' unless it is
+ * negative, and 'main' runs on a stack of 'stack_size' bytes unless it is 0,
+ * see ext_spawn(). The exit code, or 128 plus the signal killing the child
+ * like sh, is stored to 'code', and the signal, or 0 if it exited, to 'sig'.
+ * Return -1 if the child can not be started, 1 if it timed out, 0 otherwise.
+ *
+ * The child is not exec'ed, so it must not take locks, e.g. of malloc or
+ * stdio, that other threads of the parent held at the time of the fork.
+ */
+int tcc_ext_execute(int (*prog_main)(int, char **), int argc, char **argv,
+                    size_t stack_size, int timeout_ms,
+                    const char *input, size_t input_len, int *code, int *sig,
+                    void *out, void *err,
+                    void (*write_func)(void *opaque, const char *buf, size_t len))
+{
+#ifndef _WIN32
+    struct pollfd fds[3];
+    void *opaque[2] = { out, err };
+    int pipes[3][2], i, n, status, timed_out = 0, wait_ms = timeout_ms;
+    long deadline = 0;
+    struct timespec now;
+    char buf[4096];
+    ssize_t len;
+    size_t written = 0;
+    sigset_t sigpipe, old_mask, pending;
+    pid_t pid;
+    struct ext_child_args args = { prog_main, argc, argv, pipes };
+
+    for (i = 0; i < 3; i++) {
+        pipes[i][0] = pipes[i][1] = -1;
+        if ((i < 2 || input) && pipe(pipes[i])) {
+            while (i--) {
+                close(pipes[i][0]);
+                close(pipes[i][1]);
+            }
+            return -1;
+        }
+    }
+    /* do not let the child flush output buffered by the parent */
+    fflush(NULL);
+    pid = ext_spawn(&args, stack_size);
+    for (i = 0; i < 3; i++)
+        if (pipes[i][i < 2] >= 0)
+            close(pipes[i][i < 2]);
+    if (pid < 0) {
+        for (i = 0; i < 3; i++)
+            if (pipes[i][i == 2] >= 0)
+                close(pipes[i][i == 2]);
+        return -1;
+    }
+    /* a child exiting without reading all input must not kill the parent */
+    sigemptyset(&sigpipe);
+    sigaddset(&sigpipe, SIGPIPE);
+    pthread_sigmask(SIG_BLOCK, &sigpipe, &old_mask);
+    fds[2].fd = pipes[2][1];
+    fds[2].events = POLLOUT;
+    if (fds[2].fd >= 0 && !input_len) {
+        close(fds[2].fd);
+        fds[2].fd = -1;
+    } else if (fds[2].fd >= 0) {
+        fcntl(fds[2].fd, F_SETFL, fcntl(fds[2].fd, F_GETFL) | O_NONBLOCK);
+    }
+
+    if (timeout_ms >= 0) {
+        clock_gettime(CLOCK_MONOTONIC, &now);
+        deadline = now.tv_sec * 1000L + now.tv_nsec / 1000000 + timeout_ms;
+    }
+    for (i = 0; i < 2; i++) {
+        fds[i].fd = pipes[i][0];
+        fds[i].events = POLLIN;
+    }
+    while (fds[0].fd >= 0 || fds[1].fd >= 0) {
+        if (timeout_ms >= 0 && !timed_out) {
+            clock_gettime(CLOCK_MONOTONIC, &now);
+            wait_ms = deadline - (now.tv_sec * 1000L + now.tv_nsec / 1000000);
+            if (wait_ms < 0)
+                wait_ms = 0;
+        }
+        n = poll(fds, 3, timed_out ? -1 : wait_ms);
+        if (n < 0 && errno == EINTR)
+            continue;
+        if (n == 0) {
+            kill(pid, SIGKILL);
+            timed_out = 1;
+            continue;
+        }
+        for (i = 0; i < 2; i++) {
+            if (fds[i].fd < 0 || !fds[i].revents)
+                continue;
+            len = read(fds[i].fd, buf, sizeof buf);
+            if (len > 0) {
+                write_func(opaque[i], buf, len);
+            } else if (len == 0 || errno != EINTR) {
+                close(fds[i].fd);
+                fds[i].fd = -1;
+            }
+        }
+        if (fds[2].fd >= 0 && fds[2].revents) {
+            len = write(fds[2].fd, input + written, input_len - written);
+            if (len > 0)
+                written += len;
+            if (written == input_len
+                || (len < 0 && errno != EINTR && errno != EAGAIN)) {
+                close(fds[2].fd);
+                fds[2].fd = -1;
+            }
+        }
+    }
+    if (fds[2].fd >= 0)
+        close(fds[2].fd);
+    /* consume SIGPIPE raised by writing to a closed pipe */
+    if (sigpending(&pending) == 0 && sigismember(&pending, SIGPIPE))
+        sigwait(&sigpipe, &n);
+    pthread_sigmask(SIG_SETMASK, &old_mask, NULL);
+
+    while (waitpid(pid, &status, 0) < 0)
+        if (errno != EINTR)
+            return -1;
+    *sig = WIFSIGNALED(status) ? WTERMSIG(status) : 0;
+    if (*sig)
+        *code = 128 + *sig;
+    else
+        *code = WEXITSTATUS(status);
+    return timed_out;
+#else
+    return -1;
+#endif
+}
+
+/*
+ * Outside of compilation tcc_error() calls exit(), the wrappers below catch
+ * such errors instead, and return -1.
+ */
+
+int tcc_ext_define_symbol(TCCState *s, const char *sym, const char *value)
+{
+    BufferedFile *f = file;
+    int ret = 0;
+
+    if (setjmp(s->error_jmp_buf) == 0) {
+        s->error_set_jmp_enabled = 1;
+        tcc_define_symbol(s, sym, value);
+    } else {
+        /* close "<define>" left open by tcc_error */
+        while (file && file != f)
+            tcc_close();
+        ret = -1;
+    }
+    s->error_set_jmp_enabled = 0;
+    return ret;
+}
+
+int tcc_ext_set_options(TCCState *s, const char *str)
+{
+    int ret = 0;
+
+    if (setjmp(s->error_jmp_buf) == 0) {
+        s->error_set_jmp_enabled = 1;
+        tcc_set_options(s, str);
+    } else {
+        ret = -1;
+    }
+    s->error_set_jmp_enabled = 0;
+    return ret;
+}
+
+int tcc_ext_relocate(TCCState *s, void *ptr)
+{
+    int ret;
+
+    if (setjmp(s->error_jmp_buf) == 0) {
+        s->error_set_jmp_enabled = 1;
+        ret = tcc_relocate(s, ptr);
+    } else {
+        ret = -1;
+    }
+    s->error_set_jmp_enabled = 0;
+    return ret;
+}
+
+#if defined TCC_TARGET_I386 || defined TCC_TARGET_X86_64
+ #define RUN_SECTION_ALIGNMENT 63
+#else
+ #define RUN_SECTION_ALIGNMENT 15
+#endif
+
+/*
+ * Size of memory at 'ptr' written by tcc_relocate(), following the layout of
+ * tcc_relocate_ex() in tccrun.c.
+ */
+static size_t relocated_size(TCCState *s, void *ptr)
+{
+    Section *sec;
+    size_t offset = 0, fill;
+    addr_t mem = (addr_t)ptr;
+    int i, k;
+
+    fill = -mem & RUN_SECTION_ALIGNMENT;
+#ifdef _WIN64
+    offset += sizeof (void*);
+#endif
+    for (k = 0; k < 2; ++k) {
+        for (i = 1; i < s->nb_sections; i++) {
+            sec = s->sections[i];
+            if (0 == (sec->sh_flags & SHF_ALLOC))
+                continue;
+            if (k != !(sec->sh_flags & SHF_EXECINSTR))
+                continue;
+            offset += fill + sec->data_offset;
+            fill = -(mem + offset) & 15;
+        }
+#if RUN_SECTION_ALIGNMENT > 15
+        fill = -(mem + offset) & RUN_SECTION_ALIGNMENT;
+#endif
+    }
+    return offset;
+}
+
+/*
+ * Same as tcc_ext_relocate() with a buffer, but check that 'size' bytes at
+ * 'ptr' are enough first. Return the size needed if they are not, without
+ * writing anything.
+ */
+int tcc_ext_relocate_checked(TCCState *s, void *ptr, size_t size)
+{
+    size_t needed = relocated_size(s, ptr);
+
+    if (needed > size)
+        return needed > INT_MAX ? -1 : (int)needed;
+    return tcc_ext_relocate(s, ptr);
+}
+
+/*
+ * Load shared library 'filename' into the process, the handle is kept in
+ * 's' so that symbols can be looked up by tcc_ext_get_dll_symbol(), and the
+ * library is unloaded by tcc_delete().
+ */
+int tcc_ext_load_dll(TCCState *s, const char *filename)
+{
+#if defined TCC_IS_NATIVE && !defined _WIN32
+    DLLReference *ref;
+    void *handle;
+
+    handle = dlopen(filename, RTLD_GLOBAL | RTLD_LAZY);
+    if (!handle) {
+        tcc_error_noabort("could not load '%s': %s", filename, dlerror());
+        return -1;
+    }
+    ref = tcc_mallocz(sizeof(DLLReference) + strlen(filename));
+    strcpy(ref->name, filename);
+    ref->handle = handle;
+    dynarray_add(&s->loaded_dlls, &s->nb_loaded_dlls, ref);
+    return 0;
+#else
+    tcc_error_noabort("could not load '%s': not supported", filename);
+    return -1;
+#endif
+}
+
+void *tcc_ext_get_dll_symbol(TCCState *s, const char *name)
+{
+#if defined TCC_IS_NATIVE && !defined _WIN32
+    void *addr;
+    int i;
+
+    for (i = 0; i < s->nb_loaded_dlls; i++) {
+        if (!s->loaded_dlls[i]->handle)
+            continue;
+        addr = dlsym(s->loaded_dlls[i]->handle, name);
+        if (addr)
+            return addr;
+    }
+#endif
+    return NULL;
+}
+
+/*
+ * Pass every global function or object defined in 's' with default or
+ * protected visibility to 'symbol_cb', like tcc_list_symbols() of later tcc versions.
+ * Linker generated symbols, e.g. _etext, are skipped.
+ */
+void tcc_ext_list_symbols(TCCState *s, void *ctx,
+                          void (*symbol_cb)(void *ctx, const char *name, const void *val))
+{
+    ElfW(Sym) *sym;
+    Section *symtab;
+    int sym_index, end_sym;
+    const char *name;
+
+    symtab = s->symtab;
+    end_sym = symtab->data_offset / sizeof (ElfW(Sym));
+    for (sym_index = 0; sym_index < end_sym; ++sym_index) {
+        sym = &((ElfW(Sym) *)symtab->data)[sym_index];
+        if (sym->st_shndx == SHN_UNDEF
+            || ELFW(ST_BIND)(sym->st_info) != STB_GLOBAL
+            || (ELFW(ST_TYPE)(sym->st_info) != STT_FUNC
+                && ELFW(ST_TYPE)(sym->st_info) != STT_OBJECT)
+            || (ELFW(ST_VISIBILITY)(sym->st_other) != STV_DEFAULT
+                && ELFW(ST_VISIBILITY)(sym->st_other) != STV_PROTECTED))
+            continue;
+        name = (char *) symtab->link->data + sym->st_name;
+        symbol_cb(ctx, name, (void*)(uintptr_t)sym->st_value);
+    }
+}
+
+/*
+ * Pass every file read so far, sources, headers and libraries, to 'dep_cb',
+ * the same as listed by tcc -MD.
+ */
+void tcc_ext_list_deps(TCCState *s, void *ctx,
+                       void (*dep_cb)(void *ctx, const char *name))
+{
+    int i;
+
+    for (i = 0; i < s->nb_target_deps; i++)
+        dep_cb(ctx, s->target_deps[i]);
+}
+
+/*
+ * Pass every global symbol referenced but not defined in 's' to 'symbol_cb',
+ * with null as value.
+ */
+void tcc_ext_list_undefined(TCCState *s, void *ctx,
+                            void (*symbol_cb)(void *ctx, const char *name, const void *val))
+{
+    ElfW(Sym) *sym;
+    Section *symtab;
+    int sym_index, end_sym;
+    const char *name;
+
+    symtab = s->symtab;
+    end_sym = symtab->data_offset / sizeof (ElfW(Sym));
+    for (sym_index = 1; sym_index < end_sym; ++sym_index) {
+        sym = &((ElfW(Sym) *)symtab->data)[sym_index];
+        if (sym->st_shndx != SHN_UNDEF
+            || ELFW(ST_BIND)(sym->st_info) == STB_LOCAL)
+            continue;
+        name = (char *) symtab->link->data + sym->st_name;
+        if (*name)
+            symbol_cb(ctx, name, NULL);
+    }
+}
+
+/*
+ * Bytes allocated for the sections of 's', including symbol tables and
+ * relocations. tcc counts its allocations only when built with MEM_DEBUG.
+ */
+size_t tcc_ext_memory_used(TCCState *s)
+{
+    size_t size = 0;
+    int i;
+
+    for (i = 1; i < s->nb_sections; i++)
+        size += s->sections[i]->data_allocated;
+    for (i = 0; i < s->nb_priv_sections; i++)
+        size += s->priv_sections[i]->data_allocated;
+    return size;
+}
+
+/*
+ * Sizes of code and of data loaded in memory, without padding, and the
+ * number of relocation entries tcc applies, of all sections including
+ * debug info.
+ */
+void tcc_ext_reloc_stats(TCCState *s, size_t *code, size_t *data, size_t *relocs)
+{
+    Section *sec;
+    int i;
+
+    *code = *data = *relocs = 0;
+    for (i = 1; i < s->nb_sections; i++) {
+        sec = s->sections[i];
+        if (sec->reloc)
+            *relocs += sec->reloc->data_offset / sizeof(ElfW_Rel);
+        if (0 == (sec->sh_flags & SHF_ALLOC))
+            continue;
+        if (sec->sh_flags & SHF_EXECINSTR)
+            *code += sec->data_offset;
+        else
+            *data += sec->data_offset;
+    }
+}
+
+/*
+ * Make global symbols defined in sections of 's' hidden, unless they are
+ * given another visibility. Absolute symbols, e.g. added by tcc_add_symbol(),
+ * are kept as is, as calls to hidden symbols do not go through the PLT,
+ * which may be needed to reach them.
+ *
+ * A dll of tcc exports all global symbols, and references them through the
+ * dynamic symbol table, so they are made local if 'local' is set, as a
+ * linker does for hidden symbols.
+ */
+void tcc_ext_hide_symbols(TCCState *s, int local)
+{
+    ElfW(Sym) *sym;
+    Section *symtab;
+    int sym_index, end_sym;
+
+    symtab = s->symtab;
+    end_sym = symtab->data_offset / sizeof (ElfW(Sym));
+    for (sym_index = 1; sym_index < end_sym; ++sym_index) {
+        sym = &((ElfW(Sym) *)symtab->data)[sym_index];
+        if (sym->st_shndx == SHN_UNDEF || sym->st_shndx >= SHN_LORESERVE
+            || ELFW(ST_BIND)(sym->st_info) == STB_LOCAL
+            || ELFW(ST_VISIBILITY)(sym->st_other) != STV_DEFAULT)
+            continue;
+        sym->st_other = (sym->st_other & ~ELFW(ST_VISIBILITY)(-1)) | STV_HIDDEN;
+        if (local)
+            sym->st_info = ELFW(ST_INFO)(STB_LOCAL, ELFW(ST_TYPE)(sym->st_info));
+    }
+}
+
+/*
+ * Return flags of the section defining global symbol 'name', 0 if it is
+ * undefined, absolute or common, and -1 if it is not found.
+ */
+long tcc_ext_symbol_section_flags(TCCState *s, const char *name)
+{
+    ElfW(Sym) *sym;
+    int sym_index;
+
+    sym_index = find_elf_sym(s->symtab, name);
+    if (!sym_index)
+        return -1;
+    sym = &((ElfW(Sym) *)s->symtab->data)[sym_index];
+    if (sym->st_shndx == SHN_UNDEF || sym->st_shndx >= SHN_LORESERVE)
+        return 0;
+    return s->sections[sym->st_shndx]->sh_flags;
+}
+
+/*
+ * Return 'paths' with every entry, other than those under {B}, moved from
+ * CONFIG_SYSROOT to 'sysroot'. The result must be freed by tcc_free().
+ */
+static char *sysroot_paths(const char *sysroot, const char *paths)
+{
+    CString str;
+    const char *p, *q;
+    char *ret;
+
+    cstr_new(&str);
+    for (p = paths; ; p = q + 1) {
+        q = strchr(p, PATHSEP[0]);
+        if (!q)
+            q = p + strlen(p);
+        if (p != paths)
+            cstr_ccat(&str, PATHSEP[0]);
+        if (*p != '{') {
+            cstr_cat(&str, sysroot, -1);
+            p += strlen(CONFIG_SYSROOT);
+        }
+        cstr_cat(&str, p, q - p);
+        if (!*q)
+            break;
+    }
+    cstr_ccat(&str, '\0');
+    ret = tcc_strdup(str.data);
+    cstr_free(&str);
+    return ret;
+}
+
+/*
+ * Search headers, libraries and crt objects under 'sysroot' instead of the
+ * host. Must be called before tcc_set_output_type(), which would add the
+ * default include paths of the host otherwise.
+ */
+void tcc_ext_set_sysroot(TCCState *s, const char *sysroot)
+{
+    char *paths, *p, *q;
+
+    paths = sysroot_paths(sysroot, CONFIG_TCC_SYSINCLUDEPATHS);
+    tcc_add_sysinclude_path(s, paths);
+    tcc_free(paths);
+    s->nostdinc = 1;
+
+    paths = sysroot_paths(sysroot, CONFIG_TCC_LIBPATHS);
+    tcc_add_library_path(s, paths);
+    tcc_free(paths);
+
+    paths = sysroot_paths(sysroot, CONFIG_TCC_CRTPREFIX);
+    for (p = paths; p; p = q) {
+        q = strchr(p, PATHSEP[0]);
+        if (q)
+            *q++ = '\0';
+        dynarray_add(&s->crt_paths, &s->nb_crt_paths, tcc_strdup(p));
+    }
+    tcc_free(paths);
+}
+
+/*
+ * Search headers in tcc's own include dir and 'includes' only, and libraries
+ * and crt objects in 'lib' before those of the host. Must be called before
+ * tcc_set_output_type(), like tcc_ext_set_sysroot().
+ */
+void tcc_ext_set_libc(TCCState *s, const char *includes, const char *lib)
+{
+    tcc_add_sysinclude_path(s, "{B}/include");
+    tcc_add_sysinclude_path(s, includes);
+    s->nostdinc = 1;
+    tcc_add_library_path(s, lib);
+    dynarray_add(&s->crt_paths, &s->nb_crt_paths, tcc_strdup(lib));
+}
+
+/*
+ * Insert include path at 'index' of -I paths, 0 to search it before all the
+ * others. Index beyond the end appends.
+ */
+void tcc_ext_insert_include_path(TCCState *s, const char *pathname, int index)
+{
+    char **paths;
+    int i;
+
+    dynarray_add(&s->include_paths, &s->nb_include_paths, tcc_strdup(pathname));
+    paths = s->include_paths;
+    for (i = s->nb_include_paths - 1; i > index; i--) {
+        char *p = paths[i];
+        paths[i] = paths[i - 1];
+        paths[i - 1] = p;
+    }
+}
+
+/*
+ * Search header 'name' the same way as #include does in a string compiled by
+ * tcc_compile_string(), and write path of the found one to 'buf'. Return -1
+ * if it is not found.
+ */
+int tcc_ext_resolve_include(TCCState *s, const char *name, int is_bracket,
+                            char *buf, int size)
+{
+    int i, n, fd;
+
+    n = 2 + s->nb_include_paths + s->nb_sysinclude_paths;
+    for (i = 0; i < n; ++i) {
+        if (i == 0) {
+            /* check absolute include path */
+            if (!IS_ABSPATH(name))
+                continue;
+            buf[0] = 0;
+        } else if (i == 1) {
+            /* dir of "<string>", i.e. the current dir, if "header.h" */
+            if (is_bracket)
+                continue;
+            buf[0] = 0;
+        } else {
+            int j = i - 2, k = j - s->nb_include_paths;
+            const char *path = k < 0 ? s->include_paths[j] : s->sysinclude_paths[k];
+            pstrcpy(buf, size, path);
+            pstrcat(buf, size, "/");
+        }
+        pstrcat(buf, size, name);
+        fd = open(buf, O_RDONLY | O_BINARY);
+        if (fd >= 0) {
+            close(fd);
+            return 0;
+        }
+    }
+    return -1;
+}
+
+/*
+ * Record 'name' as the source file of everything compiled in 's', in file
+ * symbols and, with -g, in N_SO stabs, where 'dir' replaces the current dir.
+ * String tables are rebuilt, so that the replaced names are not left in the
+ * output.
+ */
+void tcc_ext_set_file_name(TCCState *s, const char *dir, const char *name)
+{
+    Section *strtab, *stab = NULL;
+    ElfW(Sym) *sym, *sym_end;
+    Stab_Sym *st, *st_end;
+    const char *str;
+    char *old;
+    int i;
+
+    strtab = s->symtab->link;
+    old = tcc_malloc(strtab->data_offset);
+    memcpy(old, strtab->data, strtab->data_offset);
+    strtab->data_offset = 0;
+    put_elf_str(strtab, "");
+    sym_end = (ElfW(Sym) *)(s->symtab->data + s->symtab->data_offset);
+    for (sym = (ElfW(Sym) *)s->symtab->data + 1; sym < sym_end; sym++) {
+        if (!sym->st_name)
+            continue;
+        str = old + sym->st_name;
+        if (ELFW(ST_TYPE)(sym->st_info) == STT_FILE)
+            str = name;
+        sym->st_name = put_elf_str(strtab, str);
+    }
+    tcc_free(old);
+
+    for (i = 1; i < s->nb_sections; i++)
+        if (!strcmp(s->sections[i]->name, ".stab"))
+            stab = s->sections[i];
+    if (!stab)
+        return;
+    strtab = stab->link;
+    old = tcc_malloc(strtab->data_offset);
+    memcpy(old, strtab->data, strtab->data_offset);
+    strtab->data_offset = 0;
+    put_elf_str(strtab, "");
+    st_end = (Stab_Sym *)(stab->data + stab->data_offset);
+    for (st = (Stab_Sym *)stab->data; st < st_end; st++) {
+        if (!st->n_strx)
+            continue;
+        str = old + st->n_strx;
+        if (st->n_type == N_SO && *str)
+            str = str[strlen(str) - 1] == '/' ? dir : name;
+        st->n_strx = put_elf_str(strtab, str);
+    }
+    tcc_free(old);
+}
+
+const char *tcc_ext_get_lib_path(TCCState *s)
+{
+    return s->tcc_lib_path;
+}
+
+/*
+ * libc shims for compiled code, see LibcShims of the rust binding. Output
+ * of the printf family is passed to 'shim_write', which is shared by all
+ * modules like the rest of the tcc state.
+ */
+static void (*shim_write)(void *opaque, const char *buf, size_t len);
+
+void tcc_ext_shim_set_output(void (*write_func)(void *opaque, const char *buf, size_t len))
+{
+    shim_write = write_func;
+}
+
+static void shim_output(const char *buf, size_t len)
+{
+    if (shim_write && len)
+        shim_write(NULL, buf, len);
+}
+
+static int shim_vprintf(const char *fmt, va_list ap)
+{
+    char buf[1024], *p = buf;
+    va_list aq;
+    int len;
+
+    va_copy(aq, ap);
+    len = vsnprintf(buf, sizeof buf, fmt, ap);
+    if (len >= (int)sizeof buf) {
+        /* tcc's malloc is poisoned by tcc.h, and exits on failure */
+        p = (malloc)(len + 1);
+        if (p)
+            vsnprintf(p, len + 1, fmt, aq);
+        else
+            len = -1;
+    }
+    va_end(aq);
+    if (len > 0)
+        shim_output(p, len);
+    if (p != buf)
+        (free)(p);
+    return len;
+}
+
+int tcc_ext_shim_printf(const char *fmt, ...)
+{
+    va_list ap;
+    int len;
+
+    va_start(ap, fmt);
+    len = shim_vprintf(fmt, ap);
+    va_end(ap);
+    return len;
+}
+
+int tcc_ext_shim_puts(const char *str)
+{
+    shim_output(str, strlen(str));
+    shim_output("\n", 1);
+    return 0;
+}
+
+int tcc_ext_shim_putchar(int c)
+{
+    char ch = (char)c;
+
+    shim_output(&ch, 1);
+    return (unsigned char)c;
+}
+
+int *tcc_ext_shim_errno_location(void)
+{
+    return &errno;
+}
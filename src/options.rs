@@ -0,0 +1,170 @@
+use crate::{Context, Libc, OutputType, TccError, Visibility};
+use std::ffi::CString;
+use std::path::PathBuf;
+
+/// Setting applied to a [`Context`] by one of its setters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Setting {
+    LibPath(PathBuf),
+    Options(CString),
+    Werror(bool),
+    Verbosity(u8),
+    BoundsCheck,
+    IncludePath(PathBuf),
+    PrependIncludePath(PathBuf),
+    SysIncludePath(PathBuf),
+    Sysroot(PathBuf),
+    /// the libc, its include dirs and library dir.
+    Libc(Libc, Vec<PathBuf>, PathBuf),
+    /// the dir holding the denied headers, and their names.
+    DenyIncludes(PathBuf, Vec<String>),
+    Define(CString, CString),
+    Undefine(CString),
+    OutputType(OutputType),
+    LibraryPath(PathBuf),
+    Entry(CString),
+    DefaultVisibility(Visibility),
+}
+
+impl Setting {
+    /// render as tcc command line, arguments are quoted as for sh if needed.
+    ///
+    /// A prepended include path is rendered as -I, though it is searched first.
+    /// Entry symbol and default visibility have no option in tcc, and are rendered as nothing.
+    pub(crate) fn to_cmdline(&self) -> String {
+        let path = |flag: &str, p: &PathBuf| format!("{}{}", flag, quote(&p.to_string_lossy()));
+        match self {
+            Setting::LibPath(p) => path("-B", p),
+            // already in form of command line.
+            Setting::Options(opt) => opt.to_string_lossy().trim().to_string(),
+            Setting::Werror(true) => "-Werror".into(),
+            Setting::Werror(false) => "-Wno-error".into(),
+            Setting::Verbosity(0) => String::new(),
+            Setting::Verbosity(level) => format!("-{}", "v".repeat(*level as usize)),
+            Setting::BoundsCheck => "-b".into(),
+            Setting::IncludePath(p)
+            | Setting::PrependIncludePath(p)
+            | Setting::DenyIncludes(p, _) => path("-I", p),
+            Setting::SysIncludePath(p) => path("-isystem ", p),
+            Setting::Sysroot(p) => path("--sysroot=", p),
+            Setting::Libc(_, includes, lib) => {
+                let mut opts = vec!["-nostdinc".to_string()];
+                opts.extend(includes.iter().map(|p| path("-isystem ", p)));
+                opts.push(path("-L", lib));
+                opts.join(" ")
+            }
+            Setting::Define(sym, val) => format!(
+                "-D{}",
+                quote(&format!(
+                    "{}={}",
+                    sym.to_string_lossy(),
+                    val.to_string_lossy()
+                ))
+            ),
+            Setting::Undefine(sym) => format!("-U{}", quote(&sym.to_string_lossy())),
+            Setting::OutputType(output) => match output {
+                OutputType::Memory => "-run",
+                OutputType::Exe => "",
+                OutputType::Dll => "-shared",
+                OutputType::Obj => "-c",
+                OutputType::Preprocess => "-E",
+            }
+            .into(),
+            Setting::LibraryPath(p) => path("-L", p),
+            Setting::Entry(_) | Setting::DefaultVisibility(_) => String::new(),
+        }
+    }
+
+    /// apply to `ctx` by the setter recording it.
+    pub(crate) fn apply(&self, ctx: &mut Context) -> Result<(), TccError> {
+        match self {
+            Setting::LibPath(p) => ctx.set_lib_path(p).map(drop),
+            Setting::Options(opt) => ctx.set_options(opt).map(drop),
+            Setting::Werror(on) => {
+                ctx.werror(*on);
+                Ok(())
+            }
+            Setting::Verbosity(level) => {
+                ctx.set_verbosity(*level);
+                Ok(())
+            }
+            Setting::BoundsCheck => ctx.enable_bounds_check().map(drop),
+            Setting::IncludePath(p) => ctx.add_include_path(p).map(drop),
+            Setting::PrependIncludePath(p) => ctx.prepend_include_path(p).map(drop),
+            Setting::SysIncludePath(p) => ctx.add_sys_include_path(p).map(drop),
+            Setting::Sysroot(p) => ctx.set_sysroot(p).map(drop),
+            Setting::Libc(libc, _, _) => ctx.use_libc(*libc).map(drop),
+            Setting::DenyIncludes(_, names) => {
+                let names: Vec<&str> = names.iter().map(String::as_str).collect();
+                ctx.deny_includes(&names).map(drop)
+            }
+            Setting::Define(sym, val) => ctx.define_symbol(sym, val).map(drop),
+            Setting::Undefine(sym) => {
+                ctx.undefine_symbol(sym);
+                Ok(())
+            }
+            Setting::OutputType(output) => ctx.set_output_type(*output).map(drop),
+            Setting::LibraryPath(p) => ctx.add_library_path(p).map(drop),
+            Setting::Entry(sym) => {
+                ctx.set_entry_symbol(sym);
+                Ok(())
+            }
+            Setting::DefaultVisibility(vis) => {
+                ctx.set_default_visibility(*vis);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Configuration applied to a [`Context`] so far, see [`Context::config`].
+///
+/// Input files and sources are not part of it. Contexts with the same configuration
+/// can be created by [`ContextBuilder::from_context_config`](crate::ContextBuilder::from_context_config).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContextConfig {
+    pub(crate) settings: Vec<Setting>,
+}
+
+impl ContextConfig {
+    /// equivalent tcc command line, see [`Context::effective_options`].
+    pub fn effective_options(&self) -> String {
+        let args: Vec<String> = self
+            .settings
+            .iter()
+            .map(Setting::to_cmdline)
+            .filter(|a| !a.is_empty())
+            .collect();
+        args.join(" ")
+    }
+}
+
+/// quote `arg` with single quotes if it contains any character special to sh.
+fn quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_=+./,:@%^".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_cmdline() {
+        let cmdline = |s: Setting| s.to_cmdline();
+        let c = |s: &str| CString::new(s).unwrap();
+        assert_eq!(cmdline(Setting::IncludePath("a b".into())), "-I'a b'");
+        assert_eq!(
+            cmdline(Setting::Define(c("S"), c("\"it's\""))),
+            r#"-D'S="it'\''s"'"#
+        );
+        assert_eq!(cmdline(Setting::Define(c("N"), c("1"))), "-DN=1");
+        assert_eq!(cmdline(Setting::OutputType(OutputType::Obj)), "-c");
+        assert_eq!(cmdline(Setting::Options(c(" -g  "))), "-g");
+        assert_eq!(cmdline(Setting::Entry(c("start"))), "");
+    }
+}
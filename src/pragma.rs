@@ -0,0 +1,91 @@
+/// Custom pragma found in a source, see [`collected_pragmas`](crate::Context::collected_pragmas).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pragma {
+    /// name following `#pragma`, e.g. `export` of `#pragma export foo`.
+    pub name: String,
+    /// rest of the line, trimmed, e.g. `foo` of `#pragma export foo`.
+    pub args: String,
+    /// line number in the source, starting from 1.
+    pub line: usize,
+}
+
+/// Push pragmas of `src` named in `names` to `out`, and return `src` with lines of those
+/// to strip left empty, or None if nothing is stripped.
+pub(crate) fn scan(src: &[u8], names: &[(String, bool)], out: &mut Vec<Pragma>) -> Option<Vec<u8>> {
+    let mut stripped: Option<Vec<u8>> = None;
+    let mut start = 0;
+    for (i, line) in src.split(|&c| c == b'\n').enumerate() {
+        let end = start + line.len();
+        let text = String::from_utf8_lossy(line);
+        if let Some((name, args)) = parse(&text) {
+            if let Some((_, strip)) = names.iter().find(|(n, _)| n == name) {
+                out.push(Pragma {
+                    name: name.to_string(),
+                    args: args.to_string(),
+                    line: i + 1,
+                });
+                if *strip {
+                    // keep line breaks, so lines of diagnostics are unchanged.
+                    let buf = stripped.get_or_insert_with(|| src[..start].to_vec());
+                    buf.extend_from_slice(if line.ends_with(b"\r") { b"\r" } else { b"" });
+                    if end < src.len() {
+                        buf.push(b'\n');
+                    }
+                    start = end + 1;
+                    continue;
+                }
+            }
+        }
+        if let Some(ref mut buf) = stripped {
+            buf.extend_from_slice(&src[start..(end + 1).min(src.len())]);
+        }
+        start = end + 1;
+    }
+    stripped
+}
+
+/// Split `#pragma name args` into name and args.
+fn parse(line: &str) -> Option<(&str, &str)> {
+    let rest = line.trim_start().strip_prefix('#')?.trim_start();
+    let rest = rest.strip_prefix("pragma")?;
+    if !rest.starts_with(|c: char| c.is_ascii_whitespace()) {
+        return None;
+    }
+    let rest = rest.trim_start();
+    let len = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+    if len == 0 {
+        return None;
+    }
+    Some((&rest[..len], rest[len..].trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_strip() {
+        let names = [("export".to_string(), true), ("meta".to_string(), false)];
+        let src = b"#pragma export foo\r\nint x;\n  # pragma meta a=1 \n#pragma once\n#pragma exported\n#pragma export";
+        let mut out = Vec::new();
+        let stripped = scan(src, &names, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(stripped).unwrap(),
+            "\r\nint x;\n  # pragma meta a=1 \n#pragma once\n#pragma exported\n"
+        );
+        let found: Vec<(&str, &str, usize)> = out
+            .iter()
+            .map(|p| (&p.name[..], &p.args[..], p.line))
+            .collect();
+        assert_eq!(
+            found,
+            [("export", "foo", 1), ("meta", "a=1", 3), ("export", "", 6)]
+        );
+
+        out.clear();
+        assert_eq!(scan(b"#pragma meta\n", &names, &mut out), None);
+        assert_eq!(out.len(), 1);
+    }
+}
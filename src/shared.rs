@@ -0,0 +1,74 @@
+use std::any::Any;
+use std::cell::UnsafeCell;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::os::raw::c_void;
+use std::rc::Rc;
+
+/// Global symbols registered once and shared by several modules, e.g. host state of a
+/// plugin system, see [`Context::add_shared_symbols`](crate::Context::add_shared_symbols).
+///
+/// Every context the symbols are added to resolves them to the same addresses.
+/// Storage allocated by [`add_global`](SharedSymbols::add_global) is owned by the
+/// container and its clones, including those kept by contexts and their relocated
+/// modules, so it outlives every module using it.
+#[derive(Clone, Default)]
+pub struct SharedSymbols {
+    pub(crate) entries: Vec<(CString, *const c_void)>,
+    storage: Vec<Rc<dyn Any>>,
+}
+
+impl fmt::Debug for SharedSymbols {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedSymbols")
+            .field("entries", &self.entries)
+            .finish()
+    }
+}
+
+impl SharedSymbols {
+    /// no symbols yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// share `name` at `val`, replacing the symbol of the same name if any.
+    ///
+    /// # Safety
+    /// `val` need satisfy ABI requirement, same as [`add_symbol`](crate::Context::add_symbol),
+    /// and stay valid as long as modules using it.
+    pub unsafe fn add_symbol(&mut self, name: &CStr, val: *const c_void) -> &mut Self {
+        match self.entries.iter_mut().find(|(n, _)| n.as_c_str() == name) {
+            Some(entry) => entry.1 = val,
+            None => self.entries.push((name.to_owned(), val)),
+        }
+        self
+    }
+
+    /// allocate a global variable `name` initialized to `init`, and return its address,
+    /// through which the host reads and writes it.
+    ///
+    /// Compiled code declares it `extern`, of the C type matching `T`. The address is
+    /// valid as long as this container or any of its clones lives.
+    pub fn add_global<T: Copy + 'static>(&mut self, name: &CStr, init: T) -> *mut T {
+        let cell = Rc::new(UnsafeCell::new(init));
+        let ptr = cell.get();
+        self.storage.push(cell);
+        // the storage is kept with the entry.
+        unsafe { self.add_symbol(name, ptr as *const c_void) };
+        ptr
+    }
+
+    /// address of `name`, None if it is not shared.
+    pub fn get(&self, name: &CStr) -> Option<*const c_void> {
+        self.entries
+            .iter()
+            .find(|(n, _)| n.as_c_str() == name)
+            .map(|(_, val)| *val)
+    }
+
+    /// names of the shared symbols.
+    pub fn names(&self) -> impl Iterator<Item = &CStr> {
+        self.entries.iter().map(|(n, _)| n.as_c_str())
+    }
+}
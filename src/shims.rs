@@ -0,0 +1,119 @@
+use crate::binding::*;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::slice;
+use std::sync::Mutex;
+
+extern "C" {
+    fn malloc(size: usize) -> *mut c_void;
+    fn calloc(n: usize, size: usize) -> *mut c_void;
+    fn realloc(p: *mut c_void, size: usize) -> *mut c_void;
+    fn free(p: *mut c_void);
+    fn memcpy(dst: *mut c_void, src: *const c_void, n: usize) -> *mut c_void;
+    fn memmove(dst: *mut c_void, src: *const c_void, n: usize) -> *mut c_void;
+    fn memset(dst: *mut c_void, c: c_int, n: usize) -> *mut c_void;
+    fn memcmp(a: *const c_void, b: *const c_void, n: usize) -> c_int;
+    fn strlen(s: *const c_char) -> usize;
+    fn strcmp(a: *const c_char, b: *const c_char) -> c_int;
+}
+
+/// Output of the printf family of shims, see [`LibcShims::take_output`].
+static OUTPUT: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+extern "C" fn write_output(_opaque: *mut c_void, buf: *const c_char, len: usize) {
+    let buf = unsafe { slice::from_raw_parts(buf as *const u8, len) };
+    // compiled code can not handle a panic, a poisoned buffer is still usable.
+    let mut out = OUTPUT.lock().unwrap_or_else(|e| e.into_inner());
+    out.extend_from_slice(buf);
+}
+
+/// Host implementations of common libc functions, for code compiled without libc,
+/// see [`Context::install_libc_shims`](crate::Context::install_libc_shims).
+///
+/// The default set has:
+/// - `malloc`, `calloc`, `realloc`, `free` of the host.
+/// - `memcpy`, `memmove`, `memset`, `memcmp`, `strlen`, `strcmp` of the host.
+/// - `printf`, `puts`, `putchar` writing to a buffer, see [`take_output`](LibcShims::take_output).
+/// - `__errno_location`, through which `errno` of glibc and musl headers is accessed,
+///   returning errno of the host thread.
+///
+/// Signatures are those of C, compiled code must declare them alike, e.g. by
+/// including the libc headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LibcShims {
+    pub(crate) entries: Vec<(CString, *const c_void)>,
+}
+
+impl Default for LibcShims {
+    fn default() -> Self {
+        let table: [(&[u8], *const c_void); 14] = [
+            (b"malloc\0", malloc as *const c_void),
+            (b"calloc\0", calloc as *const c_void),
+            (b"realloc\0", realloc as *const c_void),
+            (b"free\0", free as *const c_void),
+            (b"memcpy\0", memcpy as *const c_void),
+            (b"memmove\0", memmove as *const c_void),
+            (b"memset\0", memset as *const c_void),
+            (b"memcmp\0", memcmp as *const c_void),
+            (b"strlen\0", strlen as *const c_void),
+            (b"strcmp\0", strcmp as *const c_void),
+            (b"printf\0", tcc_ext_shim_printf as *const c_void),
+            (b"puts\0", tcc_ext_shim_puts as *const c_void),
+            (b"putchar\0", tcc_ext_shim_putchar as *const c_void),
+            (
+                b"__errno_location\0",
+                tcc_ext_shim_errno_location as *const c_void,
+            ),
+        ];
+        let entries = table
+            .iter()
+            .map(|(name, val)| (CStr::from_bytes_with_nul(name).unwrap().to_owned(), *val))
+            .collect();
+        LibcShims { entries }
+    }
+}
+
+impl LibcShims {
+    /// no shims, to add only those needed by [`set`](LibcShims::set).
+    pub fn empty() -> Self {
+        LibcShims {
+            entries: Vec::new(),
+        }
+    }
+
+    /// provide `name` by `val`, replacing the shim of the same name if any.
+    ///
+    /// # Safety
+    /// `val` need satisfy ABI requirement, same as [`add_symbol`](crate::Context::add_symbol).
+    pub unsafe fn set(&mut self, name: &CStr, val: *const c_void) -> &mut Self {
+        match self.entries.iter_mut().find(|(n, _)| n.as_c_str() == name) {
+            Some(entry) => entry.1 = val,
+            None => self.entries.push((name.to_owned(), val)),
+        }
+        self
+    }
+
+    /// do not provide `name`, e.g. to let it resolve from libc.
+    pub fn remove(&mut self, name: &CStr) -> &mut Self {
+        self.entries.retain(|(n, _)| n.as_c_str() != name);
+        self
+    }
+
+    /// names of the shims.
+    pub fn names(&self) -> impl Iterator<Item = &CStr> {
+        self.entries.iter().map(|(n, _)| n.as_c_str())
+    }
+
+    /// take what the printf family of shims wrote so far.
+    ///
+    /// The buffer is shared by all modules, like tcc's own state.
+    pub fn take_output() -> Vec<u8> {
+        let mut out = OUTPUT.lock().unwrap_or_else(|e| e.into_inner());
+        std::mem::take(&mut *out)
+    }
+
+    /// direct output of the shims to the buffer.
+    pub(crate) fn init_output() {
+        unsafe { tcc_ext_shim_set_output(Some(write_output)) };
+    }
+}